@@ -0,0 +1,286 @@
+use std::borrow::Cow;
+use std::io::{self, Error as IoError, ErrorKind, Read};
+
+/// BAM file magic, after decompression
+const MAGIC: &[u8; 4] = b"BAM\x01";
+
+/// Bases of the 4-bit encoded sequence
+const BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+
+/// Flags of the records not counted: secondary and supplementary alignments
+const FLAG_SKIP: u16 = 0x100 | 0x800;
+
+/// Flag of a reverse strand alignment, stored reverse complemented
+const FLAG_REVERSE: u16 = 0x10;
+
+/// Flag of a read with a mate, as in an unaligned BAM of paired reads
+const FLAG_PAIRED: u16 = 0x1;
+
+/// Flag of the first read of a pair
+const FLAG_FIRST: u16 = 0x40;
+
+/// Start of the read name in a record, following the fixed length fields
+const NAME_OFFSET: usize = 32;
+
+/// BAM records converted to interleaved FastQ pairs.
+///
+/// Paired records, as in an unaligned BAM (uBAM) of the raw reads, are paired with the next
+/// record, their mate, and written as read 1 and read 2 by their first and second read flags.
+///
+/// Unpaired records are 10x records: the read 1 record holds the corrected cell barcode of
+/// the `CB` tag followed by the UMI of the `UB` tag, or the `UR` tag when not corrected. The
+/// read 2 record holds the record sequence. Records without a `CB` tag are skipped.
+///
+/// Sequences are written in the sequenced orientation. Secondary and supplementary
+/// alignments are skipped.
+pub struct BamFastq {
+    reader: Box<dyn Read>,
+    /// The current BAM record, without the block size
+    record: Vec<u8>,
+    /// FastQ text of the converted records
+    fastq: Vec<u8>,
+    /// Start of the FastQ text not yet read
+    pos: usize,
+    /// FastQ text of a paired record waiting for its mate
+    mate: Vec<u8>,
+    /// Whether the waiting mate is the first read, None without a waiting mate
+    mate_first: Option<bool>,
+}
+
+/// Fields of a BAM record used for the conversion
+struct Fields<'a> {
+    name: &'a [u8],
+    flag: u16,
+    seq: Vec<u8>,
+    qual: Vec<u8>,
+    /// The variable length tags
+    tags: &'a [u8],
+}
+
+impl BamFastq {
+    /// Read the BAM header from the decompressed stream
+    pub fn new(mut reader: Box<dyn Read>) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(IoError::new(ErrorKind::InvalidData, "Not a BAM file"));
+        }
+        let l_text = read_i32(&mut reader)?;
+        skip(&mut reader, l_text)?;
+        for _ in 0..read_i32(&mut reader)? {
+            let l_name = read_i32(&mut reader)?;
+            skip(&mut reader, l_name + 4)?;
+        }
+
+        Ok(BamFastq {
+            reader,
+            record: Vec::new(),
+            fastq: Vec::new(),
+            pos: 0,
+            mate: Vec::new(),
+            mate_first: None,
+        })
+    }
+
+    /// Read the next record into the record buffer, false at the end of the file
+    fn next_record(&mut self) -> io::Result<bool> {
+        let mut size = [0; 4];
+        let mut n = 0;
+        while n < size.len() {
+            match self.reader.read(&mut size[n..]) {
+                Ok(0) if n == 0 => return Ok(false),
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(read) => n += read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let size = i32::from_le_bytes(size);
+        if size < NAME_OFFSET as i32 {
+            return Err(invalid("record shorter than its fixed fields"));
+        }
+        self.record.resize(size as usize, 0);
+        self.reader.read_exact(&mut self.record)?;
+        Ok(true)
+    }
+
+    /// Append the FastQ pair of the current record, when it has a cell barcode, or of the
+    /// current record and its mate
+    fn convert(&mut self) -> io::Result<()> {
+        let record = fields(&self.record)?;
+        if record.flag & FLAG_SKIP != 0 {
+            return Ok(());
+        }
+
+        if record.flag & FLAG_PAIRED != 0 {
+            let first = record.flag & FLAG_FIRST != 0;
+            match self.mate_first.take() {
+                None => {
+                    self.mate.clear();
+                    write_fastq(&mut self.mate, record.name, &record.seq, &record.qual);
+                    self.mate_first = Some(first);
+                }
+                Some(true) => {
+                    self.fastq.extend_from_slice(&self.mate);
+                    write_fastq(&mut self.fastq, record.name, &record.seq, &record.qual);
+                }
+                Some(false) => {
+                    write_fastq(&mut self.fastq, record.name, &record.seq, &record.qual);
+                    self.fastq.extend_from_slice(&self.mate);
+                }
+            }
+            return Ok(());
+        }
+
+        let Some(cb) = tag(record.tags, b"CB")? else {
+            return Ok(());
+        };
+        // the GEM well suffix, kept in the CB:Z: tag of the read names for --gem-wells
+        let bases = cb.split(|&b| b == b'-').next().unwrap_or(cb);
+        let name = if bases.len() < cb.len() {
+            Cow::Owned([record.name, b" CB:Z:", cb].concat())
+        } else {
+            Cow::Borrowed(record.name)
+        };
+        let umi = match tag(record.tags, b"UB")? {
+            Some(umi) => umi,
+            None => tag(record.tags, b"UR")?.unwrap_or_default(),
+        };
+
+        let codes = [bases, umi].concat();
+        // the corrected codes have no base qualities
+        let qual = vec![b'I'; codes.len()];
+        write_fastq(&mut self.fastq, &name, &codes, &qual);
+        write_fastq(&mut self.fastq, &name, &record.seq, &record.qual);
+        Ok(())
+    }
+}
+
+impl Read for BamFastq {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.fastq.len() {
+            self.fastq.clear();
+            self.pos = 0;
+            while self.fastq.len() < buf.len() && self.next_record()? {
+                self.convert()?;
+            }
+        }
+        let n = buf.len().min(self.fastq.len() - self.pos);
+        buf[..n].copy_from_slice(&self.fastq[self.pos..][..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn write_fastq(out: &mut Vec<u8>, name: &[u8], seq: &[u8], qual: &[u8]) {
+    out.push(b'@');
+    out.extend_from_slice(name);
+    out.push(b'\n');
+    out.extend_from_slice(seq);
+    out.extend_from_slice(b"\n+\n");
+    out.extend_from_slice(qual);
+    out.push(b'\n');
+}
+
+fn invalid(reason: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, format!("Malformed BAM record: {reason}"))
+}
+
+fn read_i32(reader: &mut dyn Read) -> io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn skip(reader: &mut dyn Read, n: i32) -> io::Result<()> {
+    let n = u64::try_from(n).map_err(|_| invalid("negative length in the header"))?;
+    let skipped = io::copy(&mut reader.take(n), &mut io::sink())?;
+    if skipped < n {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Decode the fields of a record. The sequence and qualities of a reverse strand alignment are
+/// turned back into the sequenced orientation, and the qualities encoded as Phred+33.
+fn fields(record: &[u8]) -> io::Result<Fields<'_>> {
+    let u16_at = |i: usize| u16::from_le_bytes([record[i], record[i + 1]]);
+    let l_read_name = record[8] as usize;
+    let n_cigar_op = u16_at(12) as usize;
+    let flag = u16_at(14);
+    let l_seq = i32::from_le_bytes(record[16..20].try_into().unwrap());
+    let l_seq = usize::try_from(l_seq).map_err(|_| invalid("negative sequence length"))?;
+
+    let seq_start = NAME_OFFSET + l_read_name + 4 * n_cigar_op;
+    let qual_start = seq_start + l_seq.div_ceil(2);
+    let tags_start = qual_start + l_seq;
+    if tags_start > record.len() || l_read_name == 0 {
+        return Err(invalid("fields exceed the record length"));
+    }
+
+    let name = &record[NAME_OFFSET..NAME_OFFSET + l_read_name - 1];
+    let mut seq: Vec<u8> = (0..l_seq)
+        .map(|i| {
+            let byte = record[seq_start + i / 2];
+            BASES[if i % 2 == 0 { byte >> 4 } else { byte & 0xf } as usize]
+        })
+        .collect();
+    let mut qual: Vec<u8> = record[qual_start..tags_start]
+        .iter()
+        // 0xff when the qualities are missing
+        .map(|&q| if q == 0xff { b'!' } else { q.saturating_add(b'!') })
+        .collect();
+    if flag & FLAG_REVERSE != 0 {
+        seq.reverse();
+        for base in &mut seq {
+            *base = match *base {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                b'T' => b'A',
+                other => other,
+            };
+        }
+        qual.reverse();
+    }
+
+    Ok(Fields {
+        name,
+        flag,
+        seq,
+        qual,
+        tags: &record[tags_start..],
+    })
+}
+
+/// The value of a string tag, None when the record lacks the tag
+fn tag<'a>(mut tags: &'a [u8], name: &[u8; 2]) -> io::Result<Option<&'a [u8]>> {
+    while tags.len() >= 3 {
+        let (key, kind, rest) = (&tags[..2], tags[2], &tags[3..]);
+        let size = match kind {
+            b'A' | b'c' | b'C' => 1,
+            b's' | b'S' => 2,
+            b'i' | b'I' | b'f' => 4,
+            b'Z' | b'H' => {
+                let end = rest.iter().position(|&b| b == 0).ok_or_else(|| invalid("unterminated string tag"))?;
+                if key == name {
+                    return Ok(Some(&rest[..end]));
+                }
+                end + 1
+            }
+            b'B' => {
+                let element = match rest.first() {
+                    Some(b'c' | b'C') => 1,
+                    Some(b's' | b'S') => 2,
+                    Some(b'i' | b'I' | b'f') => 4,
+                    _ => return Err(invalid("unknown array tag type")),
+                };
+                let count = rest.get(1..5).ok_or_else(|| invalid("truncated array tag"))?;
+                5 + element * u32::from_le_bytes(count.try_into().unwrap()) as usize
+            }
+            _ => return Err(invalid("unknown tag type")),
+        };
+        tags = rest.get(size..).ok_or_else(|| invalid("truncated tag"))?;
+    }
+    Ok(None)
+}
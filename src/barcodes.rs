@@ -1,24 +1,88 @@
-use std::fs::File;
 use std::io::{Error as IoError, ErrorKind, Write};
 use std::path::Path;
 
-use ahash::AHashMap;
 use anyhow::Result;
-use bktree::BkTree;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use triple_accel::levenshtein::levenshtein_exp;
 
-use crate::{Barcode, BCLENGTH};
+use crate::hash::{HashMap, HashSet};
+use crate::remote;
+use crate::{barcode_bases, to_barcode, Barcode, MAX_BARCODES};
+
+/// Feature types accepted by Cell Ranger
+pub const CELLRANGER_FEATURE_TYPES: [&str; 4] = [
+    "Antibody Capture",
+    "CRISPR Guide Capture",
+    "Multiplexing Capture",
+    "Custom",
+];
 
 fn dist(a: &Barcode, b: &Barcode) -> isize {
-    levenshtein_exp(a, b) as isize
+    levenshtein_exp(barcode_bases(a), barcode_bases(b)) as isize
 }
 
 pub struct Barcodes {
     pub records: Vec<csv::StringRecord>,
+    /// The sequences defined by more than one reference row and how they were resolved
+    pub duplicates: Vec<Duplicate>,
     header: csv::StringRecord,
-    barcodes: AHashMap<Barcode, usize>,
-    bktree: BkTree<Barcode>,
+    /// The file and row of each record, for the error messages
+    locations: Vec<String>,
+    /// Length of the barcodes
+    length: usize,
+    barcodes: HashMap<Barcode, usize>,
+    bktree: BkTree,
+}
+
+/// A BK-tree node: a reference barcode, its position and its children by distance
+struct Node {
+    barcode: Barcode,
+    pos: usize,
+    children: Vec<(isize, usize)>,
+}
+
+/// BK-tree of the reference barcodes with the nodes stored in a single list
+#[derive(Default)]
+struct BkTree(Vec<Node>);
+
+/// Reusable buffers for the approximate search, so the per read lookups do not allocate.
+/// Kept by the caller across the reads of a counting loop.
+#[derive(Default)]
+pub struct Scratch {
+    stack: Vec<usize>,
+    hits: Vec<(usize, isize)>,
+}
+/// A duplicate id or name renamed in the output
+pub struct Rename {
+    pub column: String,
+    pub from: String,
+    pub to: String,
 }
+
+/// How a sequence defined by more than one reference row is resolved
+#[derive(Clone, Copy, Debug, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precedence {
+    /// The first row, in the order of the reference files, is used
+    #[default]
+    First,
+    /// Fail on a duplicate sequence
+    Error,
+    /// The first row is used with the ids and names of all rows joined by `|`
+    Merge,
+}
+
+/// A sequence defined by more than one reference row
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Duplicate {
+    pub sequence: String,
+    /// The ids of the rows, in reference order
+    pub ids: Vec<String>,
+    /// The id the reads of the sequence are counted under
+    pub kept: String,
+}
+
 pub enum MatchResult {
     NoHit,
     Multiple,
@@ -27,66 +91,259 @@ pub enum MatchResult {
 }
 
 impl Barcodes {
-    pub fn from_csv<P: AsRef<Path>>(p: P) -> Result<Self> {
-        let f = File::open(p)?;
-        let mut reader = csv::ReaderBuilder::new()
-            .delimiter(b',')
-            .has_headers(true)
-            .from_reader(f);
-
-        //TODO check headers for Cellranger compatibility
-        let header = reader.headers()?.to_owned();
-        if header.as_slice() != "idnamereadpatternsequencefeature_type" {
-            return Err(IoError::new(
-                ErrorKind::InvalidData,
-                "Header error: Expected header: id,name,read,pattern,sequence,feature_type",
-            )
-            .into());
-        }
+    /// Read the references from one or more csv files with barcodes of this length. A sequence
+    /// on more than one row is resolved by the precedence, in the order of the files and rows.
+    pub fn from_csv<P: AsRef<Path>>(paths: &[P], precedence: Precedence, length: usize) -> Result<Self> {
+        let mut header = csv::StringRecord::new();
+        let mut records: Vec<csv::StringRecord> = Vec::new();
+        let mut locations = Vec::new();
+        let mut duplicates: Vec<Duplicate> = Vec::new();
+        let mut barcodes: HashMap<Barcode, usize> = HashMap::default();
+        for p in paths {
+            let p = p.as_ref();
+            let f = remote::open(p)?;
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(b',')
+                .has_headers(true)
+                .trim(csv::Trim::All)
+                .from_reader(f);
 
-        let mut records = Vec::new();
-        let mut barcodes = AHashMap::new();
-        for (pos, result) in reader.records().enumerate() {
-            let record = result?;
-
-            let barcode = record
-                .get(4)
-                .ok_or_else(|| IoError::new(
+            header = reader.headers()?.to_owned();
+            if header.as_slice() != "idnamereadpatternsequencefeature_type" {
+                return Err(IoError::new(
                     ErrorKind::InvalidData,
-                    "Expected barcode in column 5",
-                ))?
-                .as_bytes()
-                .try_into()
-                .map_err(|_e| {
+                    format!(
+                        "Header error in {}: Expected header: id,name,read,pattern,sequence,feature_type",
+                        p.display()
+                    ),
+                )
+                .into());
+            }
+
+            for (row, result) in reader.records().enumerate() {
+                let record = result?;
+                let location = if paths.len() == 1 {
+                    format!("row {}", row + 2)
+                } else {
+                    format!("{} row {}", p.display(), row + 2)
+                };
+
+                let sequence = record
+                    .get(4)
+                    .ok_or_else(|| IoError::new(
+                        ErrorKind::InvalidData,
+                        "Expected barcode in column 5",
+                    ))?
+                    .as_bytes();
+                let barcode = to_barcode(sequence).filter(|_| sequence.len() == length).ok_or_else(|| {
                     IoError::new(
                         ErrorKind::InvalidData,
-                        format!("Barcode length not equal to {}", BCLENGTH),
+                        format!("{location}: barcode length not equal to {length}"),
                     )
                 })?;
 
-            records.push(record);
-            barcodes.insert(barcode, pos);
+                let Some(&first) = barcodes.get(&barcode) else {
+                    barcodes.insert(barcode, records.len());
+                    records.push(record);
+                    locations.push(location);
+                    continue;
+                };
+
+                let id = record.get(0).unwrap_or_default().to_owned();
+                let sequence = record.get(4).unwrap_or_default();
+                if let Precedence::Error = precedence {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidData,
+                        format!("{location}: sequence {sequence} already used on {}", locations[first]),
+                    )
+                    .into());
+                }
+                let i = match duplicates.iter().position(|d| d.sequence == sequence) {
+                    Some(i) => i,
+                    None => {
+                        duplicates.push(Duplicate {
+                            sequence: sequence.to_owned(),
+                            ids: vec![records[first].get(0).unwrap_or_default().to_owned()],
+                            kept: String::new(),
+                        });
+                        duplicates.len() - 1
+                    }
+                };
+                duplicates[i].ids.push(id);
+                if let Precedence::Merge = precedence {
+                    let kept = &records[first];
+                    let merged: Vec<String> = kept
+                        .iter()
+                        .enumerate()
+                        .map(|(column, field)| match column {
+                            0 | 1 => format!("{field}|{}", record.get(column).unwrap_or_default()),
+                            _ => field.to_owned(),
+                        })
+                        .collect();
+                    records[first] = csv::StringRecord::from(merged);
+                }
+                duplicates[i].kept = records[first].get(0).unwrap_or_default().to_owned();
+            }
+        }
+
+        if records.len() > MAX_BARCODES {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("More than {} barcodes in the reference", MAX_BARCODES),
+            )
+            .into());
         }
 
-        let mut bktree = BkTree::new(dist);
-        bktree.insert_all(barcodes.keys().cloned());
+        let mut bktree = BkTree::default();
+        for (barcode, &pos) in &barcodes {
+            bktree.insert(*barcode, pos);
+        }
 
         Ok(Barcodes {
             records,
+            duplicates,
             header,
+            locations,
+            length,
             barcodes,
             bktree,
         })
     }
 
-    pub fn find(&self, s: &Barcode, approximate: bool) -> MatchResult {
+    /// Check that the reference will be accepted by `cellranger count`: unique ids, a valid
+    /// read and pattern and an allowed feature_type. The sequences are unique after the
+    /// duplicates are resolved.
+    pub fn check_cellranger(&self) -> Result<()> {
+        let errors: Vec<_> =
+            self.issues().into_iter().filter(|&(cellranger, _)| cellranger).map(|(_, m)| m).collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("Reference not accepted by Cell Ranger:\n{}", errors.join("\n")),
+            )
+            .into())
+        }
+    }
+
+    /// The problems of the reference rows for Cell Ranger and for downstream tools, one message
+    /// per problem starting with its row. Besides the Cell Ranger checks the ids should only
+    /// contain letters, digits, _, - and ., the names no commas or parentheses, which break
+    /// the R code reading the features, and the read should be R2.
+    pub fn lint(&self) -> Vec<String> {
+        self.issues().into_iter().map(|(_, message)| message).collect()
+    }
+
+    /// The problems of the reference rows, flagged when Cell Ranger rejects the reference
+    fn issues(&self) -> Vec<(bool, String)> {
+        let mut issues = Vec::new();
+        let mut ids = HashMap::default();
+
+        for (record, row) in self.records.iter().zip(&self.locations) {
+            let field = |i| record.get(i).unwrap_or_default();
+
+            if let Some(first) = ids.insert(field(0), row) {
+                issues.push((true, format!("{row}: id {} already used on {first}, give each row its own id", field(0))));
+            }
+            if !field(0).chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c)) {
+                issues.push((
+                    false,
+                    format!("{row}: id {} should only contain letters, digits, _, - and ., replace the others", field(0)),
+                ));
+            }
+            if field(1).contains([',', '(', ')']) {
+                issues.push((
+                    false,
+                    format!(
+                        "{row}: name {} contains commas or parentheses, which break R code reading the features, \
+                        replace them with _ or -",
+                        field(1)
+                    ),
+                ));
+            }
+            match field(2) {
+                "R2" => {}
+                "R1" => issues.push((false, format!("{row}: read R1, the feature barcodes of 10x libraries are in R2"))),
+                read => issues.push((true, format!("{row}: read should be R1 or R2, found {read}"))),
+            }
+            if !field(3).contains("(BC)") {
+                issues.push((true, format!("{row}: pattern {} lacks (BC), mark the barcode as in 5PNNNNNNNNNN(BC)", field(3))));
+            }
+            if !CELLRANGER_FEATURE_TYPES.contains(&field(5)) {
+                issues.push((
+                    true,
+                    format!("{row}: feature_type {} is not one of: {}", field(5), CELLRANGER_FEATURE_TYPES.join(", ")),
+                ));
+            }
+        }
+        issues
+    }
+
+    /// Keep only the rows with one of the ids or names, or with exclude remove them, and
+    /// return the removed rows. The ids and names merged with `|` match each part. Fails on a
+    /// name that is not in the reference.
+    pub fn select(&mut self, names: &[String], exclude: bool) -> Result<Vec<csv::StringRecord>> {
+        let matches = |record: &csv::StringRecord, name: &str| {
+            [0, 1].iter().any(|&i| record.get(i).unwrap_or_default().split('|').any(|part| part == name))
+        };
+        let missing: Vec<_> = names
+            .iter()
+            .filter(|&name| !self.records.iter().any(|r| matches(r, name)))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!("Features not in the reference: {}", missing.join(", ")),
+            )
+            .into());
+        }
+
+        let (mut kept, mut locations, mut removed) = (Vec::new(), Vec::new(), Vec::new());
+        for (record, location) in self.records.drain(..).zip(self.locations.drain(..)) {
+            if names.iter().any(|name| matches(&record, name)) != exclude {
+                kept.push(record);
+                locations.push(location);
+            } else {
+                removed.push(record);
+            }
+        }
+        self.records = kept;
+        self.locations = locations;
+
+        self.barcodes = self
+            .records
+            .iter()
+            .enumerate()
+            .map(|(pos, r)| (to_barcode(r.get(4).unwrap().as_bytes()).unwrap(), pos))
+            .collect();
+        self.bktree = BkTree::default();
+        for (barcode, &pos) in &self.barcodes {
+            self.bktree.insert(*barcode, pos);
+        }
+        Ok(removed)
+    }
+
+    /// Length of the barcodes
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// The position of the reference barcode equal to the sequence
+    pub fn exact(&self, s: &[u8]) -> Option<usize> {
+        self.barcodes.get(&to_barcode(s)?).copied()
+    }
+
+    pub fn find(&self, s: &Barcode, approximate: bool, scratch: &mut Scratch) -> MatchResult {
         if let Some(&i) = self.barcodes.get(s.as_slice()) {
             MatchResult::Unique(i)
         } else if approximate {
-            let hits = self.bktree.find(s.to_owned(), 2);
+            let hits = self.bktree.find(s, 2, scratch);
             match hits.len() {
                 0 => MatchResult::NoHit,
-                1 => MatchResult::Dist(*self.barcodes.get(hits[0].0).unwrap(), hits[0].1),
+                1 => MatchResult::Dist(hits[0].0, hits[0].1),
                 _ => MatchResult::Multiple,
             }
         } else {
@@ -94,16 +351,168 @@ impl Barcodes {
         }
     }
 
-    pub fn write_csv<W: Write, I: IntoIterator<Item = usize>>(&self, w: W, list: I) -> Result<()> {
+    /// Find the barcode by scanning the BK-tree, also when an exact match exists. Returns the
+    /// match and, for exact matches, the number of other reference barcodes within distance 2.
+    pub fn scan(&self, s: &Barcode, approximate: bool, scratch: &mut Scratch) -> (MatchResult, usize) {
+        let hits = self.bktree.find(s, 2, scratch);
+        if let Some(&(pos, _)) = hits.iter().find(|&&(_, d)| d == 0) {
+            (MatchResult::Unique(pos), hits.len() - 1)
+        } else if !approximate {
+            (MatchResult::NoHit, 0)
+        } else {
+            let result = match hits.len() {
+                0 => MatchResult::NoHit,
+                1 => MatchResult::Dist(hits[0].0, hits[0].1),
+                _ => MatchResult::Multiple,
+            };
+            (result, 0)
+        }
+    }
+
+    /// Search the windows of a read other than the one at skip for a reference barcode,
+    /// exactly or else within distance 1. The position of the barcode and the start of its
+    /// window, None when no window or more than one barcode is found.
+    pub fn scan_read(&self, read: &[u8], skip: usize, scratch: &mut Scratch) -> Option<(usize, usize)> {
+        let starts = (0..(read.len() + 1).saturating_sub(self.length)).filter(|&start| start != skip);
+        for max_dist in [0, 1] {
+            let mut found = None;
+            for start in starts.clone() {
+                let Some(window) = to_barcode(&read[start..][..self.length]) else {
+                    continue;
+                };
+                let exact = self.barcodes.get(&window).map(|&pos| (pos, 0));
+                let hits = match max_dist {
+                    0 => exact.as_slice(),
+                    _ => self.bktree.find(&window, max_dist, scratch),
+                };
+                for &(pos, _) in hits {
+                    match found {
+                        Some((first, _)) if first != pos => return None,
+                        Some(_) => {}
+                        None => found = Some((pos, start)),
+                    }
+                }
+            }
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// The (position, distance) of the reference barcodes within distance 2
+    pub fn hits<'a>(&self, s: &Barcode, scratch: &'a mut Scratch) -> &'a [(usize, isize)] {
+        self.bktree.find(s, 2, scratch)
+    }
+
+    /// The pairs of reference barcodes within max_dist of each other, with their distance
+    pub fn near_pairs(&self, max_dist: isize) -> Vec<(usize, usize, isize)> {
+        let mut scratch = Scratch::default();
+        let mut pairs = Vec::new();
+        for (barcode, &pos) in &self.barcodes {
+            for &(other, d) in self.bktree.find(barcode, max_dist, &mut scratch) {
+                if pos < other {
+                    pairs.push((pos, other, d));
+                }
+            }
+        }
+        pairs.sort_unstable();
+        pairs
+    }
+
+    /// The distance between two reference barcodes
+    pub fn distance(&self, a: usize, b: usize) -> isize {
+        let barcode = |pos: usize| self.records[pos].get(4).unwrap_or_default().as_bytes();
+        levenshtein_exp(barcode(a), barcode(b)) as isize
+    }
+
+    /// Write the records sorted by id and reference order. Duplicate ids and names get a
+    /// numbered suffix, the renames are returned.
+    pub fn write_csv<W: Write, I: IntoIterator<Item = usize>>(
+        &self,
+        w: W,
+        list: I,
+    ) -> Result<Vec<Rename>> {
         let mut writer = csv::Writer::from_writer(w);
 
         let mut positions: Vec<_> = list.into_iter().collect();
-        positions.sort_by_key(|&e| self.records[e].get(0).unwrap());
+        positions.sort_by_key(|&e| (self.records[e].get(0).unwrap(), e));
+
+        let mut used = [HashSet::default(), HashSet::default()];
+        let mut renames = Vec::new();
 
         writer.write_record(&self.header)?;
-        positions
-            .into_iter()
-            .try_for_each(|pos| writer.write_record(&self.records[pos]))?;
-        Ok(())
+        for pos in positions {
+            let mut record: Vec<_> = self.records[pos].iter().map(str::to_owned).collect();
+            for (column, used) in used.iter_mut().enumerate() {
+                let original = &record[column];
+                let mut unique = original.clone();
+                let mut n = 1;
+                while used.contains(&unique) {
+                    n += 1;
+                    unique = format!("{original}_{n}");
+                }
+                used.insert(unique.clone());
+
+                if unique != *original {
+                    renames.push(Rename {
+                        column: self.header.get(column).unwrap().to_owned(),
+                        from: original.clone(),
+                        to: unique.clone(),
+                    });
+                    record[column] = unique;
+                }
+            }
+            writer.write_record(&record)?;
+        }
+        Ok(renames)
+    }
+}
+
+impl BkTree {
+    fn insert(&mut self, barcode: Barcode, pos: usize) {
+        let new = self.0.len();
+        if new > 0 {
+            let mut node = 0;
+            loop {
+                let d = dist(&self.0[node].barcode, &barcode);
+                if d == 0 {
+                    return;
+                }
+                match self.0[node].children.iter().find(|&&(k, _)| k == d) {
+                    Some(&(_, child)) => node = child,
+                    None => {
+                        self.0[node].children.push((d, new));
+                        break;
+                    }
+                }
+            }
+        }
+        self.0.push(Node { barcode, pos, children: Vec::new() });
+    }
+
+    /// The (position, distance) of the reference barcodes within max_dist, in the scratch
+    /// buffers
+    fn find<'a>(&self, barcode: &Barcode, max_dist: isize, scratch: &'a mut Scratch) -> &'a [(usize, isize)] {
+        scratch.hits.clear();
+        scratch.stack.clear();
+        if !self.0.is_empty() {
+            scratch.stack.push(0);
+        }
+
+        while let Some(node) = scratch.stack.pop() {
+            let node = &self.0[node];
+            let d = dist(&node.barcode, barcode);
+            if d <= max_dist {
+                scratch.hits.push((node.pos, d));
+            }
+            scratch.stack.extend(
+                node.children
+                    .iter()
+                    .filter(|&&(k, _)| (k - d).abs() <= max_dist)
+                    .map(|&(_, child)| child),
+            );
+        }
+        &scratch.hits
     }
 }
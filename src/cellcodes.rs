@@ -0,0 +1,43 @@
+use crate::hash::HashMap;
+use crate::{cell_bases, CellCode};
+
+/// A cell code is a likely sequencing error of a neighbor that is at least this many times
+/// more frequent
+pub const ERROR_RATIO: usize = 10;
+
+/// Map the likely error cell codes to their parent: the most frequent cell code within one
+/// mismatch that has at least [`ERROR_RATIO`] times the reads.
+pub fn error_parents(totals: &HashMap<CellCode, usize>) -> HashMap<CellCode, CellCode> {
+    let mut parents = HashMap::default();
+    for (cellcode, &reads) in totals {
+        let mut best: Option<(usize, CellCode)> = None;
+        let mut neighbor = *cellcode;
+        for i in 0..cell_bases(cellcode).len() {
+            for base in *b"ACGTN" {
+                if base == cellcode[i] {
+                    continue;
+                }
+                neighbor[i] = base;
+                if let Some(&n) = totals.get(&neighbor) {
+                    if n >= ERROR_RATIO * reads && best.is_none_or(|(b, _)| n > b) {
+                        best = Some((n, neighbor));
+                    }
+                }
+            }
+            neighbor[i] = cellcode[i];
+        }
+        if let Some((_, parent)) = best {
+            parents.insert(*cellcode, parent);
+        }
+    }
+    parents
+}
+
+/// Follow the parents to the root cell code. Parents have more reads, so there are no cycles.
+pub fn root(parents: &HashMap<CellCode, CellCode>, cellcode: &CellCode) -> CellCode {
+    let mut cellcode = cellcode;
+    while let Some(parent) = parents.get(cellcode) {
+        cellcode = parent;
+    }
+    *cellcode
+}
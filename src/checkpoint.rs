@@ -0,0 +1,402 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error as IoError, ErrorKind, Read, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::counts::Counts;
+use crate::reader::LaneStats;
+use crate::report::Input;
+use crate::whitelist::{Distances, HitRate};
+
+/// First bytes of a checkpoint file, before the gzipped JSON
+const MAGIC: &[u8] = b"featureseek checkpoint\n";
+/// Version of the checkpoint contents, raised when a checkpoint cannot be read as before
+pub const FORMAT: u32 = 1;
+/// Arguments that may differ between the interrupted and the resumed run
+const RESUMABLE_CHANGES: [&str; 2] = ["resume", "checkpoint_every"];
+/// Arguments whose state is not kept in a checkpoint, so a run with them cannot be resumed: the
+/// hashes of the read pairs seen by --dedup differ per run, and the subsamples of --saturation
+/// would only cover the reads after the checkpoint
+const UNKEPT_STATE: [&str; 2] = ["dedup", "saturation"];
+
+/// The state of a run after the read pairs read so far, written while counting
+#[derive(Serialize)]
+pub struct Checkpoint<'a> {
+    pub format: u32,
+    /// The featureseek version that wrote the checkpoint
+    pub version: &'a str,
+    /// The arguments of the run
+    pub parameters: &'a serde_json::Value,
+    /// Checksums of the reference and whitelist files
+    pub inputs: &'a [Input],
+    /// Read pairs counted
+    pub count: usize,
+    /// Read pairs read, also those left out of a subsample
+    pub pairs: usize,
+    pub lanes: &'a [LaneStats],
+    pub counts: &'a Counts,
+    pub hit_rate: &'a HitRate,
+    pub distances: &'a Distances,
+}
+
+/// A checkpoint read back to resume the run
+#[derive(Deserialize)]
+pub struct Resumed {
+    pub format: u32,
+    pub version: String,
+    pub parameters: serde_json::Value,
+    pub inputs: Vec<Input>,
+    pub count: usize,
+    pub pairs: usize,
+    pub lanes: Vec<LaneStats>,
+    pub counts: Counts,
+    pub hit_rate: HitRate,
+    pub distances: Distances,
+}
+
+impl Checkpoint<'_> {
+    /// Write the checkpoint gzipped, through a temporary file so an interrupted write keeps the
+    /// previous checkpoint
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        let mut f = BufWriter::new(File::create(&partial)?);
+        f.write_all(MAGIC)?;
+        let mut f = GzEncoder::new(f, flate2::Compression::fast());
+        serde_json::to_writer(&mut f, self)?;
+        f.finish()?.flush()?;
+        std::fs::rename(partial, path)?;
+        Ok(())
+    }
+}
+
+impl Resumed {
+    /// Read a checkpoint of this format, written by a run with these arguments and the
+    /// reference and whitelist files of these checksums
+    pub fn read(path: &Path, parameters: &serde_json::Value, inputs: &[Input]) -> Result<Self> {
+        let mismatch = |what: String| {
+            IoError::new(ErrorKind::InvalidData, format!("Checkpoint {} {what}", path.display()))
+        };
+        let mut f = BufReader::new(File::open(path)?);
+        let mut magic = Vec::new();
+        f.by_ref().take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+        if magic != MAGIC {
+            return Err(mismatch("is not a featureseek checkpoint".to_owned()).into());
+        }
+
+        // the format is read before the rest, which may not parse in another format
+        let json: serde_json::Value = serde_json::from_reader(BufReader::new(GzDecoder::new(f)))?;
+        let format = json.get("format").and_then(serde_json::Value::as_u64).unwrap_or(0);
+        if format != u64::from(FORMAT) {
+            let version = json.get("version").and_then(serde_json::Value::as_str).unwrap_or("unknown");
+            return Err(mismatch(format!(
+                "has format {format}, written by featureseek {version}, this version reads format {FORMAT}"
+            ))
+            .into());
+        }
+        let resumed: Resumed = serde_json::from_value(json)?;
+        let unkept: Vec<_> = UNKEPT_STATE
+            .iter()
+            .filter(|name| parameters.get(**name).is_some_and(|value| !value.is_null()))
+            .map(|name| format!("--{name}"))
+            .collect();
+        if !unkept.is_empty() {
+            return Err(mismatch(format!("cannot resume a run with {}, whose state is not kept", unkept.join(", "))).into());
+        }
+        let changed = changed_parameters(&resumed.parameters, parameters);
+        if !changed.is_empty() {
+            return Err(mismatch(format!("was written with other arguments: {}", changed.join(", "))).into());
+        }
+        let changed: Vec<_> = inputs
+            .iter()
+            .filter(|input| {
+                !resumed.inputs.iter().any(|before| before.path == input.path && before.sha256 == input.sha256)
+            })
+            .map(|input| input.path.display().to_string())
+            .collect();
+        if !changed.is_empty() {
+            return Err(mismatch(format!("was written with other contents of {}", changed.join(", "))).into());
+        }
+        Ok(resumed)
+    }
+}
+
+/// The arguments that differ, except those that may change when resuming
+fn changed_parameters(before: &serde_json::Value, now: &serde_json::Value) -> Vec<String> {
+    let (Some(before), Some(now)) = (before.as_object(), now.as_object()) else {
+        return vec!["all".to_owned()];
+    };
+    let mut names: Vec<_> = before.keys().chain(now.keys()).collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| !RESUMABLE_CHANGES.contains(&name.as_str()) && before.get(*name) != now.get(*name))
+        .map(|name| format!("--{}", name.replace('_', "-")))
+        .collect()
+}
+
+/// A code in the counts as written in a checkpoint: the cell codes, feature barcodes and UMIs
+/// as text instead of arrays of numbers
+pub trait Key: Sized {
+    type Text: Serialize + DeserializeOwned;
+
+    fn to_text(&self) -> Self::Text;
+    fn from_text(text: Self::Text) -> Option<Self>;
+}
+
+impl Key for u16 {
+    type Text = u16;
+
+    fn to_text(&self) -> u16 {
+        *self
+    }
+
+    fn from_text(text: u16) -> Option<Self> {
+        Some(text)
+    }
+}
+
+/// The bases before the zero padding, followed by the -N suffix of a GEM well in the last byte
+/// as in [`cell_name`](crate::cell_name). A code filling the array, as a cell code of the
+/// longest length with its well, is written byte for byte.
+impl<const N: usize> Key for [u8; N] {
+    type Text = String;
+
+    fn to_text(&self) -> String {
+        let n = self.iter().position(|&b| b == 0).unwrap_or(N);
+        let mut text: String = self[..n].iter().copied().map(char::from).collect();
+        if n < N && self[N - 1] != 0 {
+            text += &format!("-{}", self[N - 1]);
+        }
+        text
+    }
+
+    fn from_text(text: String) -> Option<Self> {
+        let mut code = [0; N];
+        if text.chars().count() == N {
+            for (byte, c) in code.iter_mut().zip(text.chars()) {
+                *byte = u8::try_from(c).ok()?;
+            }
+            return Some(code);
+        }
+        let (bases, well) = match text.split_once('-') {
+            Some((bases, well)) => (bases, well.parse().ok()?),
+            None => (text.as_str(), 0),
+        };
+        code.get_mut(..bases.len())?.copy_from_slice(bases.as_bytes());
+        if well != 0 {
+            *code.get_mut(bases.len()..)?.last_mut()? = well;
+        }
+        Some(code)
+    }
+}
+
+impl<A: Key, B: Key> Key for (A, B) {
+    type Text = (A::Text, B::Text);
+
+    fn to_text(&self) -> Self::Text {
+        (self.0.to_text(), self.1.to_text())
+    }
+
+    fn from_text((a, b): Self::Text) -> Option<Self> {
+        Some((A::from_text(a)?, B::from_text(b)?))
+    }
+}
+
+impl<A: Key, B: Key, C: Key> Key for (A, B, C) {
+    type Text = (A::Text, B::Text, C::Text);
+
+    fn to_text(&self) -> Self::Text {
+        (self.0.to_text(), self.1.to_text(), self.2.to_text())
+    }
+
+    fn from_text((a, b, c): Self::Text) -> Option<Self> {
+        Some((A::from_text(a)?, B::from_text(b)?, C::from_text(c)?))
+    }
+}
+
+/// The error of a code that cannot be read back
+fn invalid_code<E: serde::de::Error>() -> E {
+    E::custom("invalid code in checkpoint")
+}
+
+/// Serde of a hash map as a sequence of (key, value) pairs, for keys that are neither strings
+/// nor numbers, which JSON objects cannot have
+pub mod entries {
+    use std::hash::Hash;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{invalid_code, Key};
+    use crate::hash::HashMap;
+
+    pub fn serialize<K: Key, V: Serialize, S: Serializer>(map: &HashMap<K, V>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(map.iter().map(|(k, v)| (k.to_text(), v)))
+    }
+
+    pub fn deserialize<'de, K, V, D>(d: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Key + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs: Vec<(K::Text, V)> = Vec::deserialize(d)?;
+        pairs
+            .into_iter()
+            .map(|(k, v)| Ok((K::from_text(k).ok_or_else(invalid_code)?, v)))
+            .collect()
+    }
+}
+
+/// Serde of an optional hash map as [`entries`]
+pub mod option_entries {
+    use std::hash::Hash;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{invalid_code, Key};
+    use crate::hash::HashMap;
+
+    pub fn serialize<K: Key, V: Serialize, S: Serializer>(
+        map: &Option<HashMap<K, V>>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        map.as_ref().map(|map| map.iter().map(|(k, v)| (k.to_text(), v)).collect::<Vec<_>>()).serialize(s)
+    }
+
+    pub fn deserialize<'de, K, V, D>(d: D) -> Result<Option<HashMap<K, V>>, D::Error>
+    where
+        K: Key + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs: Option<Vec<(K::Text, V)>> = Option::deserialize(d)?;
+        pairs
+            .map(|pairs| pairs.into_iter().map(|(k, v)| Ok((K::from_text(k).ok_or_else(invalid_code)?, v))).collect())
+            .transpose()
+    }
+}
+
+/// Serde of an optional hash set as a sequence of [`Key`] texts
+pub mod option_keys {
+    use std::hash::Hash;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{invalid_code, Key};
+    use crate::hash::HashSet;
+
+    pub fn serialize<K: Key, S: Serializer>(set: &Option<HashSet<K>>, s: S) -> Result<S::Ok, S::Error> {
+        set.as_ref().map(|set| set.iter().map(K::to_text).collect::<Vec<_>>()).serialize(s)
+    }
+
+    pub fn deserialize<'de, K, D>(d: D) -> Result<Option<HashSet<K>>, D::Error>
+    where
+        K: Key + Eq + Hash,
+        D: Deserializer<'de>,
+    {
+        let keys: Option<Vec<K::Text>> = Option::deserialize(d)?;
+        keys.map(|keys| keys.into_iter().map(|k| K::from_text(k).ok_or_else(invalid_code)).collect())
+            .transpose()
+    }
+}
+
+/// Serde of the sorted (key, count) pairs of a cell as a sequence of [`Key`] texts and counts
+pub mod counts {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use smallvec::SmallVec;
+
+    use super::{invalid_code, Key};
+
+    pub fn serialize<K: Key, S: Serializer>(pairs: &SmallVec<[(K, u32); 4]>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(pairs.iter().map(|(k, n)| (k.to_text(), n)))
+    }
+
+    pub fn deserialize<'de, K: Key, D: Deserializer<'de>>(d: D) -> Result<SmallVec<[(K, u32); 4]>, D::Error> {
+        let pairs: Vec<(K::Text, u32)> = Vec::deserialize(d)?;
+        pairs
+            .into_iter()
+            .map(|(k, n)| Ok((K::from_text(k).ok_or_else(invalid_code)?, n)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Barcode, CellCode, Umi, MAX_BCLENGTH, MAX_CCLENGTH};
+
+    #[test]
+    fn codes_round_trip_as_text() {
+        let mut cellcode: CellCode = [0; MAX_CCLENGTH + 1];
+        cellcode[..16].copy_from_slice(b"ACGTACGTACGTACGT");
+        let mut umi: Umi = [0; 12];
+        umi[..10].copy_from_slice(b"TTTTTGGGGG");
+        let key = (cellcode, 7u16, umi);
+        assert_eq!(key.to_text(), ("ACGTACGTACGTACGT".to_owned(), 7, "TTTTTGGGGG".to_owned()));
+        assert!(<(CellCode, u16, Umi)>::from_text(key.to_text()) == Some(key));
+
+        // the GEM well after the longest cell code
+        let mut longest: CellCode = [b'C'; MAX_CCLENGTH + 1];
+        longest[MAX_CCLENGTH] = 3;
+        assert_eq!(CellCode::from_text(longest.to_text()), Some(longest));
+        let mut short = [0; MAX_CCLENGTH + 1];
+        short[..16].copy_from_slice(&longest[..16]);
+        short[MAX_CCLENGTH] = 3;
+        assert_eq!(short.to_text(), format!("{}-3", "C".repeat(16)));
+        assert_eq!(CellCode::from_text(short.to_text()), Some(short));
+        let barcode = [b'G'; MAX_BCLENGTH];
+        assert_eq!(Barcode::from_text(barcode.to_text()), Some(barcode));
+
+        assert_eq!(Umi::from_text("A".repeat(13)), None);
+        assert_eq!(CellCode::from_text("ACGT-x".to_owned()), None);
+    }
+
+    #[test]
+    fn refuses_other_files_and_formats() {
+        let path = std::env::temp_dir().join(format!("featureseek-checkpoint-{}", std::process::id()));
+        let parameters = serde_json::json!({"min_reads": 5});
+        let inputs = |sha256: &str| {
+            vec![Input {
+                path: "ref.csv".into(),
+                sha256: sha256.to_owned(),
+            }]
+        };
+        let checkpoint = Checkpoint {
+            format: FORMAT,
+            version: "0.1.0",
+            parameters: &parameters,
+            inputs: &inputs("aa"),
+            count: 10,
+            pairs: 12,
+            lanes: &[],
+            counts: &Counts::default(),
+            hit_rate: &HitRate::new(1000),
+            distances: &Distances::new(1000),
+        };
+        checkpoint.write(&path).unwrap();
+        let error = |inputs: &[Input]| Resumed::read(&path, &parameters, inputs).err().map(|e| e.to_string());
+
+        assert_eq!(error(&inputs("aa")), None);
+        assert!(error(&inputs("bb")).unwrap().ends_with("was written with other contents of ref.csv"));
+
+        Checkpoint { format: FORMAT + 1, ..checkpoint }.write(&path).unwrap();
+        assert!(error(&inputs("aa")).unwrap().contains(&format!("has format {}", FORMAT + 1)));
+
+        let dedup = serde_json::json!({"min_reads": 5, "dedup": "codes"});
+        checkpoint.write(&path).unwrap();
+        let refused = Resumed::read(&path, &dedup, &inputs("aa")).err().unwrap().to_string();
+        assert!(refused.ends_with("cannot resume a run with --dedup, whose state is not kept"));
+        let saturation = serde_json::json!({"min_reads": 5, "saturation": "saturation.csv"});
+        let refused = Resumed::read(&path, &saturation, &inputs("aa")).err().unwrap().to_string();
+        assert!(refused.ends_with("cannot resume a run with --saturation, whose state is not kept"));
+
+        std::fs::write(&path, b"{}").unwrap();
+        assert!(error(&inputs("aa")).unwrap().ends_with("is not a featureseek checkpoint"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
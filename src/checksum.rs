@@ -0,0 +1,114 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Error as IoError, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+use crate::remote;
+
+/// Suffix of the file listing the input checksums of an output file
+pub const SUFFIX: &str = ".inputs.sha256";
+
+/// Calculate the hex encoded SHA-256 of a local or remote file
+pub fn sha256<P: AsRef<Path>>(p: P) -> Result<String> {
+    let mut f = remote::open(p)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut f, &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Checksums of the input files used for a run
+pub struct Inputs(Vec<(String, PathBuf)>);
+
+impl Inputs {
+    pub fn from_paths<'a, I: IntoIterator<Item = &'a Path>>(paths: I) -> Result<Self> {
+        let sums = paths
+            .into_iter()
+            .map(|p| {
+                let path = if remote::is_remote(p) { p.to_owned() } else { fs::canonicalize(p)? };
+                Ok((sha256(p)?, path))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Inputs(sums))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, PathBuf)> {
+        self.0.iter()
+    }
+
+    /// Write the checksums next to an output file in `sha256sum` format
+    pub fn write_for<P: AsRef<Path>>(&self, out: P) -> Result<()> {
+        let mut path = out.as_ref().as_os_str().to_owned();
+        path.push(SUFFIX);
+
+        let mut f = File::create(path)?;
+        for (sum, p) in &self.0 {
+            writeln!(f, "{sum}  {}", p.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the checksum of an input with the expected: OK, FAILED or MISSING
+pub fn status<P: AsRef<Path>>(sum: &str, input: P) -> &'static str {
+    match sha256(input) {
+        Ok(s) if s == sum => "OK",
+        Ok(_) => "FAILED",
+        Err(_) => "MISSING",
+    }
+}
+
+/// Verify the input checksums of a result directory or checksum file.
+/// Returns false when any of the inputs changed or is missing.
+pub fn verify<P: AsRef<Path>>(p: P) -> Result<bool> {
+    let p = p.as_ref();
+    let files = if p.is_dir() {
+        let mut files: Vec<_> = fs::read_dir(p)?
+            .map(|e| e.map(|e| e.path()))
+            .filter(|e| {
+                e.as_ref()
+                    .map_or(true, |p| p.to_string_lossy().ends_with(SUFFIX))
+            })
+            .collect::<Result<_, IoError>>()?;
+        files.sort();
+        files
+    } else {
+        vec![p.to_owned()]
+    };
+
+    if files.is_empty() {
+        return Err(IoError::new(
+            ErrorKind::NotFound,
+            format!("No *{SUFFIX} files found in {}", p.display()),
+        )
+        .into());
+    }
+
+    let mut ok = true;
+    for file in files {
+        println!("{}:", file.display());
+        let b = BufReader::new(File::open(&file)?);
+        for line in b.lines() {
+            let line = line?;
+            let (sum, input) = line.split_once("  ").ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid checksum line in {}: {line}", file.display()),
+                )
+            })?;
+
+            let status = status(sum, input);
+            ok &= status == "OK";
+            println!("  {input}: {status}");
+        }
+    }
+
+    Ok(ok)
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::reader::{self, Compression, Layout};
+use crate::whitelist::Whitelist;
+use crate::CCLENGTH;
+
+/// Number of read 1 records inspected to detect the chemistry
+const DETECT_READS: usize = 10_000;
+
+/// Fraction of the inspected cell codes that must be whitelisted for the whitelist to decide
+/// the chemistry, below it a warning is printed
+pub const MIN_HIT_RATE: f64 = 0.5;
+
+/// 10x single cell chemistries, differing in the UMI length and whitelist
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Chemistry {
+    /// 3' v2, 16 base cell code and 10 base UMI
+    V2,
+    /// 3' v3, 16 base cell code and 12 base UMI
+    V3,
+    /// 5' v2, 16 base cell code and 10 base UMI, only set by `--chemistry 5p` as the read 1
+    /// of 5' libraries often continues into the template switch oligo
+    #[serde(rename = "5p")]
+    FivePrime,
+}
+
+/// The detected chemistry with the evidence for the decision
+pub struct Detection {
+    pub chemistry: Chemistry,
+    /// Median read 1 length of the inspected reads
+    pub r1_length: usize,
+    pub evidence: Evidence,
+    /// Fraction of the inspected cell codes found in the whitelist
+    pub whitelist_hit_rate: Option<f64>,
+}
+
+/// What decided the chemistry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Evidence {
+    /// Set by `--chemistry`
+    Preset,
+    /// The median read 1 length
+    ReadLength,
+    /// The size of a 10x whitelist matching most cell codes, when read 1 is long enough for
+    /// both UMI lengths
+    Whitelist,
+}
+
+impl fmt::Display for Chemistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chemistry::V2 => write!(f, "v2"),
+            Chemistry::V3 => write!(f, "v3"),
+            Chemistry::FivePrime => write!(f, "5' v2"),
+        }
+    }
+}
+
+impl Chemistry {
+    pub fn umi_length(self) -> usize {
+        match self {
+            Chemistry::V2 | Chemistry::FivePrime => 10,
+            Chemistry::V3 => 12,
+        }
+    }
+
+    /// Name of the 10x whitelist of the cell codes of the chemistry
+    pub fn whitelist(self) -> &'static str {
+        match self {
+            Chemistry::V2 | Chemistry::FivePrime => "737K-august-2016",
+            Chemistry::V3 => "3M-february-2018",
+        }
+    }
+
+    /// Number of cell codes in the 10x whitelist of the chemistry
+    pub fn whitelist_codes(self) -> usize {
+        match self {
+            Chemistry::V2 | Chemistry::FivePrime => 737_280,
+            Chemistry::V3 => 6_794_880,
+        }
+    }
+
+    /// Detect the chemistry from the median length of the first read 1 records: 26 bases or
+    /// less is v2, longer is v3 unless the whitelist is the 10x v2 whitelist and matches most
+    /// cell codes, as v2 read 1 is often sequenced 28 bases like v3. The chemistry of a preset
+    /// is only checked against the whitelist. None when the file is empty.
+    pub fn detect<P: AsRef<Path>>(
+        r1: P,
+        layout: Layout,
+        compression: Compression,
+        whitelist: Option<&Whitelist>,
+        preset: Option<Chemistry>,
+    ) -> Result<Option<Detection>> {
+        let (mut lengths, mut hits) = (Vec::new(), 0);
+        reader::for_each_record(r1, DETECT_READS, layout, false, compression, |seq| {
+            lengths.push(seq.len());
+            if whitelist.is_some_and(|w| seq.len() >= CCLENGTH && w.contains(&seq[..CCLENGTH])) {
+                hits += 1;
+            }
+        })?;
+        if lengths.is_empty() {
+            return Ok(None);
+        }
+
+        lengths.sort_unstable();
+        let r1_length = lengths[lengths.len() / 2];
+        let whitelist_hit_rate = whitelist.map(|_| hits as f64 / lengths.len() as f64);
+        let codes = whitelist.map(Whitelist::codes);
+        let (chemistry, evidence) = decide(r1_length, whitelist_hit_rate.zip(codes), preset);
+
+        Ok(Some(Detection {
+            chemistry,
+            r1_length,
+            evidence,
+            whitelist_hit_rate,
+        }))
+    }
+}
+
+/// The chemistry from the median read 1 length and the hit rate and size of the whitelist
+fn decide(r1_length: usize, whitelist: Option<(f64, usize)>, preset: Option<Chemistry>) -> (Chemistry, Evidence) {
+    if let Some(chemistry) = preset {
+        return (chemistry, Evidence::Preset);
+    }
+    if r1_length <= CCLENGTH + Chemistry::V2.umi_length() {
+        return (Chemistry::V2, Evidence::ReadLength);
+    }
+    // long enough for both, a 10x whitelist matching most cell codes tells them apart
+    match whitelist {
+        Some((rate, codes)) if rate >= MIN_HIT_RATE => [Chemistry::V2, Chemistry::V3]
+            .into_iter()
+            .find(|c| c.whitelist_codes() == codes)
+            .map_or((Chemistry::V3, Evidence::ReadLength), |c| (c, Evidence::Whitelist)),
+        _ => (Chemistry::V3, Evidence::ReadLength),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_read_1_is_v2() {
+        assert_eq!(decide(26, None, None), (Chemistry::V2, Evidence::ReadLength));
+        assert_eq!(decide(26, Some((0.9, 6_794_880)), None), (Chemistry::V2, Evidence::ReadLength));
+    }
+
+    #[test]
+    fn whitelist_decides_a_long_read_1() {
+        assert_eq!(decide(28, None, None), (Chemistry::V3, Evidence::ReadLength));
+        assert_eq!(decide(28, Some((0.9, 737_280)), None), (Chemistry::V2, Evidence::Whitelist));
+        assert_eq!(decide(28, Some((0.9, 6_794_880)), None), (Chemistry::V3, Evidence::Whitelist));
+        // a whitelist matching few cell codes or of another size leaves the read length
+        assert_eq!(decide(28, Some((0.1, 737_280)), None), (Chemistry::V3, Evidence::ReadLength));
+        assert_eq!(decide(28, Some((0.9, 1000)), None), (Chemistry::V3, Evidence::ReadLength));
+    }
+
+    #[test]
+    fn preset_wins() {
+        let whitelist = Some((0.9, 6_794_880));
+        assert_eq!(decide(28, whitelist, Some(Chemistry::FivePrime)), (Chemistry::FivePrime, Evidence::Preset));
+    }
+}
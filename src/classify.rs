@@ -0,0 +1,259 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Method to separate the background from the signal cells of a feature
+#[derive(Clone, Copy, Debug, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Classifier {
+    /// Otsu's method on the log counts
+    #[default]
+    Otsu,
+    /// Two component gaussian mixture on the log counts
+    Gmm,
+}
+
+/// Summary of the per cell counts of a feature
+#[derive(Serialize, Deserialize)]
+pub struct Distribution {
+    pub p90: f64,
+    pub p99: f64,
+    /// Most frequent count of the cells below the threshold
+    pub background_mode: usize,
+    /// Adaptive threshold separating the background from the signal
+    pub threshold: usize,
+    /// Fraction of the cells at or above the threshold
+    pub positive_fraction: f64,
+}
+
+impl Classifier {
+    /// Count threshold separating the background from the signal. A cell is positive when its
+    /// count is at least the threshold.
+    pub fn threshold(self, counts: &[usize]) -> usize {
+        match self {
+            Classifier::Otsu => otsu_threshold(counts),
+            Classifier::Gmm => gmm_threshold(counts),
+        }
+    }
+}
+
+impl Distribution {
+    pub fn new(counts: &[usize], classifier: Classifier) -> Self {
+        let mut sorted = counts.to_vec();
+        sorted.sort_unstable();
+
+        let threshold = classifier.threshold(&sorted);
+        let n_background = sorted.partition_point(|&c| c < threshold);
+        let positive_fraction = if sorted.is_empty() {
+            0.0
+        } else {
+            (sorted.len() - n_background) as f64 / sorted.len() as f64
+        };
+
+        Distribution {
+            p90: quantile(&sorted, 0.9),
+            p99: quantile(&sorted, 0.99),
+            background_mode: mode(&sorted[..n_background]),
+            threshold,
+            positive_fraction,
+        }
+    }
+}
+
+/// Quantile of sorted values using linear interpolation between the closest ranks
+pub fn quantile(sorted: &[usize], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let h = (sorted.len() - 1) as f64 * q;
+    let (lo, hi) = (h.floor() as usize, h.ceil() as usize);
+    sorted[lo] as f64 + (h - lo as f64) * (sorted[hi] - sorted[lo]) as f64
+}
+
+/// Most frequent value of sorted values, the smallest one on ties
+pub fn mode(sorted: &[usize]) -> usize {
+    sorted
+        .chunk_by(|a, b| a == b)
+        .fold((0, 0), |best, run| if run.len() > best.1 { (run[0], run.len()) } else { best })
+        .0
+}
+
+/// Adaptive count threshold separating background from signal cells using Otsu's method on the
+/// log counts. A cell is positive when its count is at least the returned threshold.
+pub fn otsu_threshold(counts: &[usize]) -> usize {
+    let mut sorted = counts.to_vec();
+    sorted.sort_unstable();
+
+    let log = |c: usize| (c as f64).ln_1p();
+    let n = sorted.len() as f64;
+    let total: f64 = sorted.iter().map(|&c| log(c)).sum();
+
+    let mut best = (f64::MIN, 1);
+    let mut sum_low = 0.0;
+    for (i, w) in sorted.windows(2).enumerate() {
+        sum_low += log(w[0]);
+        if w[0] == w[1] {
+            continue;
+        }
+
+        let n_low = (i + 1) as f64;
+        let n_high = n - n_low;
+        let mean_low = sum_low / n_low;
+        let mean_high = (total - sum_low) / n_high;
+        let variance = n_low * n_high * (mean_low - mean_high).powi(2);
+        if variance > best.0 {
+            best = (variance, w[1]);
+        }
+    }
+
+    best.1
+}
+
+/// Gaussian component of a mixture
+#[derive(Clone, Copy)]
+struct Component {
+    weight: f64,
+    mean: f64,
+    var: f64,
+}
+
+impl Component {
+    fn fit(x: &[f64], resp: impl Iterator<Item = f64> + Clone) -> Self {
+        let total: f64 = resp.clone().sum::<f64>().max(f64::MIN_POSITIVE);
+        let mean = x.iter().zip(resp.clone()).map(|(x, r)| r * x).sum::<f64>() / total;
+        let var = x.iter().zip(resp).map(|(x, r)| r * (x - mean).powi(2)).sum::<f64>() / total;
+
+        Component {
+            weight: total / x.len() as f64,
+            mean,
+            var: var.max(1e-3),
+        }
+    }
+
+    /// Log of the weighted density at x
+    fn log_density(&self, x: f64) -> f64 {
+        self.weight.ln()
+            - 0.5 * (2.0 * std::f64::consts::PI * self.var).ln()
+            - (x - self.mean).powi(2) / (2.0 * self.var)
+    }
+}
+
+/// Adaptive count threshold from a two component gaussian mixture fitted on the log counts. The
+/// threshold is the crossing point of the weighted background and signal densities. Falls back
+/// to Otsu's method when the counts cannot be separated.
+pub fn gmm_threshold(counts: &[usize]) -> usize {
+    let otsu = otsu_threshold(counts);
+    let x: Vec<f64> = counts.iter().map(|&c| (c as f64).ln_1p()).collect();
+
+    // initialize with the Otsu split
+    let mut resp: Vec<f64> = counts.iter().map(|&c| (c >= otsu) as u8 as f64).collect();
+    if resp.iter().all(|&r| r == resp[0]) {
+        return otsu;
+    }
+
+    let mut low = Component::fit(&x, resp.iter().map(|r| 1.0 - r));
+    let mut high = Component::fit(&x, resp.iter().copied());
+    let mut log_likelihood = f64::MIN;
+    for _ in 0..200 {
+        // expectation
+        let mut ll = 0.0;
+        for (r, &x) in resp.iter_mut().zip(&x) {
+            let (l, h) = (low.log_density(x), high.log_density(x));
+            let max = l.max(h);
+            let sum = max + ((l - max).exp() + (h - max).exp()).ln();
+            *r = (h - sum).exp();
+            ll += sum;
+        }
+
+        // maximization
+        low = Component::fit(&x, resp.iter().map(|r| 1.0 - r));
+        high = Component::fit(&x, resp.iter().copied());
+
+        if ll - log_likelihood < 1e-6 {
+            break;
+        }
+        log_likelihood = ll;
+    }
+
+    if high.mean <= low.mean {
+        return otsu;
+    }
+
+    // bisect the crossing point between the two means
+    let diff = |x: f64| high.log_density(x) - low.log_density(x);
+    let (mut a, mut b) = (low.mean, high.mean);
+    if diff(a) >= 0.0 || diff(b) <= 0.0 {
+        return otsu;
+    }
+    for _ in 0..60 {
+        let m = (a + b) / 2.0;
+        if diff(m) < 0.0 {
+            a = m;
+        } else {
+            b = m;
+        }
+    }
+
+    b.exp_m1().ceil() as usize
+}
+
+/// Ambient background of the features, estimated from the reads of each cell outside its most
+/// frequent feature
+pub struct Ambient {
+    /// Fraction of the reads of a cell from the ambient background
+    pub fraction: f64,
+    /// Fraction of each feature in the ambient background
+    profile: Vec<f64>,
+}
+
+impl Ambient {
+    /// Estimate the background from the per cell counts of the features
+    pub fn estimate(cells: &[Vec<usize>]) -> Self {
+        let n = cells.first().map_or(0, Vec::len);
+        let top = |counts: &[usize]| (0..counts.len()).max_by_key(|&f| (counts[f], std::cmp::Reverse(f)));
+
+        // one pseudocount per feature, so no feature is impossible as background
+        let mut profile = vec![1.0; n];
+        for counts in cells {
+            let top = top(counts);
+            for (f, &c) in counts.iter().enumerate() {
+                if Some(f) != top {
+                    profile[f] += c as f64;
+                }
+            }
+        }
+        let total: f64 = profile.iter().sum();
+        profile.iter_mut().for_each(|p| *p /= total);
+
+        // the background reads of the most frequent feature are hidden in its signal
+        let (mut outside, mut expected) = (0.0, 0.0);
+        for counts in cells {
+            if let Some(top) = top(counts) {
+                let reads: usize = counts.iter().sum();
+                outside += (reads - counts[top]) as f64;
+                expected += reads as f64 * (1.0 - profile[top]);
+            }
+        }
+        let fraction = if expected > 0.0 { (outside / expected).clamp(1e-3, 0.999) } else { 1e-3 };
+
+        Ambient { fraction, profile }
+    }
+
+    /// Posterior of each feature labeling a cell with these counts, with equal priors. The reads
+    /// of a cell are the fraction of background and the rest from its label.
+    pub fn posteriors(&self, counts: &[usize]) -> Vec<f64> {
+        let background: Vec<f64> = self.profile.iter().map(|p| (self.fraction * p).ln()).collect();
+        let base: f64 = counts.iter().zip(&background).map(|(&c, b)| c as f64 * b).sum();
+        let log_likelihood: Vec<f64> = counts
+            .iter()
+            .zip(&self.profile)
+            .zip(&background)
+            .map(|((&c, p), b)| base + c as f64 * ((self.fraction * p + 1.0 - self.fraction).ln() - b))
+            .collect();
+
+        let max = log_likelihood.iter().copied().fold(f64::MIN, f64::max);
+        let weights: Vec<f64> = log_likelihood.iter().map(|l| (l - max).exp()).collect();
+        let sum: f64 = weights.iter().sum();
+        weights.iter().map(|w| w / sum).collect()
+    }
+}
@@ -0,0 +1,676 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use cli_table::{
+    format::{Border, Justify, Separator},
+    Cell, Color, Style, Table, TableStruct,
+};
+use serde::Deserialize;
+
+use crate::hash::HashSet;
+use crate::report::{Feature, ReferenceFeature, Report};
+
+/// A row of the sample sheet
+#[derive(Deserialize)]
+struct SheetRow {
+    sample: String,
+    json: PathBuf,
+    #[serde(default)]
+    replicate: Option<String>,
+    #[serde(default)]
+    concentration: Option<f64>,
+}
+
+/// The JSON summary of a single sample
+pub struct Sample {
+    pub name: String,
+    /// Samples with the same replicate group are technical replicates
+    pub replicate: Option<String>,
+    /// Antibody concentration of a titration series
+    pub concentration: Option<f64>,
+    pub report: Report,
+}
+
+/// Concordance of the normalized feature counts of two technical replicates
+pub struct Concordance {
+    pub a: usize,
+    pub b: usize,
+    /// Pearson correlation of the log normalized counts
+    pub correlation: f64,
+    /// Features differing more than [`DISCORDANT_LOG2_RATIO`], with their log2 ratio
+    pub discordant: Vec<(usize, f64)>,
+}
+
+/// Counts per million reads of a feature over a concentration series. The size factors are not
+/// used as they would cancel a common response of all features.
+pub struct Titration {
+    pub feature: usize,
+    /// (sample, counts per million reads) sorted by concentration
+    pub points: Vec<(usize, f64)>,
+    /// Slope of the log2 counts per million on the log2 concentration
+    pub slope: f64,
+}
+
+/// Differences of the reference of a sample to the reference of the first sample, of the
+/// samples with the reference in their summary
+pub struct ReferenceChanges {
+    pub sample: usize,
+    /// The sample compared to
+    pub base: usize,
+    /// Rows with a sequence only in this sample
+    pub added: Vec<ReferenceFeature>,
+    /// Rows with a sequence only in the base sample
+    pub removed: Vec<ReferenceFeature>,
+    /// Rows with the same sequence and another id or name, as (base, this sample)
+    pub renamed: Vec<(ReferenceFeature, ReferenceFeature)>,
+}
+
+/// Features of replicates with a larger absolute log2 ratio of normalized counts are flagged
+pub const DISCORDANT_LOG2_RATIO: f64 = 1.0;
+
+/// Results of multiple samples, with the union of their features
+pub struct Combined {
+    pub samples: Vec<Sample>,
+    /// (id, name, barcode) in order of first appearance
+    features: Vec<(String, String, String)>,
+    /// Median of ratios size factor per sample
+    pub size_factors: Vec<f64>,
+}
+
+impl Sample {
+    fn feature(&self, barcode: &str) -> Option<&Feature> {
+        self.report.features.iter().find(|f| f.barcode == barcode)
+    }
+
+    /// Whether the sequence was in the reference of the sample, assumed for summaries without
+    /// the reference
+    fn in_reference(&self, barcode: &str) -> bool {
+        self.report.reference.is_empty() || self.report.reference.iter().any(|r| r.sequence == barcode)
+    }
+}
+
+impl Combined {
+    /// Read the sample sheet, a csv file with `sample` and `json` columns. The JSON summaries
+    /// are relative to the sample sheet.
+    pub fn from_samplesheet<P: AsRef<Path>>(p: P) -> Result<Self> {
+        let dir = p.as_ref().parent().unwrap_or(Path::new(""));
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(p.as_ref())?;
+
+        let mut samples: Vec<Sample> = Vec::new();
+        for (i, row) in reader.deserialize().enumerate() {
+            let row: SheetRow = row?;
+            if samples.iter().any(|s| s.name == row.sample) {
+                bail!("Duplicate sample {} on row {} of the sample sheet", row.sample, i + 2);
+            }
+            let path = dir.join(&row.json);
+            let report = Report::from_path(&path)
+                .with_context(|| format!("Reading summary {} of sample {}", path.display(), row.sample))?;
+            samples.push(Sample {
+                name: row.sample,
+                replicate: row.replicate,
+                concentration: row.concentration,
+                report,
+            });
+        }
+        if samples.is_empty() {
+            bail!("The sample sheet contains no samples");
+        }
+
+        let mut seen = HashSet::default();
+        let features = samples
+            .iter()
+            .flat_map(|s| &s.report.features)
+            .filter(|f| seen.insert(f.barcode.as_str()))
+            .map(|f| (f.id.clone(), f.name.clone(), f.barcode.clone()))
+            .collect::<Vec<_>>();
+
+        let counts: Vec<Vec<usize>> = samples
+            .iter()
+            .map(|s| {
+                features
+                    .iter()
+                    .map(|(_, _, barcode)| s.feature(barcode).map_or(0, |f| f.count))
+                    .collect()
+            })
+            .collect();
+        let size_factors = size_factors(&counts);
+
+        Ok(Combined { samples, features, size_factors })
+    }
+
+    /// Features by samples table of the counts and cells
+    pub fn table(&self) -> TableStruct {
+        let mut tabledata = Vec::new();
+        for (_, name, barcode) in &self.features {
+            let mut row = vec![name.cell(), barcode.cell()];
+            for sample in &self.samples {
+                if !sample.in_reference(barcode) {
+                    row.push("NA".cell().justify(Justify::Right));
+                    row.push("NA".cell().justify(Justify::Right));
+                    continue;
+                }
+                let f = sample.feature(barcode);
+                let col = match f {
+                    Some(f) if f.passed => Some(Color::Green),
+                    _ => Some(Color::Red),
+                };
+                row.push(f.map_or(0, |f| f.count).cell().justify(Justify::Right).foreground_color(col));
+                row.push(f.map_or(0, |f| f.cells).cell().justify(Justify::Right).foreground_color(col));
+            }
+            tabledata.push(row);
+        }
+
+        let mut title = vec!["name".cell(), "barcode".cell()];
+        for sample in &self.samples {
+            title.push(format!("{} count", sample.name).cell());
+            title.push(format!("{} cells", sample.name).cell());
+        }
+
+        tabledata
+            .table()
+            .title(title)
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build())
+    }
+
+    /// The feature counts of each sample divided by its size factor
+    fn normalized(&self) -> Vec<Vec<f64>> {
+        self.samples
+            .iter()
+            .zip(&self.size_factors)
+            .map(|(s, factor)| {
+                self.features
+                    .iter()
+                    .map(|(_, _, barcode)| s.feature(barcode).map_or(0, |f| f.count) as f64 / factor)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Concordance of each pair of samples in the same replicate group
+    pub fn replicates(&self) -> Vec<Concordance> {
+        let normalized = self.normalized();
+
+        let mut pairs = Vec::new();
+        for (a, sa) in self.samples.iter().enumerate() {
+            for (b, sb) in self.samples.iter().enumerate().skip(a + 1) {
+                if sa.replicate.is_none() || sa.replicate != sb.replicate {
+                    continue;
+                }
+                // the features in the references of both
+                let shared: Vec<usize> = (0..self.features.len())
+                    .filter(|&i| sa.in_reference(&self.features[i].2) && sb.in_reference(&self.features[i].2))
+                    .collect();
+                let (x, y): (Vec<f64>, Vec<f64>) = shared.iter().map(|&i| (normalized[a][i], normalized[b][i])).unzip();
+                let log = |v: &[f64]| v.iter().map(|c| c.ln_1p()).collect::<Vec<_>>();
+                let discordant = x
+                    .iter()
+                    .zip(&y)
+                    .map(|(x, y)| ((x + 1.0) / (y + 1.0)).log2())
+                    .zip(&shared)
+                    .filter(|(ratio, _)| ratio.abs() > DISCORDANT_LOG2_RATIO)
+                    .map(|(ratio, &i)| (i, ratio))
+                    .collect();
+                pairs.push(Concordance { a, b, correlation: pearson(&log(&x), &log(&y)), discordant });
+            }
+        }
+        pairs
+    }
+
+    /// The counts per million reads of each feature over the samples with a concentration.
+    /// Empty when fewer than two distinct concentrations are given.
+    pub fn titration(&self) -> Vec<Titration> {
+        let mut series: Vec<(usize, f64)> = self
+            .samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.concentration.filter(|&c| c > 0.0).map(|c| (i, c)))
+            .collect();
+        series.sort_by(|a, b| a.1.total_cmp(&b.1));
+        if series.first().map(|s| s.1) == series.last().map(|s| s.1) {
+            return Vec::new();
+        }
+
+        let x: Vec<f64> = series.iter().map(|&(_, c)| c.log2()).collect();
+        let cpm = |i: usize, barcode: &str| {
+            let sample = &self.samples[i];
+            if !sample.in_reference(barcode) {
+                return f64::NAN;
+            }
+            let count = sample.feature(barcode).map_or(0, |f| f.count);
+            1e6 * count as f64 / sample.report.reads.max(1) as f64
+        };
+        self.features
+            .iter()
+            .enumerate()
+            .map(|(feature, (_, _, barcode))| {
+                let points: Vec<_> = series.iter().map(|&(i, _)| (i, cpm(i, barcode))).collect();
+                let y: Vec<f64> = points.iter().map(|&(_, n)| (n + 1.0).log2()).collect();
+                Titration { feature, points, slope: slope(&x, &y) }
+            })
+            .collect()
+    }
+
+    fn titration_header(&self, titration: &Titration) -> Vec<String> {
+        titration
+            .points
+            .iter()
+            .map(|&(i, _)| format!("{} ({})", self.samples[i].name, self.samples[i].concentration.unwrap()))
+            .collect()
+    }
+
+    /// Print the counts per million and slope of each feature over the concentration series
+    pub fn print_titration(&self) {
+        let titration = self.titration();
+        let Some(first) = titration.first() else {
+            return;
+        };
+
+        let mut tabledata = Vec::new();
+        for t in &titration {
+            let mut row = vec![self.features[t.feature].1.clone().cell()];
+            for (_, n) in &t.points {
+                row.push(format!("{n:.1}").cell().justify(Justify::Right));
+            }
+            row.push(format!("{:.2}", t.slope).cell().justify(Justify::Right));
+            tabledata.push(row);
+        }
+
+        let mut title = vec!["name".cell()];
+        title.extend(self.titration_header(first).into_iter().map(|h| h.cell()));
+        title.push("slope".cell());
+
+        let table = tabledata
+            .table()
+            .title(title)
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+        println!("\nTitration, counts per million reads by concentration:\n{}", table.display().unwrap());
+    }
+
+    /// Write the counts per million and slope of each feature over the concentration series
+    pub fn write_titration<W: Write>(&self, w: W) -> Result<()> {
+        let titration = self.titration();
+        let Some(first) = titration.first() else {
+            bail!("The sample sheet contains less than two concentrations");
+        };
+
+        let mut writer = csv::Writer::from_writer(w);
+        let mut header = vec!["id".to_owned(), "name".to_owned()];
+        header.extend(self.titration_header(first));
+        header.push("slope".to_owned());
+        writer.write_record(&header)?;
+
+        for t in &titration {
+            let (id, name, _) = &self.features[t.feature];
+            let mut record = vec![id.clone(), name.clone()];
+            record.extend(t.points.iter().map(|(_, n)| format!("{n:.1}")));
+            record.push(format!("{:.3}", t.slope));
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The reference differences of each sample to the first sample with the reference in its
+    /// summary. Samples with the same reference are left out.
+    pub fn reference_changes(&self) -> Vec<ReferenceChanges> {
+        let with_reference: Vec<usize> =
+            (0..self.samples.len()).filter(|&i| !self.samples[i].report.reference.is_empty()).collect();
+        let Some((&base, others)) = with_reference.split_first() else {
+            return Vec::new();
+        };
+        let reference = |i: usize| &self.samples[i].report.reference;
+        let find = |i: usize, sequence: &str| reference(i).iter().find(|r| r.sequence == sequence);
+
+        let mut changes = Vec::new();
+        for &sample in others {
+            let added: Vec<_> = reference(sample).iter().filter(|r| find(base, &r.sequence).is_none()).cloned().collect();
+            let removed: Vec<_> = reference(base).iter().filter(|r| find(sample, &r.sequence).is_none()).cloned().collect();
+            let renamed: Vec<_> = reference(base)
+                .iter()
+                .filter_map(|b| find(sample, &b.sequence).map(|r| (b, r)))
+                .filter(|(b, r)| b.id != r.id || b.name != r.name)
+                .map(|(b, r)| (b.clone(), r.clone()))
+                .collect();
+            if !added.is_empty() || !removed.is_empty() || !renamed.is_empty() {
+                changes.push(ReferenceChanges { sample, base, added, removed, renamed });
+            }
+        }
+        changes
+    }
+
+    /// Print the reference differences of the samples. The counts of a feature missing from
+    /// the reference of a sample are shown as NA.
+    pub fn print_reference_changes(&self) {
+        for c in self.reference_changes() {
+            println!(
+                "WARNING: the reference of {} differs from {}, {} added, {} removed, {} renamed",
+                self.samples[c.sample].name,
+                self.samples[c.base].name,
+                c.added.len(),
+                c.removed.len(),
+                c.renamed.len()
+            );
+            for r in &c.added {
+                println!("  added {} {} {}", r.id, r.name, r.sequence);
+            }
+            for r in &c.removed {
+                println!("  removed {} {} {}", r.id, r.name, r.sequence);
+            }
+            for (b, r) in &c.renamed {
+                println!("  renamed {} {} to {} {} {}", b.id, b.name, r.id, r.name, r.sequence);
+            }
+        }
+    }
+
+    /// Print the correlation of the replicates and their discordant features
+    pub fn print_replicates(&self) {
+        for c in self.replicates() {
+            println!(
+                "Replicates {} and {}: correlation {:.3}",
+                self.samples[c.a].name, self.samples[c.b].name, c.correlation
+            );
+            for (i, ratio) in c.discordant {
+                println!("  WARNING: {} differs {ratio:+.2} log2", self.features[i].1);
+            }
+        }
+    }
+
+    /// Print the size factor of each sample
+    pub fn print_size_factors(&self) {
+        let factors: Vec<_> = self
+            .samples
+            .iter()
+            .zip(&self.size_factors)
+            .map(|(s, f)| format!("{} {f:.3}", s.name))
+            .collect();
+        println!("\nSize factors: {}", factors.join(", "));
+    }
+
+    /// Write the features by samples counts and cells as csv
+    pub fn write_csv<W: Write>(&self, w: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(w);
+
+        let mut header = vec!["id".to_owned(), "name".to_owned(), "barcode".to_owned()];
+        for sample in &self.samples {
+            header.push(format!("{}_count", sample.name));
+            header.push(format!("{}_cells", sample.name));
+            header.push(format!("{}_normalized", sample.name));
+        }
+        writer.write_record(&header)?;
+
+        for (id, name, barcode) in &self.features {
+            let mut record = vec![id.clone(), name.clone(), barcode.clone()];
+            for (sample, factor) in self.samples.iter().zip(&self.size_factors) {
+                if !sample.in_reference(barcode) {
+                    record.extend(["NA", "NA", "NA"].map(str::to_owned));
+                    continue;
+                }
+                let f = sample.feature(barcode);
+                let count = f.map_or(0, |f| f.count);
+                record.push(count.to_string());
+                record.push(f.map_or(0, |f| f.cells).to_string());
+                record.push(format!("{:.1}", count as f64 / factor));
+            }
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write a self contained HTML report with the combined table and a tab per sample
+    pub fn write_html<W: Write>(&self, mut w: W) -> Result<()> {
+        writeln!(w, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(w, "<title>featureseek combined report</title>\n<style>\n{HTML_STYLE}")?;
+        for i in 0..=self.samples.len() {
+            writeln!(w, "#tab{i}:checked ~ #page{i} {{ display: block; }}")?;
+        }
+        writeln!(w, "</style>\n</head>\n<body>\n<h1>featureseek combined report</h1>\n<div class=\"tabs\">")?;
+
+        writeln!(w, "<input type=\"radio\" name=\"tabs\" id=\"tab0\" checked><label for=\"tab0\">All samples</label>")?;
+        for (i, sample) in self.samples.iter().enumerate() {
+            let i = i + 1;
+            writeln!(w, "<input type=\"radio\" name=\"tabs\" id=\"tab{i}\"><label for=\"tab{i}\">{}</label>", escape(&sample.name))?;
+        }
+
+        write!(w, "<div class=\"page\" id=\"page0\">\n<table>\n<tr><th>id</th><th>name</th><th>barcode</th>")?;
+        for sample in &self.samples {
+            let name = escape(&sample.name);
+            write!(w, "<th>{name} count</th><th>{name} cells</th>")?;
+        }
+        writeln!(w, "</tr>")?;
+        for (id, name, barcode) in &self.features {
+            write!(w, "<tr><td>{}</td><td>{}</td><td>{}</td>", escape(id), escape(name), escape(barcode))?;
+            for sample in &self.samples {
+                if !sample.in_reference(barcode) {
+                    write!(w, "<td>NA</td><td>NA</td>")?;
+                    continue;
+                }
+                match sample.feature(barcode) {
+                    Some(f) => write!(w, "<td{0}>{1}</td><td{0}>{2}</td>", passed_class(f.passed), f.count, f.cells)?,
+                    None => write!(w, "<td class=\"failed\">0</td><td class=\"failed\">0</td>")?,
+                }
+            }
+            writeln!(w, "</tr>")?;
+        }
+        writeln!(w, "</table>")?;
+
+        write!(w, "<table>\n<tr><th>size factor</th>")?;
+        for factor in &self.size_factors {
+            write!(w, "<td>{factor:.3}</td>")?;
+        }
+        writeln!(w, "</tr>\n</table>")?;
+
+        write!(w, "<table>\n<tr><th>name</th>")?;
+        for sample in &self.samples {
+            write!(w, "<th>{} normalized</th>", escape(&sample.name))?;
+        }
+        writeln!(w, "</tr>")?;
+        for (_, name, barcode) in &self.features {
+            write!(w, "<tr><td>{}</td>", escape(name))?;
+            for (sample, factor) in self.samples.iter().zip(&self.size_factors) {
+                if !sample.in_reference(barcode) {
+                    write!(w, "<td>NA</td>")?;
+                    continue;
+                }
+                let count = sample.feature(barcode).map_or(0, |f| f.count);
+                write!(w, "<td>{:.1}</td>", count as f64 / factor)?;
+            }
+            writeln!(w, "</tr>")?;
+        }
+        writeln!(w, "</table>")?;
+
+        for c in self.reference_changes() {
+            let (sample, base) = (escape(&self.samples[c.sample].name), escape(&self.samples[c.base].name));
+            writeln!(w, "<p>The reference of {sample} differs from {base}</p>")?;
+            writeln!(w, "<table>\n<tr><th>change</th><th>id</th><th>name</th><th>sequence</th></tr>")?;
+            let rows = c.added.iter().map(|r| ("added", r)).chain(c.removed.iter().map(|r| ("removed", r)));
+            for (change, r) in rows {
+                let (id, name, sequence) = (escape(&r.id), escape(&r.name), escape(&r.sequence));
+                writeln!(w, "<tr class=\"failed\"><td>{change}</td><td>{id}</td><td>{name}</td><td>{sequence}</td></tr>")?;
+            }
+            for (b, r) in &c.renamed {
+                writeln!(
+                    w,
+                    "<tr class=\"failed\"><td>renamed</td><td>{} to {}</td><td>{} to {}</td><td>{}</td></tr>",
+                    escape(&b.id),
+                    escape(&r.id),
+                    escape(&b.name),
+                    escape(&r.name),
+                    escape(&r.sequence)
+                )?;
+            }
+            writeln!(w, "</table>")?;
+        }
+        for c in self.replicates() {
+            let (a, b) = (escape(&self.samples[c.a].name), escape(&self.samples[c.b].name));
+            writeln!(w, "<p>Replicates {a} and {b}: correlation {:.3}</p>", c.correlation)?;
+            if !c.discordant.is_empty() {
+                writeln!(w, "<table>\n<tr><th>discordant feature</th><th>log2 {a}/{b}</th></tr>")?;
+                for (i, ratio) in c.discordant {
+                    writeln!(w, "<tr class=\"failed\"><td>{}</td><td>{ratio:+.2}</td></tr>", escape(&self.features[i].1))?;
+                }
+                writeln!(w, "</table>")?;
+            }
+        }
+        let titration = self.titration();
+        if let Some(first) = titration.first() {
+            write!(w, "<h2>Titration</h2>\n<table>\n<tr><th>name</th>")?;
+            for h in self.titration_header(first) {
+                write!(w, "<th>{}</th>", escape(&h))?;
+            }
+            writeln!(w, "<th>slope</th></tr>")?;
+            for t in &titration {
+                write!(w, "<tr><td>{}</td>", escape(&self.features[t.feature].1))?;
+                for (_, n) in &t.points {
+                    write!(w, "<td>{n:.1}</td>")?;
+                }
+                writeln!(w, "<td>{:.2}</td></tr>", t.slope)?;
+            }
+            writeln!(w, "</table>")?;
+        }
+        writeln!(w, "</div>")?;
+
+        for (i, sample) in self.samples.iter().enumerate() {
+            let report = &sample.report;
+            writeln!(w, "<div class=\"page\" id=\"page{}\">", i + 1)?;
+            writeln!(w, "<p>{} reads, featureseek {}</p>", report.reads, escape(&report.version))?;
+            if !report.read_structure.is_empty() {
+                writeln!(w, "<pre>{}</pre>", escape(&report.read_structure))?;
+            }
+
+            let umis = report.features.iter().any(|f| f.umis.is_some());
+            write!(w, "<table>\n<tr><th>name</th><th>barcode</th><th>count</th><th>cells</th><th>reads/cell</th><th>rescued</th>")?;
+            if umis {
+                write!(w, "<th>UMIs</th><th>reads/UMI</th>")?;
+            }
+            writeln!(w, "</tr>")?;
+            for f in &report.features {
+                let rpc = f.reads_per_cell.map_or("NA".to_owned(), |r| r.to_string());
+                write!(
+                    w,
+                    "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{rpc}</td><td>{}</td>",
+                    passed_class(f.passed),
+                    escape(&f.name),
+                    escape(&f.barcode),
+                    f.count,
+                    f.cells,
+                    f.rescued
+                )?;
+                if umis {
+                    let rpu = f.reads_per_umi.map_or("NA".to_owned(), |r| format!("{r:.2}"));
+                    write!(w, "<td>{}</td><td>{rpu}</td>", f.umis.unwrap_or(0))?;
+                }
+                writeln!(w, "</tr>")?;
+            }
+            writeln!(w, "</table>")?;
+
+            writeln!(w, "<table>")?;
+            if let serde_json::Value::Object(counters) = serde_json::to_value(&report.counters)? {
+                for (name, value) in counters {
+                    writeln!(w, "<tr><th>{}</th><td>{value}</td></tr>", name.replace('_', " "))?;
+                }
+            }
+            writeln!(w, "</table>")?;
+
+            writeln!(w, "<ul>")?;
+            for input in &report.inputs {
+                writeln!(w, "<li><code>{}</code> {}</li>", input.sha256, escape(&input.path.display().to_string()))?;
+            }
+            writeln!(w, "</ul>\n</div>")?;
+        }
+
+        writeln!(w, "</div>\n</body>\n</html>")?;
+        Ok(())
+    }
+}
+
+/// Median of ratios size factors (as in DESeq2) of the feature counts per sample. The ratios
+/// are taken to the geometric mean of the features counted in all samples. Falls back to the
+/// total counts relative to their geometric mean when no feature was counted in all samples.
+pub fn size_factors(counts: &[Vec<usize>]) -> Vec<f64> {
+    let n_features = counts.first().map_or(0, |c| c.len());
+    let log_means: Vec<Option<f64>> = (0..n_features)
+        .map(|i| {
+            let mut sum = 0.0;
+            for c in counts {
+                if c[i] == 0 {
+                    return None;
+                }
+                sum += (c[i] as f64).ln();
+            }
+            Some(sum / counts.len() as f64)
+        })
+        .collect();
+
+    if log_means.iter().all(Option::is_none) {
+        let totals: Vec<f64> = counts.iter().map(|c| c.iter().sum::<usize>().max(1) as f64).collect();
+        let log_mean = totals.iter().map(|t| t.ln()).sum::<f64>() / totals.len() as f64;
+        return totals.iter().map(|t| (t.ln() - log_mean).exp()).collect();
+    }
+
+    counts
+        .iter()
+        .map(|c| {
+            let mut ratios: Vec<f64> = c
+                .iter()
+                .zip(&log_means)
+                .filter_map(|(&count, mean)| mean.map(|m| (count as f64).ln() - m))
+                .collect();
+            ratios.sort_unstable_by(f64::total_cmp);
+            let mid = ratios.len() / 2;
+            let median = if ratios.len().is_multiple_of(2) { (ratios[mid - 1] + ratios[mid]) / 2.0 } else { ratios[mid] };
+            median.exp()
+        })
+        .collect()
+}
+
+/// Least squares slope of y on x
+fn slope(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let sxy: f64 = x.iter().zip(y).map(|(x, y)| (x - mx) * (y - my)).sum();
+    let sxx: f64 = x.iter().map(|x| (x - mx).powi(2)).sum();
+    sxy / sxx
+}
+
+/// Pearson correlation coefficient, NaN when either has no variance
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in x.iter().zip(y) {
+        sxy += (x - mx) * (y - my);
+        sxx += (x - mx).powi(2);
+        syy += (y - my).powi(2);
+    }
+    sxy / (sxx * syy).sqrt()
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { padding: 0.2em 0.6em; text-align: right; border-bottom: 1px solid #ddd; }
+.tabs > input { display: none; }
+.tabs > label { display: inline-block; padding: 0.4em 1em; border: 1px solid #ccc; cursor: pointer; }
+.tabs > input:checked + label { background: #eee; }
+.page { display: none; }
+.passed { color: #060; }
+.failed { color: #a00; }";
+
+fn passed_class(passed: bool) -> &'static str {
+    if passed {
+        " class=\"passed\""
+    } else {
+        " class=\"failed\""
+    }
+}
+
+/// Escape text for use in HTML
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
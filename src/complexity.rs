@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+/// Multiples of the reads sequenced the distinct molecules are extrapolated to
+pub const DEPTHS: [f64; 3] = [2.0, 5.0, 10.0];
+
+/// Library complexity from the distinct molecules of the reads, with the Lander-Waterman model
+/// of reads sampling the molecules of the library uniformly
+#[derive(Serialize, Deserialize)]
+pub struct Complexity {
+    pub reads: usize,
+    /// Distinct (cell, barcode, UMI) molecules
+    pub molecules: usize,
+    /// Estimated distinct molecules in the library
+    pub library_size: f64,
+    /// (multiple of the reads, expected distinct molecules) at more sequencing
+    pub extrapolation: Vec<(f64, f64)>,
+}
+
+impl Complexity {
+    /// Estimate the library size from the reads and their distinct molecules. None without
+    /// duplicate reads, as the library size is then unbounded.
+    pub fn estimate(reads: usize, molecules: usize) -> Option<Self> {
+        if molecules == 0 || molecules >= reads {
+            return None;
+        }
+        let (n, u) = (reads as f64, molecules as f64);
+        let library_size = library_size(n, u);
+        let extrapolation = DEPTHS
+            .iter()
+            .map(|&k| (k, expected(library_size, k * n)))
+            .collect();
+        Some(Complexity {
+            reads,
+            molecules,
+            library_size,
+            extrapolation,
+        })
+    }
+
+    /// Fraction of the reads that are duplicates of an earlier read of the molecule
+    pub fn saturation(&self) -> f64 {
+        1.0 - self.molecules as f64 / self.reads as f64
+    }
+}
+
+/// Expected distinct molecules of n reads from a library of c molecules
+fn expected(c: f64, n: f64) -> f64 {
+    c * -(-n / c).exp_m1()
+}
+
+/// The library size with u expected distinct molecules in n reads, by bisection on the log
+/// scale. The expected molecules increase with the library size towards n.
+fn library_size(n: f64, u: f64) -> f64 {
+    let (mut lo, mut hi) = (u.ln(), u.ln());
+    while expected(hi.exp(), n) < u {
+        hi += std::f64::consts::LN_2;
+    }
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if expected(mid.exp(), n) < u {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    hi.exp()
+}
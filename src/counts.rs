@@ -1,32 +1,138 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
 use std::io::Write;
 use std::hash::Hash;
 
-use ahash::AHashMap as HashMap;
 use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 use cli_table::{
     format::{Border, Justify, Separator},
     Cell, Color, Style, Table, TableStruct,
 };
 
-use crate::barcodes::Barcodes;
-use crate::{CellCode, Barcode, BarcodeRef};
+use crate::barcodes::{Barcodes, Rename};
+use crate::cellcodes;
+use crate::checkpoint::{self, entries, option_entries, option_keys, Key};
+use crate::classify::{self, Classifier};
+use crate::complexity::Complexity;
+use crate::export::LongCount;
+use crate::hash::{BuildHasher, HashMap, HashSet};
+use crate::report;
+use crate::{barcode_bases, cell_name, gem_well, to_barcode, CellCode, Barcode, BarcodeRef, CompactRef, Umi, CCLENGTH, MAX_CCLENGTH};
+
+/// The single cell code all reads are counted to in bulk mode
+const BULK_CELL: CellCode = {
+    let mut cell = [0; MAX_CCLENGTH + 1];
+    let mut i = 0;
+    while i < CCLENGTH {
+        cell[i] = b'N';
+        i += 1;
+    }
+    cell
+};
+
+/// Which read pairs of a cell are duplicates of an earlier one
+#[derive(Clone, Copy, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dedup {
+    /// The same feature barcode and UMI
+    Codes,
+    /// The same read 1 and read 2 sequences
+    Reads,
+}
+
+/// The summed counts and number of cells per barcode
+type FeatureSummary = HashMap<BarcodeRef, (usize, usize)>;
 
 /// Count the barcode (usize references) per cellcode
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Counts {
-    cells: CellCounts<BarcodeRef>,
-    ignored: usize,
+    /// Ignore the cell codes and count the whole library as a single cell
+    bulk: bool,
+    /// The counts are distinct UMIs instead of reads
+    umis: bool,
+    cells: CellCounts<CompactRef>,
+    /// Reads per ignore list barcode
+    #[serde(with = "entries")]
+    ignored: HashMap<Barcode, usize>,
+    rejected: usize,
     multiple: usize,
-    nohit: usize,
+    no_exact_hit: usize,
+    no_approximate_hit: usize,
+    contains_n: usize,
+    /// Barcodes without a reference hit with a base below Q10
+    #[serde(default)]
+    below_quality: usize,
+    too_short: usize,
+    padded: usize,
+    no_anchor: usize,
+    no_bc_anchor: usize,
+    no_name_match: usize,
+    no_umi_name_match: usize,
+    trimmed: usize,
+    malformed: usize,
     not_whitelisted: usize,
+    evicted_cells: usize,
+    evicted_reads: usize,
+    collapsed_cells: usize,
+    collapsed_reads: usize,
+    capped_pairs: usize,
+    capped_reads: usize,
+    /// UMIs merged into a more frequent UMI of the same cell and barcode
+    corrected_umis: usize,
+    /// Read pairs dropped as duplicates of an earlier read pair of the same cell
+    duplicates: usize,
+    /// Reads not counted as the count of their (cell, barcode) pair or molecule reached the
+    /// 32 bit maximum, as a single abundant feature of a large bulk library
+    #[serde(default)]
+    saturated: usize,
+    /// Hashes of the codes or read pairs seen per cell, when removing duplicates, not kept in
+    /// a checkpoint as the hasher differs per run, so a run with --dedup is not resumed
+    #[serde(skip)]
+    seen: Option<(BuildHasher, HashMap<CellCode, HashSet<u64>>)>,
+    rescued: HashMap<BarcodeRef, usize>,
+    /// Reads found by scanning the whole feature barcode read, per shift from the barcode
+    /// position
+    scan_rescued: HashMap<isize, usize>,
+    /// Reads per cell and barcode gained by approximate matching, read scanning or collapsing
+    /// the cell codes, when tracked
+    lenient: Option<CellCounts<CompactRef>>,
+    /// Reads and distance per (barcode, read sequence) corrected by approximate matching,
+    /// when corrections are tracked
+    #[serde(with = "option_entries")]
+    corrections: Option<HashMap<(CompactRef, Barcode), (isize, usize)>>,
+    /// Ambiguous reads per (nearest barcode, other barcode within distance 2), when tracked
+    #[serde(with = "option_entries")]
+    bleed: Option<HashMap<(CompactRef, CompactRef), usize>>,
+    /// Reads per (cell, barcode, UMI) molecule, when UMIs are tracked
+    #[serde(with = "option_entries")]
+    molecules: Option<HashMap<(CellCode, CompactRef, Umi), u32>>,
+    distances: [usize; 3],
+    exact_neighbors: usize,
     unknown: CellCounts<Barcode>,
+    unknown_totals: BarcodeCounts<Barcode>,
+    /// The (cell, unknown barcode, UMI) molecules, when UMIs are tracked
+    #[serde(with = "option_keys")]
+    unknown_molecules: Option<HashSet<(CellCode, Barcode, Umi)>>,
+    unknown_evicted: usize,
+    unknown_evicted_reads: usize,
 }
 
-#[derive(Default)]
-struct BarcodeCounts<T>(HashMap<T, usize>);
+#[derive(Default, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Key", deserialize = "T: Key + Eq + Hash"))]
+struct BarcodeCounts<T>(#[serde(with = "entries")] HashMap<T, usize>);
 
-#[derive(Default)]
-pub struct CellCounts<T>(HashMap<CellCode, BarcodeCounts<T>>);
+/// Counts of the barcodes of a single cell, sorted by barcode. Most cells have only a few
+/// barcodes, so a small sorted vector takes far less memory than a map.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(bound = "T: Key")]
+struct SparseCounts<T>(#[serde(with = "checkpoint::counts")] SmallVec<[(T, u32); 4]>);
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(bound = "T: Key")]
+pub struct CellCounts<T>(#[serde(with = "entries")] HashMap<CellCode, SparseCounts<T>>);
 
 pub struct Summary<'a> {
     barcodes: &'a Barcodes,
@@ -34,22 +140,248 @@ pub struct Summary<'a> {
 }
 
 impl Counts {
+    /// Counts ignoring the cell codes
+    pub fn bulk() -> Self {
+        Counts {
+            bulk: true,
+            ..Default::default()
+        }
+    }
+
+    /// Also track the reads per (cell, barcode, UMI) molecule, and the unknown molecules
+    pub fn with_umis(self) -> Self {
+        Counts {
+            molecules: Some(HashMap::default()),
+            unknown_molecules: Some(HashSet::default()),
+            ..self
+        }
+    }
+
+    /// Also track the read sequences corrected into each barcode
+    pub fn with_corrections(self) -> Self {
+        Counts {
+            corrections: Some(HashMap::default()),
+            ..self
+        }
+    }
+
+    /// Also track the reads per cell and barcode gained by the lenient settings
+    pub fn with_lenient(self) -> Self {
+        Counts {
+            lenient: Some(CellCounts::default()),
+            ..self
+        }
+    }
+
+    /// Drop the read pairs duplicating an earlier read pair of the same cell
+    pub fn with_dedup(self) -> Self {
+        Counts {
+            seen: Some((BuildHasher::default(), HashMap::default())),
+            ..self
+        }
+    }
+
+    /// Also track the barcodes of the ambiguous reads
+    pub fn with_bleed(self) -> Self {
+        Counts {
+            bleed: Some(HashMap::default()),
+            ..self
+        }
+    }
+
+    /// Whether the read pair duplicates an earlier read pair of the cell: the same read pair
+    /// when the hash of its sequences is given, else the same feature barcode and UMI. The
+    /// duplicates are counted, false when duplicates are not removed.
+    pub fn is_duplicate(&mut self, cellcode: CellCode, bc: &Barcode, umi: Option<Umi>, pair: Option<u64>) -> bool {
+        let cellcode = if self.bulk { BULK_CELL } else { cellcode };
+        let Some((hasher, seen)) = &mut self.seen else {
+            return false;
+        };
+        let key = pair.unwrap_or_else(|| std::hash::BuildHasher::hash_one(hasher, (bc, umi)));
+        let duplicate = !seen.entry(cellcode).or_default().insert(key);
+        self.duplicates += duplicate as usize;
+        duplicate
+    }
+
+    /// Count the UMI of an accepted read
+    pub fn count_molecule(&mut self, cellcode: CellCode, pos: usize, umi: Umi) {
+        let cellcode = if self.bulk { BULK_CELL } else { cellcode };
+        if let Some(molecules) = &mut self.molecules {
+            self.saturated += saturating_add(molecules.entry((cellcode, pos as CompactRef, umi)).or_default(), 1);
+        }
+    }
+
     pub fn count_barcode(&mut self, cellcode: CellCode, pos: usize) {
+        let cellcode = if self.bulk { BULK_CELL } else { cellcode };
         let cell = self.cells.0.entry(cellcode).or_default();
-        cell.count(pos);
+        self.saturated += cell.count(pos as CompactRef);
     }
 
-    pub fn count_unknown(&mut self, cellcode: CellCode, barcode: Barcode) {
+    /// Count a barcode that was only found by approximate matching
+    pub fn count_rescued(&mut self, cellcode: CellCode, pos: usize) {
+        self.count_barcode(cellcode, pos);
+        self.count_lenient(cellcode, pos);
+        *self.rescued.entry(pos).or_default() += 1;
+    }
+
+    /// Count a barcode found by scanning the read, shifted from its position
+    pub fn count_scan_rescued(&mut self, cellcode: CellCode, pos: usize, shift: isize) {
+        self.count_barcode(cellcode, pos);
+        self.count_lenient(cellcode, pos);
+        *self.scan_rescued.entry(shift).or_default() += 1;
+    }
+
+    fn count_lenient(&mut self, cellcode: CellCode, pos: usize) {
+        let cellcode = if self.bulk { BULK_CELL } else { cellcode };
+        if let Some(lenient) = &mut self.lenient {
+            lenient.0.entry(cellcode).or_default().count(pos as CompactRef);
+        }
+    }
+
+    /// Record the read sequence of a barcode found by approximate matching
+    pub fn count_correction(&mut self, pos: usize, barcode: Barcode, dist: isize) {
+        if let Some(corrections) = &mut self.corrections {
+            corrections.entry((pos as CompactRef, barcode)).or_insert((dist, 0)).1 += 1;
+        }
+    }
+
+    /// Record the reference hits of an ambiguous read: when a single barcode is nearest, the
+    /// read counts as bleed from it into each of the other barcodes
+    pub fn count_bleed(&mut self, hits: &[(usize, isize)]) {
+        let Some(bleed) = &mut self.bleed else {
+            return;
+        };
+        let Some(&(nearest, d)) = hits.iter().min_by_key(|&&(_, d)| d) else {
+            return;
+        };
+        if hits.iter().filter(|&&(_, dist)| dist == d).count() > 1 {
+            return;
+        }
+        for &(other, _) in hits.iter().filter(|&&(other, _)| other != nearest) {
+            *bleed.entry((nearest as CompactRef, other as CompactRef)).or_default() += 1;
+        }
+    }
+
+    /// Count an unknown barcode, and its molecule when UMIs are tracked. At most max_unknown
+    /// distinct barcodes are tracked, when exceeded the least frequent barcodes are evicted.
+    pub fn count_unknown(
+        &mut self,
+        cellcode: CellCode,
+        barcode: Barcode,
+        umi: Option<Umi>,
+        max_unknown: usize,
+    ) {
+        let cellcode = if self.bulk { BULK_CELL } else { cellcode };
         let cell = self.unknown.0.entry(cellcode).or_default();
         cell.count(barcode);
+        self.unknown_totals.count(barcode);
+        if let (Some(molecules), Some(umi)) = (&mut self.unknown_molecules, umi) {
+            molecules.insert((cellcode, barcode, umi));
+        }
+
+        if self.unknown_totals.0.len() > max_unknown {
+            let keep = max_unknown - max_unknown / 10;
+            let evicted = self.unknown_totals.evict(keep);
+            self.unknown_evicted += evicted.len();
+            self.unknown_evicted_reads += evicted.values().sum::<usize>();
+            self.unknown.remove(&evicted);
+            if let Some(molecules) = &mut self.unknown_molecules {
+                molecules.retain(|(_, barcode, _)| !evicted.contains_key(barcode));
+            }
+        }
+    }
+
+    /// Record the match distance of an accepted read
+    pub fn count_distance(&mut self, dist: isize) {
+        self.distances[dist as usize] += 1;
+    }
+
+    /// Count an exact match having other reference barcodes within the search distance
+    pub fn exact_neighbors(&mut self) {
+        self.exact_neighbors += 1;
     }
 
-    pub fn ignored(&mut self) {
-        self.ignored += 1;
+    /// Report the reads of these ignore list barcodes, also when none are found
+    pub fn with_ignores<'a, I: IntoIterator<Item = &'a [u8]>>(self, ignore: I) -> Self {
+        let ignored = ignore
+            .into_iter()
+            .filter_map(|barcode| Some((to_barcode(barcode)?, 0)))
+            .collect();
+        Counts { ignored, ..self }
     }
 
-    pub fn nohit(&mut self) {
-        self.nohit += 1;
+    /// Count a read of an ignore list barcode
+    pub fn ignored(&mut self, barcode: Barcode) {
+        *self.ignored.entry(barcode).or_default() += 1;
+    }
+
+    /// The reads per ignore list barcode, sorted by reads
+    fn ignored_barcodes(&self) -> Vec<(String, usize)> {
+        let mut ignored: Vec<_> = self
+            .ignored
+            .iter()
+            .map(|(barcode, &reads)| (String::from_utf8_lossy(barcode_bases(barcode)).into_owned(), reads))
+            .collect();
+        ignored.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ignored
+    }
+
+    /// Count a read rejected by a motif
+    pub fn rejected(&mut self) {
+        self.rejected += 1;
+    }
+
+    /// Count a barcode without a reference hit, low_quality when it has a base below Q10
+    pub fn nohit(&mut self, barcode: &Barcode, low_quality: bool, approximate: bool) {
+        if barcode.contains(&b'N') {
+            self.contains_n += 1;
+        } else if low_quality {
+            self.below_quality += 1;
+        } else if approximate {
+            self.no_approximate_hit += 1;
+        } else {
+            self.no_exact_hit += 1;
+        }
+    }
+
+    /// Count a read pair too short to contain the codes
+    pub fn too_short(&mut self) {
+        self.too_short += 1;
+    }
+
+    /// Count a read pair too short to contain the codes, padded with N
+    pub fn padded(&mut self) {
+        self.padded += 1;
+    }
+
+    /// Count a read pair without the cell code anchor
+    pub fn no_anchor(&mut self) {
+        self.no_anchor += 1;
+    }
+
+    /// Count a read pair without the feature barcode anchor
+    pub fn no_bc_anchor(&mut self) {
+        self.no_bc_anchor += 1;
+    }
+
+    /// Count a read pair without a cell code in the read name
+    pub fn no_name_match(&mut self) {
+        self.no_name_match += 1;
+    }
+
+    /// Count a read pair without a UMI in the read name
+    pub fn no_umi_name_match(&mut self) {
+        self.no_umi_name_match += 1;
+    }
+
+    /// Count a read pair with read 2 trimmed at an adapter before the feature barcode end
+    pub fn trimmed(&mut self) {
+        self.trimmed += 1;
+    }
+
+    /// Count a read pair with a malformed FastQ record
+    pub fn malformed(&mut self) {
+        self.malformed += 1;
     }
 
     pub fn multiple(&mut self) {
@@ -59,6 +391,329 @@ impl Counts {
     pub fn not_whitelisted(&mut self) {
         self.not_whitelisted += 1;
     }
+
+    /// Merge the counts of another run into these counts. The per cell counts, unknown
+    /// barcodes and all category counters are summed.
+    pub fn merge(&mut self, other: Counts) {
+        self.bulk |= other.bulk;
+        self.umis |= other.umis;
+        self.saturated += other.saturated + self.cells.merge(other.cells);
+        if let Some(other) = other.lenient {
+            self.lenient.get_or_insert_with(CellCounts::default).merge(other);
+        }
+        for (barcode, count) in other.ignored {
+            *self.ignored.entry(barcode).or_default() += count;
+        }
+        self.rejected += other.rejected;
+        self.multiple += other.multiple;
+        self.no_exact_hit += other.no_exact_hit;
+        self.no_approximate_hit += other.no_approximate_hit;
+        self.contains_n += other.contains_n;
+        self.below_quality += other.below_quality;
+        self.too_short += other.too_short;
+        self.padded += other.padded;
+        self.no_anchor += other.no_anchor;
+        self.no_bc_anchor += other.no_bc_anchor;
+        self.no_name_match += other.no_name_match;
+        self.no_umi_name_match += other.no_umi_name_match;
+        self.trimmed += other.trimmed;
+        self.malformed += other.malformed;
+        self.not_whitelisted += other.not_whitelisted;
+        self.evicted_cells += other.evicted_cells;
+        self.evicted_reads += other.evicted_reads;
+        self.collapsed_cells += other.collapsed_cells;
+        self.collapsed_reads += other.collapsed_reads;
+        self.capped_pairs += other.capped_pairs;
+        self.capped_reads += other.capped_reads;
+        self.corrected_umis += other.corrected_umis;
+        for (pos, count) in other.rescued {
+            *self.rescued.entry(pos).or_default() += count;
+        }
+        for (shift, count) in other.scan_rescued {
+            *self.scan_rescued.entry(shift).or_default() += count;
+        }
+        for (d, count) in self.distances.iter_mut().zip(other.distances) {
+            *d += count;
+        }
+        self.exact_neighbors += other.exact_neighbors;
+        if let Some(other) = other.bleed {
+            let bleed = self.bleed.get_or_insert_with(HashMap::default);
+            for (pair, count) in other {
+                *bleed.entry(pair).or_default() += count;
+            }
+        }
+        if let Some(other) = other.corrections {
+            let corrections = self.corrections.get_or_insert_with(HashMap::default);
+            for (key, (dist, count)) in other {
+                corrections.entry(key).or_insert((dist, 0)).1 += count;
+            }
+        }
+        if let Some(other) = other.molecules {
+            let molecules = self.molecules.get_or_insert_with(HashMap::default);
+            for (molecule, count) in other {
+                self.saturated += saturating_add(molecules.entry(molecule).or_default(), count);
+            }
+        }
+        self.unknown.merge(other.unknown);
+        if let Some(other) = other.unknown_molecules {
+            self.unknown_molecules.get_or_insert_with(HashSet::default).extend(other);
+        }
+        self.unknown_totals.merge(other.unknown_totals);
+        self.duplicates += other.duplicates;
+        self.unknown_evicted += other.unknown_evicted;
+        self.unknown_evicted_reads += other.unknown_evicted_reads;
+    }
+
+    /// The per category read counters
+    pub fn counters(&self) -> report::Counters {
+        report::Counters {
+            ignored: self.ignored.values().sum(),
+            ignored_barcodes: self.ignored_barcodes().into_iter().collect(),
+            rejected: self.rejected,
+            no_exact_hit: self.no_exact_hit,
+            no_approximate_hit: self.no_approximate_hit,
+            multiple: self.multiple,
+            contains_n: self.contains_n,
+            below_quality: self.below_quality,
+            too_short: self.too_short,
+            padded: self.padded,
+            no_anchor: self.no_anchor,
+            no_bc_anchor: self.no_bc_anchor,
+            no_name_match: self.no_name_match,
+            no_umi_name_match: self.no_umi_name_match,
+            trimmed: self.trimmed,
+            malformed: self.malformed,
+            not_whitelisted: self.not_whitelisted,
+            evicted_cells: self.evicted_cells,
+            evicted_reads: self.evicted_reads,
+            collapsed_cells: self.collapsed_cells,
+            capped: self.capped_reads,
+            collapsed_reads: self.collapsed_reads,
+            corrected_umis: self.corrected_umis,
+            duplicates: self.duplicates,
+            saturated: self.saturated,
+            scan_rescued: self.scan_rescued.iter().map(|(&shift, &reads)| (shift, reads)).collect(),
+        }
+    }
+
+    /// The summed counts and number of cells per barcode, over the cells above min_reads
+    fn cell_summary(&self, min_reads: usize) -> HashMap<BarcodeRef, (usize, usize)> {
+        self.cells
+            .summary(min_reads)
+            .into_iter()
+            .map(|(&pos, c)| (pos as BarcodeRef, c))
+            .collect()
+    }
+
+    /// The summed counts and number of cells per barcode over the cells above min_reads
+    /// without the reads gained by the lenient settings, and the number of cells with a
+    /// barcode above min_reads without them. None when these reads are not tracked.
+    fn strict_summary(&self, min_reads: usize) -> Option<(FeatureSummary, usize)> {
+        let lenient = self.lenient.as_ref()?;
+        let mut summary = FeatureSummary::default();
+        let mut cells = 0;
+        for (cellcode, counter) in &self.cells.0 {
+            let gained = lenient.0.get(cellcode);
+            let mut accepted = false;
+            for (&pos, count) in counter.iter() {
+                let strict = count.saturating_sub(gained.map_or(0, |g| g.get(pos)));
+                if strict > min_reads {
+                    let c = summary.entry(pos as BarcodeRef).or_default();
+                    c.0 += strict;
+                    c.1 += 1;
+                    accepted = true;
+                }
+            }
+            cells += accepted as usize;
+        }
+        Some((summary, cells))
+    }
+
+    /// The total barcode reads of each cell code
+    pub fn cell_totals(&self) -> HashMap<CellCode, usize> {
+        self.cells.0.iter().map(|(cellcode, counter)| (*cellcode, counter.total())).collect()
+    }
+
+    /// Number of reads counted to a barcode
+    pub fn counted(&self) -> usize {
+        self.cells.0.values().map(SparseCounts::total).sum()
+    }
+
+    /// Number of distinct cell codes with a barcode count
+    pub fn n_cells(&self) -> usize {
+        self.cells.0.len()
+    }
+
+    /// Evict the cell codes with the lowest read counts until at most keep cells remain
+    pub fn evict_cells(&mut self, keep: usize) {
+        let (cells, reads) = self.cells.evict(keep);
+        self.evicted_cells += cells;
+        self.evicted_reads += reads;
+        if let Some(molecules) = &mut self.molecules {
+            molecules.retain(|(cellcode, _, _), _| self.cells.0.contains_key(cellcode));
+        }
+        // the rescued reads of the evicted cells are among their lenient reads, which also
+        // include the reads found by scanning read 2
+        if let Some(lenient) = &mut self.lenient {
+            lenient.0.retain(|cellcode, gained| {
+                let kept = self.cells.0.contains_key(cellcode);
+                if !kept {
+                    for &(pos, reads) in gained.0.iter() {
+                        if let Some(rescued) = self.rescued.get_mut(&(pos as BarcodeRef)) {
+                            *rescued = rescued.saturating_sub(reads as usize);
+                        }
+                    }
+                }
+                kept
+            });
+        }
+        if let Some((_, seen)) = &mut self.seen {
+            seen.retain(|cellcode, _| self.cells.0.contains_key(cellcode));
+        }
+    }
+
+    /// Merge the counts of the likely error cell codes into their root cell code
+    pub fn collapse_cells(&mut self, parents: &HashMap<CellCode, CellCode>) {
+        for cellcode in parents.keys() {
+            if let Some(counter) = self.cells.0.remove(cellcode) {
+                self.collapsed_cells += 1;
+                self.collapsed_reads += counter.total();
+                let root = cellcodes::root(parents, cellcode);
+                // all reads of a collapsed code are gained by the root
+                if let Some(lenient) = &mut self.lenient {
+                    lenient.0.remove(cellcode);
+                    lenient.0.entry(root).or_default().merge(SparseCounts(counter.0.clone()));
+                }
+                self.saturated += self.cells.0.entry(root).or_default().merge(counter);
+            }
+        }
+
+        if let Some(molecules) = &mut self.molecules {
+            let collapsed: Vec<_> = molecules
+                .keys()
+                .filter(|(cellcode, _, _)| parents.contains_key(cellcode))
+                .copied()
+                .collect();
+            for key @ (cellcode, pos, umi) in collapsed {
+                let reads = molecules.remove(&key).unwrap();
+                let root = molecules.entry((cellcodes::root(parents, &cellcode), pos, umi)).or_default();
+                self.saturated += saturating_add(root, reads);
+            }
+        }
+    }
+
+    /// Limit the reads of each (cell, barcode) to max, to damp PCR jackpots
+    pub fn cap(&mut self, max: u32) {
+        for counter in self.cells.0.values_mut() {
+            for (_, count) in counter.0.iter_mut().filter(|(_, count)| *count > max) {
+                self.capped_pairs += 1;
+                self.capped_reads += (*count - max) as usize;
+                *count = max;
+            }
+        }
+    }
+
+    /// Merge the UMIs of each cell and barcode within one mismatch of a UMI with at least twice
+    /// their reads minus one into that UMI, following these edges from the most frequent UMIs
+    /// as the directional method of UMI-tools. The reads of the merged UMIs are added to the
+    /// UMI they are merged into.
+    pub fn correct_umis(&mut self) {
+        let Some(molecules) = self.molecules.take() else {
+            return;
+        };
+        let mut umis: HashMap<(CellCode, CompactRef), Vec<(Umi, u32)>> = HashMap::default();
+        for ((cellcode, pos, umi), reads) in molecules {
+            umis.entry((cellcode, pos)).or_default().push((umi, reads));
+        }
+
+        let mut corrected = HashMap::default();
+        for ((cellcode, pos), mut umis) in umis {
+            umis.sort_unstable_by_key(|&(umi, reads)| (std::cmp::Reverse(reads), umi));
+            let index: HashMap<Umi, usize> = umis.iter().enumerate().map(|(i, &(umi, _))| (umi, i)).collect();
+            let mut root = vec![None; umis.len()];
+            for i in 0..umis.len() {
+                if root[i].is_some() {
+                    continue;
+                }
+                root[i] = Some(i);
+                let mut stack = vec![i];
+                while let Some(j) = stack.pop() {
+                    let (umi, reads) = umis[j];
+                    for k in umi_neighbors(&umi).filter_map(|n| index.get(&n).copied()) {
+                        if root[k].is_none() && reads + 1 >= 2 * umis[k].1 {
+                            root[k] = Some(i);
+                            stack.push(k);
+                        }
+                    }
+                }
+            }
+            for (&(_, reads), r) in umis.iter().zip(root) {
+                let (umi, _) = umis[r.unwrap()];
+                self.saturated += saturating_add(corrected.entry((cellcode, pos, umi)).or_default(), reads);
+            }
+            self.corrected_umis += umis.len();
+        }
+        self.corrected_umis -= corrected.len();
+        self.molecules = Some(corrected);
+    }
+
+    /// Replace the read counts of each cell and barcode by their distinct UMIs. The reads gained
+    /// by the lenient settings are no longer comparable and dropped. Needs tracked UMIs.
+    pub fn count_umis(&mut self) {
+        let Some(molecules) = &self.molecules else {
+            return;
+        };
+        let mut cells = CellCounts::default();
+        for &(cellcode, pos, _) in molecules.keys() {
+            cells.0.entry(cellcode).or_default().count(pos);
+        }
+        self.cells = cells;
+        self.lenient = None;
+        self.umis = true;
+    }
+
+    /// Whether the counts are distinct UMIs instead of reads
+    pub fn counts_umis(&self) -> bool {
+        self.umis
+    }
+
+    /// The library complexity from the reads and distinct molecules of all barcodes, None when
+    /// UMIs are not tracked or no read was a duplicate
+    pub fn complexity(&self) -> Option<Complexity> {
+        let molecules = self.molecules.as_ref()?;
+        let reads = molecules.values().map(|&r| r as usize).sum();
+        Complexity::estimate(reads, molecules.len())
+    }
+
+    /// The cells with a barcode above min_reads and the counts of each GEM well, by well, empty
+    /// when the cell codes have no GEM well
+    pub fn gem_wells(&self, min_reads: usize) -> Vec<report::GemWell> {
+        let mut wells: BTreeMap<u8, (usize, usize)> = BTreeMap::new();
+        for (cellcode, counter) in &self.cells.0 {
+            let well = wells.entry(gem_well(cellcode)).or_default();
+            well.0 += counter.filter_hits(min_reads).next().is_some() as usize;
+            well.1 += counter.total();
+        }
+        if wells.keys().all(|&well| well == 0) {
+            return Vec::new();
+        }
+        wells
+            .into_iter()
+            .map(|(well, (cells, reads))| report::GemWell { well, cells, reads })
+            .collect()
+    }
+
+    /// The reads and distinct UMIs per barcode, None when UMIs are not tracked
+    fn umi_summary(&self) -> Option<HashMap<BarcodeRef, (usize, usize)>> {
+        let molecules = self.molecules.as_ref()?;
+        let mut result: HashMap<BarcodeRef, (usize, usize)> = HashMap::default();
+        for (&(_, pos, _), &reads) in molecules {
+            let c = result.entry(pos as BarcodeRef).or_default();
+            c.0 += reads as usize;
+            c.1 += 1;
+        }
+        Some(result)
+    }
 }
 
 impl<T> BarcodeCounts<T> where T: Eq + Hash {
@@ -72,24 +727,82 @@ impl<T> BarcodeCounts<T> where T: Eq + Hash {
         }
     }
 
-    /// Filter the barcodes to those having more than min_reads counts
-    pub fn filter_hits(&self, min_reads: usize) -> impl Iterator<Item=(&T, usize)> {
+    /// Add the counts of another counter
+    fn merge(&mut self, other: BarcodeCounts<T>) {
+        for (id, count) in other.0 {
+            *self.0.entry(id).or_default() += count;
+        }
+    }
+
+    /// Remove the barcodes with the lowest counts, keeping at most keep barcodes. Of the
+    /// barcodes with equal counts at the limit the lowest barcodes are kept.
+    /// Returns the removed barcodes with their counts.
+    fn evict(&mut self, keep: usize) -> HashMap<T, usize>
+    where
+        T: Ord,
+    {
+        if self.0.len() <= keep {
+            return HashMap::default();
+        }
+
+        let mut counts: Vec<_> = std::mem::take(&mut self.0).into_iter().collect();
+        counts.select_nth_unstable_by(keep, |a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let evicted = counts.split_off(keep).into_iter().collect();
+        self.0 = counts.into_iter().collect();
+        evicted
+    }
+}
+
+impl<T> SparseCounts<T> where T: Ord + Copy {
+    /// Add a count for the provided barcode. The count not added at the 32 bit maximum.
+    fn count(&mut self, id: T) -> usize {
+        self.add(id, 1)
+    }
+
+    /// Add to the count of a barcode, saturating at the 32 bit maximum. The count not added.
+    fn add(&mut self, id: T, count: u32) -> usize {
+        match self.0.binary_search_by_key(&id, |&(id, _)| id) {
+            Ok(i) => saturating_add(&mut self.0[i].1, count),
+            Err(i) => {
+                self.0.insert(i, (id, count));
+                0
+            }
+        }
+    }
+
+    /// Add the counts of another counter. The counts not added at the 32 bit maximum.
+    fn merge(&mut self, other: SparseCounts<T>) -> usize {
+        other.0.into_iter().map(|(id, count)| self.add(id, count)).sum()
+    }
+
+    /// The count of a barcode, 0 when not found
+    fn get(&self, id: T) -> usize {
         self.0
-            .iter()
-            .filter_map(move |(id, count)| {
-                if *count > min_reads {
-                    Some((id, *count))
-                } else {
-                    None
-                }
-            })
+            .binary_search_by_key(&id, |&(id, _)| id)
+            .map_or(0, |i| self.0[i].1 as usize)
+    }
+
+    /// Total counts over all barcodes
+    fn total(&self) -> usize {
+        self.0.iter().map(|&(_, count)| count as usize).sum()
+    }
+
+    /// The barcodes and their counts, sorted by barcode
+    fn iter(&self) -> impl Iterator<Item = (&T, usize)> {
+        self.0.iter().map(|(id, count)| (id, *count as usize))
+    }
+
+    /// Filter the barcodes to those having more than min_reads counts
+    fn filter_hits(&self, min_reads: usize) -> impl Iterator<Item = (&T, usize)> {
+        self.iter().filter(move |&(_, count)| count > min_reads)
     }
 }
 
-impl<T> CellCounts<T> where T: Eq + Hash {
+impl<T> CellCounts<T> where T: Ord + Copy + Hash {
     /// Return a flattened map of barcode ids and their barcode and cell counts
     fn summary(&self, min_reads: usize) -> HashMap<&T, (usize, usize)> {
-        let mut result = HashMap::new();
+        let mut result = HashMap::default();
         self.0.values()
             .flat_map(|counter| counter.filter_hits(min_reads))
             .for_each(|(id, count)| {
@@ -100,6 +813,46 @@ impl<T> CellCounts<T> where T: Eq + Hash {
 
         result
     }
+
+    /// Add the counts of another set of cells. The counts not added at the 32 bit maximum.
+    fn merge(&mut self, other: CellCounts<T>) -> usize {
+        let mut saturated = 0;
+        for (cellcode, counter) in other.0 {
+            match self.0.entry(cellcode) {
+                Entry::Occupied(mut e) => saturated += e.get_mut().merge(counter),
+                Entry::Vacant(e) => {
+                    e.insert(counter);
+                }
+            }
+        }
+        saturated
+    }
+
+    /// Remove the provided barcodes from all cells
+    fn remove<V>(&mut self, barcodes: &HashMap<T, V>) {
+        self.0.retain(|_, counter| {
+            counter.0.retain(|(barcode, _)| !barcodes.contains_key(barcode));
+            !counter.0.is_empty()
+        });
+    }
+
+    /// Remove the cells with the lowest total counts, keeping at most keep cells. Of the cells
+    /// with equal totals at the limit those with the lowest cell codes are kept.
+    /// Returns the number of removed cells and their reads.
+    fn evict(&mut self, keep: usize) -> (usize, usize) {
+        if self.0.len() <= keep {
+            return (0, 0);
+        }
+
+        let mut totals: Vec<_> = self.0.iter().map(|(cellcode, c)| (c.total(), *cellcode)).collect();
+        totals.select_nth_unstable_by(keep, |a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        let evicted = &totals[keep..];
+        for (_, cellcode) in evicted {
+            self.0.remove(cellcode);
+        }
+        (evicted.len(), evicted.iter().map(|&(total, _)| total).sum())
+    }
 }
 
 impl<'a> Summary<'a> {
@@ -123,10 +876,82 @@ impl<'a> Summary<'a> {
         print!("{}", table.display().unwrap());
         let cl: &str = termion::clear::AfterCursor.as_ref();
 
+        let c = self.counts;
+        let mut ignored = c.ignored.values().sum::<usize>().to_string();
+        if c.ignored.len() > 1 {
+            let per_barcode: Vec<_> =
+                c.ignored_barcodes().iter().map(|(barcode, reads)| format!("{barcode}: {reads}")).collect();
+            ignored += &format!(" ({})", per_barcode.join(", "));
+        }
         println!(
-            "{cl}\nIgnored: {}{cl}\nNo barcode hit: {}{cl}\nMultiple barcode hits: {}{cl}\nCellcodes not whitelisted: {}{cl}",
-            self.counts.ignored, self.counts.nohit, self.counts.multiple, self.counts.not_whitelisted
+            "{cl}\nIgnored: {}{cl}\nNo exact barcode hit: {}{cl}\nNo approximate barcode hit: {}{cl}\nAmbiguous approximate barcode hits: {}{cl}\nBarcodes containing N: {}{cl}\nBarcodes below quality: {}{cl}\nReads too short: {}{cl}\nCellcodes not whitelisted: {}{cl}",
+            ignored, c.no_exact_hit, c.no_approximate_hit, c.multiple, c.contains_n, c.below_quality, c.too_short, c.not_whitelisted
         );
+        if self.counts.rejected > 0 {
+            println!("Rejected by motif: {}{cl}", self.counts.rejected);
+        }
+        if self.counts.padded > 0 {
+            println!("Reads padded with N: {}{cl}", self.counts.padded);
+        }
+        if self.counts.no_anchor > 0 {
+            println!("Cell code anchor not found: {}{cl}", self.counts.no_anchor);
+        }
+        if self.counts.no_bc_anchor > 0 {
+            println!("Feature barcode anchor not found: {}{cl}", self.counts.no_bc_anchor);
+        }
+        if !c.scan_rescued.is_empty() {
+            let mut shifts: Vec<_> = c.scan_rescued.iter().collect();
+            shifts.sort_unstable_by_key(|&(&shift, &reads)| (std::cmp::Reverse(reads), shift));
+            let top: Vec<_> = shifts.iter().take(5).map(|(shift, reads)| format!("shift {shift:+}: {reads}")).collect();
+            println!(
+                "Rescued by scanning read 2: {} ({}){cl}",
+                c.scan_rescued.values().sum::<usize>(),
+                top.join(", ")
+            );
+        }
+        if self.counts.trimmed > 0 {
+            println!("Trimmed at an adapter: {}{cl}", self.counts.trimmed);
+        }
+        if self.counts.no_name_match > 0 {
+            println!("Cell code not found in the read name: {}{cl}", self.counts.no_name_match);
+        }
+        if self.counts.no_umi_name_match > 0 {
+            println!("UMI not found in the read name: {}{cl}", self.counts.no_umi_name_match);
+        }
+        if self.counts.malformed > 0 {
+            println!("Malformed records: {}{cl}", self.counts.malformed);
+        }
+        if self.counts.evicted_cells > 0 {
+            println!(
+                "Cellcodes evicted: {} ({} reads){cl}",
+                self.counts.evicted_cells, self.counts.evicted_reads
+            );
+        }
+        if self.counts.collapsed_cells > 0 {
+            println!(
+                "Cellcodes collapsed: {} ({} reads){cl}",
+                self.counts.collapsed_cells, self.counts.collapsed_reads
+            );
+        }
+        if self.counts.capped_pairs > 0 {
+            println!(
+                "Reads capped: {} (in {} cell, barcode pairs){cl}",
+                self.counts.capped_reads, self.counts.capped_pairs
+            );
+        }
+        if self.counts.saturated > 0 {
+            println!(
+                "WARNING: {} reads not counted as their cell, barcode count reached {}{cl}",
+                self.counts.saturated,
+                u32::MAX
+            );
+        }
+        if self.counts.corrected_umis > 0 {
+            println!("UMIs corrected: {}{cl}", self.counts.corrected_umis);
+        }
+        if self.counts.duplicates > 0 {
+            println!("Duplicate read pairs removed: {}{cl}", self.counts.duplicates);
+        }
     }
 
     pub fn gen_table(
@@ -135,9 +960,7 @@ impl<'a> Summary<'a> {
         min_cells: usize,
         reads_per_cell: Option<usize>,
     ) -> TableStruct {
-        let mut hits: Vec<_> = self
-            .counts.cells
-            .summary(min_reads)
+        let mut hits: Vec<_> = self.counts.cell_summary(min_reads)
             .into_iter()
             .map(|(pos, (count, cells))| (pos, count, cells))
             .collect();
@@ -146,7 +969,7 @@ impl<'a> Summary<'a> {
 
         let mut tabledata = Vec::new();
         for (pos, count, cells) in hits.into_iter().rev() {
-            let record = &self.barcodes.records[*pos];
+            let record = &self.barcodes.records[pos];
 
             let col = if passes(count, cells, min_reads, min_cells, reads_per_cell) {
                 Some(Color::Green)
@@ -154,69 +977,634 @@ impl<'a> Summary<'a> {
                 Some(Color::Red)
             };
 
-            tabledata.push(vec![
+            let mut row = vec![
                 record.get(1).unwrap().cell().foreground_color(col),
                 record.get(4).unwrap().cell().foreground_color(col),
                 count.cell().justify(Justify::Right),
-                cells.cell().justify(Justify::Right),
-                (count / cells).cell().justify(Justify::Right),
-            ]);
+            ];
+            if !self.counts.bulk {
+                row.push(cells.cell().justify(Justify::Right));
+                row.push(format_reads_per_cell(count, cells).cell().justify(Justify::Right));
+            }
+            tabledata.push(row);
+        }
+
+        let mut title = vec![
+            "name".cell(),
+            "barcode".cell(),
+            format!("{} (>{})", if self.counts.umis { "UMIs" } else { "count" }, min_reads).cell(),
+        ];
+        if !self.counts.bulk {
+            title.push(format!("cells (>{})", min_cells).cell());
+            title.push(format!("{}/cell{}", if self.counts.umis { "UMIs" } else { "reads" }, if let Some(rpc) = reads_per_cell { format!(" (>{})", rpc)} else { "".to_owned() }).cell());
         }
 
         tabledata
             .table()
-            .title(vec![
-                "name".cell(),
-                "barcode".cell(),
-                format!("count (>{})", min_reads).cell(),
-                format!("cells (>{})", min_cells).cell(),
-                format!("reads/cell{}", if let Some(rpc) = reads_per_cell { format!(" (>{})", rpc)} else { "".to_owned() }).cell(),
-            ])
+            .title(title)
             .border(Border::builder().build())
             .separator(Separator::builder().row(None).column(None).build())
     }
 
 
+    /// Print the most frequent unknown barcodes. When UMIs are tracked the barcodes are ranked
+    /// by their distinct molecules, so PCR duplicates of a single chimera do not stand out.
     pub fn print_unknown(&self, min_reads: usize) {
+        let umis = self.unknown_umis(min_reads);
         let mut hits: Vec<_> = self.counts.unknown.summary(min_reads)
             .into_iter()
-            .map(|(barcode, (count, cells))| (barcode, count, cells))
+            .map(|(barcode, (count, cells))| {
+                (barcode, count, cells, umis.as_ref().map(|u| u.get(barcode).copied().unwrap_or(0)))
+            })
             .collect();
-        hits.sort_by_key(|e| e.1);
+        hits.sort_by_key(|e| (e.3, e.1));
 
         let mut tabledata = Vec::new();
-        for (barcode, count, cells) in hits.iter().rev().take(20) {
-            tabledata.push(vec![
-                String::from_utf8_lossy(barcode.as_slice()).cell(),
+        for (barcode, count, cells, umis) in hits.iter().rev().take(20) {
+            let mut row = vec![
+                String::from_utf8_lossy(barcode_bases(barcode)).cell(),
                 count.cell().justify(Justify::Right),
                 cells.cell().justify(Justify::Right),
-                (count / cells).cell().justify(Justify::Right),
+                format_reads_per_cell(*count, *cells).cell().justify(Justify::Right),
+            ];
+            if let Some(umis) = umis {
+                row.push(umis.cell().justify(Justify::Right));
+            }
+            tabledata.push(row);
+        }
+
+        let mut title = vec![
+            "barcode".cell(),
+            format!("count (>{}/c)", min_reads).cell(),
+            "cells".cell(),
+            "reads/cell".cell(),
+        ];
+        if umis.is_some() {
+            title.push("UMIs".cell());
+        }
+        let table = tabledata
+            .table()
+            .title(title)
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+
+        println!("\nUnknown barcode summary ({} total):\n{}", hits.len(), table.display().unwrap());
+        if self.counts.unknown_evicted > 0 {
+            println!(
+                "Approximate: the least frequent unknown barcodes were evicted to cap memory use \
+                ({} barcodes, {} reads)",
+                self.counts.unknown_evicted, self.counts.unknown_evicted_reads
+            );
+        }
+
+    }
+
+    /// The distinct molecules of each unknown barcode, over the cells above min_reads. None
+    /// when UMIs are not tracked.
+    fn unknown_umis(&self, min_reads: usize) -> Option<HashMap<Barcode, usize>> {
+        let molecules = self.counts.unknown_molecules.as_ref()?;
+        let mut umis = HashMap::default();
+        for (cellcode, barcode, _) in molecules {
+            let reads = self.counts.unknown.0.get(cellcode).map_or(0, |cell| cell.get(*barcode));
+            if reads > min_reads {
+                *umis.entry(*barcode).or_default() += 1;
+            }
+        }
+        Some(umis)
+    }
+
+    /// Print the per feature reads gained by approximate matching
+    pub fn print_rescues(&self) {
+        let mut hits: Vec<_> = self.counts.cell_summary(0)
+            .into_iter()
+            .map(|(pos, (count, _cells))| {
+                let rescued = self.counts.rescued.get(&pos).copied().unwrap_or(0);
+                (pos, count.saturating_sub(rescued), rescued)
+            })
+            .collect();
+        hits.sort_by_key(|e| e.1 + e.2);
+
+        let mut tabledata = Vec::new();
+        for (pos, exact, rescued) in hits.into_iter().rev() {
+            let record = &self.barcodes.records[pos];
+            let gain = if exact > 0 {
+                format!("{:.2}%", 100.0 * rescued as f64 / exact as f64)
+            } else {
+                "NA".to_owned()
+            };
+
+            tabledata.push(vec![
+                record.get(1).unwrap().cell(),
+                exact.cell().justify(Justify::Right),
+                rescued.cell().justify(Justify::Right),
+                gain.cell().justify(Justify::Right),
+            ]);
+        }
+
+        let table = tabledata
+            .table()
+            .title(vec![
+                "name".cell(),
+                "exact".cell(),
+                "approximate".cell(),
+                "gain".cell(),
+            ])
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+
+        println!("\nApproximate matching rescues:\n{}", table.display().unwrap());
+    }
+
+    /// Print the cells and features above the thresholds only through the reads gained by
+    /// approximate matching, read scanning or collapsing the cell codes, next to the counts
+    /// without these reads
+    pub fn print_lenient(&self, min_reads: usize, min_cells: usize, reads_per_cell: Option<usize>) {
+        let Some((strict, strict_cells)) = self.counts.strict_summary(min_reads) else {
+            return;
+        };
+        let mut hits: Vec<_> = self.counts.cell_summary(min_reads).into_iter().collect();
+        hits.sort_by_key(|&(pos, (count, _))| (std::cmp::Reverse(count), pos));
+
+        let mut tabledata = Vec::new();
+        let mut lenient_only = Vec::new();
+        for (pos, (count, cells)) in hits {
+            let record = &self.barcodes.records[pos];
+            let (strict_count, strict_cells) = strict.get(&pos).copied().unwrap_or_default();
+            if passes(count, cells, min_reads, min_cells, reads_per_cell)
+                && !passes(strict_count, strict_cells, min_reads, min_cells, reads_per_cell)
+            {
+                lenient_only.push(record.get(1).unwrap());
+            }
+            tabledata.push(vec![
+                record.get(1).unwrap().cell(),
+                cells.cell().justify(Justify::Right),
+                strict_cells.cell().justify(Justify::Right),
+                (cells - strict_cells).cell().justify(Justify::Right),
             ]);
         }
 
         let table = tabledata
             .table()
             .title(vec![
-                "barcode".cell(),
-                format!("count (>{}/c)", min_reads).cell(),
+                "name".cell(),
                 "cells".cell(),
-                "reads/cell".cell(),
+                "strict".cell(),
+                "gained".cell(),
             ])
             .border(Border::builder().build())
             .separator(Separator::builder().row(None).column(None).build());
 
-        println!("\nUnknown barcode summary ({} total):\n{}", hits.len(), table.display().unwrap());
+        let cells = self.accepted_cells(min_reads).len();
+        println!(
+            "\nCells gained by the lenient settings: {} of {cells} cells with a barcode above the minimum reads\n{}",
+            cells - strict_cells,
+            table.display().unwrap()
+        );
+        if !lenient_only.is_empty() {
+            println!("Features passing only by the lenient settings: {}", lenient_only.join(", "));
+        }
+    }
 
+    /// Print the most frequent read sequences corrected into each barcode, to audit that the
+    /// corrections are sequencing errors rather than another barcode
+    pub fn print_corrections(&self, top: usize) {
+        let Some(corrections) = &self.counts.corrections else {
+            return;
+        };
+        let mut hits: Vec<_> = corrections
+            .iter()
+            .map(|(&(pos, seq), &(dist, reads))| (pos, seq, dist, reads))
+            .collect();
+        let rescued = |pos: CompactRef| self.counts.rescued.get(&(pos as BarcodeRef)).copied().unwrap_or(0);
+        hits.sort_by_key(|&(pos, seq, _, reads)| {
+            (std::cmp::Reverse(rescued(pos)), pos, std::cmp::Reverse(reads), seq)
+        });
+
+        let mut tabledata = Vec::new();
+        let mut listed = 0;
+        for (i, &(pos, seq, dist, reads)) in hits.iter().enumerate() {
+            listed = if i > 0 && hits[i - 1].0 == pos { listed + 1 } else { 1 };
+            if listed > top {
+                continue;
+            }
+            let record = &self.barcodes.records[pos as BarcodeRef];
+            tabledata.push(vec![
+                record.get(1).unwrap().cell(),
+                record.get(4).unwrap().cell(),
+                String::from_utf8_lossy(barcode_bases(&seq)).cell(),
+                dist.cell().justify(Justify::Right),
+                reads.cell().justify(Justify::Right),
+                format!("{:.2}%", 100.0 * reads as f64 / rescued(pos) as f64)
+                    .cell()
+                    .justify(Justify::Right),
+            ]);
+        }
+
+        let table = tabledata
+            .table()
+            .title(vec![
+                "name".cell(),
+                "reference".cell(),
+                "read".cell(),
+                "distance".cell(),
+                "reads".cell(),
+                "of corrected".cell(),
+            ])
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+
+        println!(
+            "\nApproximate matching corrections, top {top} read sequences per barcode:\n{}",
+            table.display().unwrap()
+        );
     }
 
-    pub fn write_csv<W: Write>(
+    /// Print the bleed through between nearby barcodes: the ambiguous reads nearest to one
+    /// barcode but also within distance 2 of another, relative to the reads of the nearest
+    /// barcode. Also lists the reference barcodes within distance 2 of each other.
+    pub fn print_bleed(&self) {
+        let Some(bleed) = &self.counts.bleed else {
+            return;
+        };
+        let totals = self.counts.cell_summary(0);
+        let mut pairs: HashMap<(BarcodeRef, BarcodeRef), usize> = bleed
+            .iter()
+            .map(|(&(from, to), &reads)| ((from as BarcodeRef, to as BarcodeRef), reads))
+            .collect();
+        for (a, b, _) in self.barcodes.near_pairs(2) {
+            pairs.entry((a, b)).or_default();
+            pairs.entry((b, a)).or_default();
+        }
+        let mut pairs: Vec<_> = pairs.into_iter().collect();
+        pairs.sort_by_key(|&(pair, reads)| (std::cmp::Reverse(reads), pair));
+
+        let name = |pos: BarcodeRef| self.barcodes.records[pos].get(1).unwrap().cell();
+        let mut tabledata = Vec::new();
+        for ((from, to), reads) in pairs {
+            let total = totals.get(&from).map_or(0, |&(count, _)| count);
+            let rate = if total > 0 {
+                format!("{:.3}%", 100.0 * reads as f64 / total as f64)
+            } else {
+                "NA".to_owned()
+            };
+            tabledata.push(vec![
+                name(from),
+                name(to),
+                self.barcodes.distance(from, to).cell().justify(Justify::Right),
+                reads.cell().justify(Justify::Right),
+                rate.cell().justify(Justify::Right),
+            ]);
+        }
+        if tabledata.is_empty() {
+            println!("\nBleed through: no ambiguous reads and no barcodes within distance 2");
+            return;
+        }
+
+        let table = tabledata
+            .table()
+            .title(vec![
+                "from".cell(),
+                "into".cell(),
+                "distance".cell(),
+                "reads".cell(),
+                "of from".cell(),
+            ])
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+
+        println!("\nBleed through between nearby barcodes:\n{}", table.display().unwrap());
+    }
+
+    /// Print the cells with a barcode above min_reads and the counts of each GEM well of an
+    /// aggregated library
+    pub fn print_gem_wells(&self, min_reads: usize) {
+        let wells = self.counts.gem_wells(min_reads);
+        if wells.is_empty() {
+            return;
+        }
+        let mut tabledata = Vec::new();
+        for w in wells {
+            tabledata.push(vec![
+                if w.well == 0 { "none".to_owned() } else { w.well.to_string() }.cell(),
+                w.cells.cell().justify(Justify::Right),
+                w.reads.cell().justify(Justify::Right),
+            ]);
+        }
+
+        let counted = if self.counts.counts_umis() { "UMIs" } else { "reads" };
+        let table = tabledata
+            .table()
+            .title(vec!["GEM well".cell(), format!("cells (>{min_reads})").cell(), counted.cell()])
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+
+        println!("\nPer GEM well:\n{}", table.display().unwrap());
+    }
+
+    /// Print the reads, distinct UMIs and reads per UMI of each barcode
+    pub fn print_umis(&self) {
+        let Some(umis) = self.counts.umi_summary() else {
+            return;
+        };
+        let mut hits: Vec<_> = umis.into_iter().collect();
+        hits.sort_by_key(|&(pos, (reads, _))| (std::cmp::Reverse(reads), pos));
+
+        let mut tabledata = Vec::new();
+        for (pos, (reads, umis)) in hits {
+            tabledata.push(vec![
+                self.barcodes.records[pos].get(1).unwrap().cell(),
+                reads.cell().justify(Justify::Right),
+                umis.cell().justify(Justify::Right),
+                format!("{:.2}", reads as f64 / umis as f64).cell().justify(Justify::Right),
+            ]);
+        }
+
+        let table = tabledata
+            .table()
+            .title(vec!["name".cell(), "reads".cell(), "UMIs".cell(), "reads/UMI".cell()])
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+
+        println!("\nUMI duplication:\n{}", table.display().unwrap());
+    }
+
+    /// Print the estimated library complexity and the distinct molecules expected with more
+    /// sequencing
+    pub fn print_complexity(&self) {
+        let Some(c) = self.counts.complexity() else {
+            return;
+        };
+        println!(
+            "\nLibrary complexity: {} molecules in {} reads, saturation {:.1}%, estimated {:.0} molecules",
+            c.molecules,
+            c.reads,
+            100.0 * c.saturation(),
+            c.library_size
+        );
+        let gains: Vec<_> = c
+            .extrapolation
+            .iter()
+            .map(|&(k, m)| format!("{k}x {m:.0} (+{:.1}%)", 100.0 * (m / c.molecules as f64 - 1.0)))
+            .collect();
+        println!("Expected molecules at more reads: {}", gains.join(", "));
+    }
+
+    /// Print the distance distribution of the accepted reads
+    pub fn print_distances(&self, full_scan: bool) {
+        let [d0, d1, d2] = self.counts.distances;
+        println!("\nMatch distances: 0: {d0}, 1: {d1}, 2: {d2}");
+        if full_scan {
+            println!(
+                "Exact matches with another reference within distance 2: {}",
+                self.counts.exact_neighbors
+            );
+        }
+    }
+
+    /// The barcodes having cells above min_reads, sorted by count
+    pub fn features(
+        &self,
+        min_reads: usize,
+        min_cells: usize,
+        reads_per_cell: Option<usize>,
+        classifier: Classifier,
+    ) -> Vec<report::Feature> {
+        let mut hits: Vec<_> = self.counts.cell_summary(min_reads).into_iter().collect();
+        hits.sort_by_key(|&(pos, (count, _))| (std::cmp::Reverse(count), pos));
+
+        let accepted = self.accepted_cells(min_reads);
+        let umis = self.counts.umi_summary();
+        let strict = self.counts.strict_summary(min_reads).map(|(s, _)| s);
+        hits.into_iter()
+            .map(|(pos, (count, cells))| {
+                let record = &self.barcodes.records[pos];
+                let distribution = classify::Distribution::new(&feature_counts(&accepted, pos), classifier);
+                let strict = strict.as_ref().map(|s| s.get(&pos).copied().unwrap_or_default());
+                report::Feature {
+                    id: record.get(0).unwrap().to_owned(),
+                    name: record.get(1).unwrap().to_owned(),
+                    barcode: record.get(4).unwrap().to_owned(),
+                    count,
+                    cells,
+                    reads_per_cell: average_reads(count, cells),
+                    rescued: self.counts.rescued.get(&pos).copied().unwrap_or(0),
+                    passed: passes(count, cells, min_reads, min_cells, reads_per_cell),
+                    distribution,
+                    umis: umis.as_ref().map(|u| u.get(&pos).map_or(0, |&(_, umis)| umis)),
+                    reads_per_umi: umis
+                        .as_ref()
+                        .and_then(|u| u.get(&pos))
+                        .map(|&(reads, umis)| reads as f64 / umis as f64),
+                    lenient_cells: strict.map(|(_, strict_cells)| cells - strict_cells),
+                    passed_strict: strict.map(|(count, cells)| passes(count, cells, min_reads, min_cells, reads_per_cell)),
+                }
+            })
+            .collect()
+    }
+
+    /// The cells having a barcode above min_reads, sorted by cell code
+    fn accepted_cells(&self, min_reads: usize) -> Vec<(&CellCode, &SparseCounts<CompactRef>)> {
+        let mut cells: Vec<_> = self
+            .counts
+            .cells
+            .0
+            .iter()
+            .filter(|(_, counter)| counter.filter_hits(min_reads).next().is_some())
+            .collect();
+        cells.sort_by_key(|(cellcode, _)| *cellcode);
+        cells
+    }
+
+    /// Write the per cell presence calls of the barcodes passing all thresholds as CSV. A
+    /// barcode is present in a cell when its count reaches the adaptive threshold of that
+    /// barcode. Only cells with a barcode above min_reads are written.
+    pub fn write_calls<W: Write>(
         &self,
         w: W,
         min_reads: usize,
         min_cells: usize,
         reads_per_cell: Option<usize>,
+        classifier: Classifier,
     ) -> Result<()> {
-        let result = self.counts.cells.summary(min_reads);
+        let positions = self.passing_positions(min_reads, min_cells, reads_per_cell);
+        let cells = self.accepted_cells(min_reads);
+        let thresholds: Vec<_> = positions
+            .iter()
+            .map(|&pos| classifier.threshold(&feature_counts(&cells, pos)))
+            .collect();
+
+        let mut writer = csv::Writer::from_writer(w);
+        writer.write_field("cell")?;
+        for &pos in &positions {
+            writer.write_field(self.barcodes.records[pos].get(1).unwrap())?;
+        }
+        writer.write_record(["positive"])?;
+
+        for (cellcode, counter) in cells {
+            writer.write_field(cell_name(cellcode))?;
+            let mut positive = 0;
+            for (pos, &threshold) in positions.iter().zip(&thresholds) {
+                let present = counter.get(*pos as CompactRef) >= threshold;
+                positive += present as usize;
+                writer.write_field(if present { "1" } else { "0" })?;
+            }
+            writer.write_record([positive.to_string()])?;
+        }
+        Ok(())
+    }
+
+    /// Write the most likely barcode of each cell, of the barcodes passing all thresholds, as
+    /// CSV with its posterior and the runner-up. The ambient background is estimated from the
+    /// reads outside the most frequent barcode of each cell. Only cells with a barcode above
+    /// min_reads are written. Returns the estimated ambient fraction of the reads.
+    pub fn write_assignments<W: Write>(
+        &self,
+        w: W,
+        min_reads: usize,
+        min_cells: usize,
+        reads_per_cell: Option<usize>,
+    ) -> Result<f64> {
+        let positions = self.passing_positions(min_reads, min_cells, reads_per_cell);
+        let cells = self.accepted_cells(min_reads);
+        let counts: Vec<Vec<usize>> = cells
+            .iter()
+            .map(|(_, counter)| positions.iter().map(|&pos| counter.get(pos as CompactRef)).collect())
+            .collect();
+        let ambient = classify::Ambient::estimate(&counts);
+        let name = |f: usize| self.barcodes.records[positions[f]].get(1).unwrap();
+
+        let mut writer = csv::Writer::from_writer(w);
+        writer.write_record(["cell", "reads", "feature", "score", "runner_up", "runner_up_score"])?;
+        for ((cellcode, _), counts) in cells.iter().zip(&counts) {
+            let posteriors = ambient.posteriors(counts);
+            let mut ranked: Vec<_> = (0..positions.len()).collect();
+            ranked.sort_by(|&a, &b| posteriors[b].total_cmp(&posteriors[a]).then(a.cmp(&b)));
+
+            let reads: usize = counts.iter().sum();
+            let entry = |f: Option<&usize>| {
+                f.map_or(("", String::new()), |&f| (name(f), format!("{:.4}", posteriors[f])))
+            };
+            let (feature, score) = entry(ranked.first());
+            let (runner_up, runner_up_score) = entry(ranked.get(1));
+            writer.write_field(cell_name(cellcode))?;
+            writer.write_record([&reads.to_string(), feature, &score, runner_up, &runner_up_score])?;
+        }
+        Ok(ambient.fraction)
+    }
+
+    /// The barcodes passing all thresholds, sorted by id
+    fn passing_positions(&self, min_reads: usize, min_cells: usize, reads_per_cell: Option<usize>) -> Vec<usize> {
+        let mut positions: Vec<_> = self.counts.cell_summary(min_reads)
+            .into_iter()
+            .filter(|&(_, (count, cells))| passes(count, cells, min_reads, min_cells, reads_per_cell))
+            .map(|(pos, _)| pos)
+            .collect();
+        positions.sort_by_key(|&pos| self.barcodes.records[pos].get(0).unwrap());
+        positions
+    }
+
+    /// Print the per cell count distribution of the barcodes above min_reads
+    pub fn print_distributions(&self, min_reads: usize, classifier: Classifier) {
+        let features = self.features(min_reads, 0, None, classifier);
+
+        let mut tabledata = Vec::new();
+        for feature in features {
+            let d = feature.distribution;
+            tabledata.push(vec![
+                feature.name.cell(),
+                format!("{:.1}", d.p90).cell().justify(Justify::Right),
+                format!("{:.1}", d.p99).cell().justify(Justify::Right),
+                d.background_mode.cell().justify(Justify::Right),
+                d.threshold.cell().justify(Justify::Right),
+                format!("{:.1}%", 100.0 * d.positive_fraction).cell().justify(Justify::Right),
+            ]);
+        }
+
+        let table = tabledata
+            .table()
+            .title(vec![
+                "name".cell(),
+                "p90".cell(),
+                "p99".cell(),
+                "background".cell(),
+                "threshold".cell(),
+                "positive".cell(),
+            ])
+            .border(Border::builder().build())
+            .separator(Separator::builder().row(None).column(None).build());
+
+        println!("\nPer cell count distribution:\n{}", table.display().unwrap());
+    }
+
+    /// The nonzero (cell, barcode, count) rows of the cells having a barcode above min_reads
+    pub fn long_counts(&self, min_reads: usize) -> Vec<LongCount<'_>> {
+        let mut rows = Vec::new();
+        for (cellcode, counter) in self.accepted_cells(min_reads) {
+            for (&pos, count) in counter.iter() {
+                let record = &self.barcodes.records[pos as BarcodeRef];
+                rows.push(LongCount {
+                    cell: cell_name(cellcode),
+                    feature_id: record.get(0).unwrap(),
+                    feature: record.get(1).unwrap(),
+                    count,
+                });
+            }
+        }
+        rows
+    }
+
+    /// Number of barcodes passing all thresholds
+    pub fn n_passing(
+        &self,
+        min_reads: usize,
+        min_cells: usize,
+        reads_per_cell: Option<usize>,
+    ) -> usize {
+        self.counts.cell_summary(min_reads)
+            .into_values()
+            .filter(|&(count, cells)| passes(count, cells, min_reads, min_cells, reads_per_cell))
+            .count()
+    }
+
+    /// Write the id, name, barcode and count of the barcodes passing all thresholds as CSV
+    pub fn write_bulk<W: Write>(
+        &self,
+        w: W,
+        min_reads: usize,
+        min_cells: usize,
+        reads_per_cell: Option<usize>,
+    ) -> Result<()> {
+        let mut hits: Vec<_> = self
+            .counts
+            .cell_summary(min_reads)
+            .into_iter()
+            .filter(|&(_, (count, cells))| passes(count, cells, min_reads, min_cells, reads_per_cell))
+            .collect();
+        hits.sort_by_key(|&(pos, (count, _))| (std::cmp::Reverse(count), pos));
+
+        let mut writer = csv::Writer::from_writer(w);
+        writer.write_record(["id", "name", "barcode", "count"])?;
+        for (pos, (count, _)) in hits {
+            let record = &self.barcodes.records[pos];
+            writer.write_record([
+                record.get(0).unwrap(),
+                record.get(1).unwrap(),
+                record.get(4).unwrap(),
+                &count.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_csv<W: Write>(
+        &self,
+        w: W,
+        min_reads: usize,
+        min_cells: usize,
+        reads_per_cell: Option<usize>,
+    ) -> Result<Vec<Rename>> {
+        let result = self.counts.cell_summary(min_reads);
 
         self.barcodes.write_csv(
             w,
@@ -225,11 +1613,31 @@ impl<'a> Summary<'a> {
                 .filter(|&(_pos, (count, cells))| {
                     passes(count, cells, min_reads, min_cells, reads_per_cell)
                 })
-                .map(|(pos, _)| *pos),
+                .map(|(pos, _)| pos),
         )
     }
 }
 
+/// Add to a 32 bit count, saturating at the maximum. The count not added.
+fn saturating_add(total: &mut u32, count: u32) -> usize {
+    let sum = total.saturating_add(count);
+    let lost = count - (sum - *total);
+    *total = sum;
+    lost as usize
+}
+
+/// The UMIs with one base replaced by another base or N
+fn umi_neighbors(umi: &Umi) -> impl Iterator<Item = Umi> + '_ {
+    let length = umi.iter().position(|&b| b == 0).unwrap_or(umi.len());
+    (0..length).flat_map(move |i| {
+        b"ACGTN".iter().filter(move |&&b| b != umi[i]).map(move |&b| {
+            let mut neighbor = *umi;
+            neighbor[i] = b;
+            neighbor
+        })
+    })
+}
+
 /// Helper function for testing if the thresholds are met
 fn passes(
     count: usize,
@@ -238,5 +1646,216 @@ fn passes(
     min_cells: usize,
     reads_per_cell: Option<usize>,
 ) -> bool {
-    cells >= min_cells && count > min_reads && reads_per_cell.map_or(true, |r| count / cells > r)
+    cells >= min_cells
+        && count > min_reads
+        && reads_per_cell.is_none_or(|r| average_reads(count, cells).is_some_and(|a| a > r))
+}
+
+/// The counts of a barcode in each of the cells
+fn feature_counts(cells: &[(&CellCode, &SparseCounts<CompactRef>)], pos: BarcodeRef) -> Vec<usize> {
+    cells.iter().map(|(_, counter)| counter.get(pos as CompactRef)).collect()
+}
+
+/// Average reads per cell, None when there are no cells
+fn average_reads(count: usize, cells: usize) -> Option<usize> {
+    count.checked_div(cells)
+}
+
+/// Format the average reads per cell, NA when there are no cells
+fn format_reads_per_cell(count: usize, cells: usize) -> String {
+    average_reads(count, cells).map_or_else(|| "NA".to_owned(), |a| a.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAX_BCLENGTH, UMILENGTH};
+
+    /// A cell code of 16 bases from a number
+    fn cell(i: usize) -> CellCode {
+        let mut cellcode = [0; MAX_CCLENGTH + 1];
+        for (k, base) in cellcode[..CCLENGTH].iter_mut().enumerate() {
+            *base = b"ACGT"[(i >> (2 * k)) & 3];
+        }
+        cellcode
+    }
+
+    /// A reference of two features, written to a temporary file
+    fn barcodes(name: &str) -> Barcodes {
+        let path = std::env::temp_dir().join(format!("featureseek-{}-{name}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "id,name,read,pattern,sequence,feature_type\n\
+            H1,Hashtag_1,R2,5PNNNNNNNNNN(BC),CAGATTTTCATATTA,Antibody Capture\n\
+            H2,Hashtag_2,R2,5PNNNNNNNNNN(BC),TGCAGAAAATCTACT,Antibody Capture\n",
+        )
+        .unwrap();
+        let barcodes = Barcodes::from_csv(&[&path], crate::barcodes::Precedence::First, crate::BCLENGTH).unwrap();
+        std::fs::remove_file(path).unwrap();
+        barcodes
+    }
+
+    #[test]
+    fn reads_per_cell_without_cells() {
+        assert_eq!(average_reads(10, 0), None);
+        assert_eq!(average_reads(10, 3), Some(3));
+        assert_eq!(format_reads_per_cell(10, 0), "NA");
+        assert_eq!(format_reads_per_cell(0, 0), "NA");
+        // no cells fails a reads per cell threshold, and passes without one
+        assert!(!passes(10, 0, 5, 0, Some(1)));
+        assert!(passes(10, 0, 5, 0, None));
+    }
+
+    #[test]
+    fn features_with_only_lenient_cells() {
+        let barcodes = barcodes("lenient");
+        let mut counts = Counts::default().with_lenient();
+        for _ in 0..10 {
+            counts.count_rescued(cell(1), 0);
+        }
+        counts.count_barcode(cell(2), 1);
+        let summary = Summary::new(&barcodes, &counts);
+        let features = summary.features(5, 1, Some(2), Classifier::Otsu);
+        // without the lenient reads the feature has no cells left
+        assert_eq!(features.len(), 1);
+        assert_eq!((features[0].count, features[0].cells), (10, 1));
+        assert_eq!(features[0].reads_per_cell, Some(10));
+        assert!(features[0].passed);
+        assert_eq!(features[0].lenient_cells, Some(1));
+        assert_eq!(features[0].passed_strict, Some(false));
+        // above all counts no feature remains
+        assert!(summary.features(10, 1, Some(2), Classifier::Otsu).is_empty());
+    }
+
+    #[test]
+    fn merge_sums_overlapping_counts() {
+        let (bc, umi_a, umi_b) = ([b'A'; MAX_BCLENGTH], [b'A'; UMILENGTH], [b'C'; UMILENGTH]);
+        let (x, y) = (to_barcode(b"GGGGGGGGGGGGGGG").unwrap(), to_barcode(b"TTTTTTTTTTTTTTT").unwrap());
+
+        let mut a = Counts::default().with_umis().with_lenient().with_dedup();
+        for _ in 0..3 {
+            a.count_barcode(cell(1), 0);
+        }
+        a.count_rescued(cell(1), 0);
+        a.count_barcode(cell(2), 1);
+        a.count_molecule(cell(1), 0, umi_a);
+        a.count_molecule(cell(1), 0, umi_a);
+        a.count_unknown(cell(1), x, Some(umi_a), 100);
+        a.rejected();
+        assert!(!a.is_duplicate(cell(1), &bc, Some(umi_a), None));
+        assert!(a.is_duplicate(cell(1), &bc, Some(umi_a), None));
+
+        let mut b = Counts::default().with_umis().with_lenient().with_dedup();
+        b.count_barcode(cell(1), 0);
+        b.count_rescued(cell(1), 0);
+        b.count_rescued(cell(1), 0);
+        b.count_barcode(cell(3), 0);
+        b.count_molecule(cell(1), 0, umi_a);
+        b.count_molecule(cell(1), 0, umi_b);
+        b.count_unknown(cell(1), x, Some(umi_b), 100);
+        b.count_unknown(cell(3), y, None, 100);
+        b.rejected();
+        b.rejected();
+        b.not_whitelisted();
+        assert!(!b.is_duplicate(cell(1), &bc, Some(umi_a), None));
+        assert!(b.is_duplicate(cell(1), &bc, Some(umi_a), None));
+
+        a.merge(b);
+        assert_eq!(a.n_cells(), 3);
+        assert_eq!(a.cells.0[&cell(1)].get(0), 3 + 1 + 1 + 2);
+        assert_eq!(a.cells.0[&cell(2)].get(1), 1);
+        assert_eq!(a.cells.0[&cell(3)].get(0), 1);
+        assert_eq!(a.lenient.as_ref().unwrap().0[&cell(1)].get(0), 3);
+        assert_eq!(a.rescued[&0], 3);
+
+        let molecules = a.molecules.as_ref().unwrap();
+        assert_eq!(molecules[&(cell(1), 0, umi_a)], 3);
+        assert_eq!(molecules[&(cell(1), 0, umi_b)], 1);
+        assert_eq!(a.unknown.0[&cell(1)].get(x), 2);
+        assert_eq!(a.unknown.0[&cell(3)].get(y), 1);
+        assert_eq!((a.unknown_totals.0[&x], a.unknown_totals.0[&y]), (2, 1));
+        assert_eq!(a.unknown_molecules.as_ref().unwrap().len(), 2);
+
+        let counters = a.counters();
+        assert_eq!(counters.rejected, 3);
+        assert_eq!(counters.not_whitelisted, 1);
+        assert_eq!(counters.duplicates, 2);
+    }
+
+    #[test]
+    fn evicted_cells_leave_the_rescued_reads() {
+        let mut counts = Counts::default().with_lenient();
+        for i in 0..6 {
+            counts.count_barcode(cell(i), 0);
+            // the cells differ in reads, so eviction keeps exactly two
+            for _ in 0..3 + i {
+                counts.count_rescued(cell(i), 0);
+            }
+        }
+        counts.evict_cells(2);
+        assert_eq!(counts.n_cells(), 2);
+        assert_eq!(counts.rescued[&0], 7 + 8);
+        let (count, _) = counts.cell_summary(0)[&0];
+        assert!(counts.rescued[&0] <= count);
+    }
+
+    #[test]
+    fn no_hits_are_counted_by_cause() {
+        let mut counts = Counts::default();
+        let mut barcode = [b'A'; MAX_BCLENGTH];
+        counts.nohit(&barcode, true, true);
+        counts.nohit(&barcode, false, true);
+        counts.nohit(&barcode, false, false);
+        barcode[3] = b'N';
+        counts.nohit(&barcode, true, false);
+        let c = counts.counters();
+        assert_eq!((c.contains_n, c.below_quality, c.no_approximate_hit, c.no_exact_hit), (1, 1, 1, 1));
+    }
+
+    #[test]
+    fn evicting_cells_with_tied_reads_keeps_exactly_keep() {
+        let mut counts = Counts::default();
+        for i in 0..10 {
+            counts.count_barcode(cell(i), 0);
+        }
+        counts.evict_cells(4);
+        assert_eq!(counts.n_cells(), 4);
+        assert_eq!((counts.evicted_cells, counts.evicted_reads), (6, 6));
+    }
+
+    #[test]
+    fn evicting_unknown_barcodes_with_tied_reads_keeps_exactly_keep() {
+        let mut counts = Counts::default();
+        for i in 0..11 {
+            let mut barcode = [0; MAX_BCLENGTH];
+            barcode[..CCLENGTH].copy_from_slice(&cell(i)[..CCLENGTH]);
+            counts.count_unknown(cell(0), barcode, None, 10);
+        }
+        // 11 distinct barcodes exceed the limit of 10, which keeps 9
+        assert_eq!(counts.unknown_totals.0.len(), 9);
+        assert_eq!((counts.unknown_evicted, counts.unknown_evicted_reads), (2, 2));
+    }
+
+    #[test]
+    fn bulk_counts_saturate_at_the_32_bit_maximum() {
+        let umi = [b'A'; UMILENGTH];
+        let mut counts = Counts::bulk().with_umis();
+        counts.count_barcode(cell(1), 0);
+        counts.count_molecule(cell(1), 0, umi);
+        counts.cells.0.get_mut(&BULK_CELL).unwrap().0[0].1 = u32::MAX - 1;
+        *counts.molecules.as_mut().unwrap().get_mut(&(BULK_CELL, 0, umi)).unwrap() = u32::MAX;
+
+        for i in 0..3 {
+            counts.count_barcode(cell(i), 0);
+        }
+        counts.count_molecule(cell(2), 0, umi);
+        assert_eq!(counts.cells.0[&BULK_CELL].get(0), u32::MAX as usize);
+        assert_eq!(counts.counters().saturated, 2 + 1);
+
+        let mut other = Counts::bulk();
+        other.count_barcode(cell(1), 0);
+        counts.merge(other);
+        assert_eq!(counts.cells.0[&BULK_CELL].get(0), u32::MAX as usize);
+        assert_eq!(counts.counters().saturated, 3 + 1);
+    }
 }
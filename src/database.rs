@@ -0,0 +1,236 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::export::LongCount;
+use crate::report::Report;
+
+/// Version of the SQLite schema, increased on incompatible changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE parameters (name TEXT PRIMARY KEY, value TEXT);
+CREATE TABLE metrics (name TEXT PRIMARY KEY, value INTEGER NOT NULL);
+CREATE TABLE ignored_barcodes (barcode TEXT PRIMARY KEY, reads INTEGER NOT NULL);
+CREATE TABLE scan_rescued (shift INTEGER PRIMARY KEY, reads INTEGER NOT NULL);
+CREATE TABLE features (
+    barcode TEXT PRIMARY KEY,
+    id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    cells INTEGER NOT NULL,
+    reads_per_cell INTEGER,
+    passed INTEGER NOT NULL
+);
+CREATE TABLE cell_counts (
+    cell TEXT NOT NULL,
+    feature_id TEXT NOT NULL,
+    count INTEGER NOT NULL
+);
+CREATE INDEX cell_counts_cell ON cell_counts (cell);
+";
+
+/// Write the run results to a new SQLite database: the parameters, the read counters as
+/// metrics with the reads per ignored barcode and per scan shift in tables of their own, the
+/// features and the per cell counts.
+#[cfg(feature = "sqlite")]
+pub fn write_sqlite<P: AsRef<Path>>(
+    p: P,
+    parameters: &serde_json::Value,
+    report: &Report,
+    counts: &[LongCount],
+) -> Result<()> {
+    use rusqlite::{params, Connection};
+    use serde_json::Value;
+
+    if p.as_ref().exists() {
+        std::fs::remove_file(p.as_ref())?;
+    }
+    let mut conn = Connection::open(p)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+
+    tx.execute(
+        "INSERT INTO meta VALUES ('schema_version', ?1), ('featureseek_version', ?2)",
+        params![SCHEMA_VERSION.to_string(), report.version],
+    )?;
+
+    if let Value::Object(parameters) = parameters {
+        let mut insert = tx.prepare("INSERT INTO parameters VALUES (?1, ?2)")?;
+        for (name, value) in parameters {
+            let value = match value {
+                Value::Null => None,
+                Value::String(s) => Some(s.clone()),
+                v => Some(v.to_string()),
+            };
+            insert.execute(params![name, value])?;
+        }
+    }
+
+    {
+        let mut insert = tx.prepare("INSERT INTO metrics VALUES (?1, ?2)")?;
+        insert.execute(params!["reads", report.reads])?;
+        if let Value::Object(counters) = serde_json::to_value(&report.counters)? {
+            for (name, value) in counters {
+                if let Some(value) = value.as_u64() {
+                    insert.execute(params![name, value])?;
+                }
+            }
+        }
+
+        let mut insert = tx.prepare("INSERT INTO ignored_barcodes VALUES (?1, ?2)")?;
+        for (barcode, reads) in &report.counters.ignored_barcodes {
+            insert.execute(params![barcode, reads])?;
+        }
+
+        let mut insert = tx.prepare("INSERT INTO scan_rescued VALUES (?1, ?2)")?;
+        for (shift, reads) in &report.counters.scan_rescued {
+            insert.execute(params![shift, reads])?;
+        }
+
+        let mut insert = tx.prepare("INSERT INTO features VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+        for f in &report.features {
+            insert.execute(params![
+                f.barcode,
+                f.id,
+                f.name,
+                f.count,
+                f.cells,
+                f.reads_per_cell,
+                f.passed
+            ])?;
+        }
+
+        let mut insert = tx.prepare("INSERT INTO cell_counts VALUES (?1, ?2, ?3)")?;
+        for c in counts {
+            insert.execute(params![String::from_utf8_lossy(&c.cell), c.feature_id, c.count])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// The (feature id, count) profile of a cell in a results database, by decreasing count
+#[cfg(feature = "sqlite")]
+pub fn query_cell<P: AsRef<Path>>(p: P, cell: &str) -> Result<Vec<(String, usize)>> {
+    let conn = open(p)?;
+    let mut query = conn.prepare(
+        "SELECT feature_id, count FROM cell_counts WHERE cell = ?1 ORDER BY count DESC, feature_id",
+    )?;
+    let rows = query.query_map([cell], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// The (cell, count) distribution of a feature, by id or name, in a results database by
+/// decreasing count
+#[cfg(feature = "sqlite")]
+pub fn query_feature<P: AsRef<Path>>(p: P, feature: &str) -> Result<Vec<(String, usize)>> {
+    let conn = open(p)?;
+    let mut query = conn.prepare(
+        "SELECT cell, count FROM cell_counts WHERE feature_id IN
+            (SELECT id FROM features WHERE id = ?1 OR name = ?1)
+        ORDER BY count DESC, cell",
+    )?;
+    let rows = query.query_map([feature], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Open an existing results database read only and check the schema version
+#[cfg(feature = "sqlite")]
+fn open<P: AsRef<Path>>(p: P) -> Result<rusqlite::Connection> {
+    use rusqlite::{Connection, OpenFlags};
+
+    let conn = Connection::open_with_flags(p, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let version: String = conn.query_row(
+        "SELECT value FROM meta WHERE key = 'schema_version'",
+        [],
+        |row| row.get(0),
+    )?;
+    if version != SCHEMA_VERSION.to_string() {
+        anyhow::bail!("Unsupported schema version {version}, expected {SCHEMA_VERSION}");
+    }
+    Ok(conn)
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn write_sqlite<P: AsRef<Path>>(
+    _p: P,
+    _parameters: &serde_json::Value,
+    _report: &Report,
+    _counts: &[LongCount],
+) -> Result<()> {
+    anyhow::bail!("featureseek was built without SQLite support, rebuild with --features sqlite")
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn query_cell<P: AsRef<Path>>(_p: P, _cell: &str) -> Result<Vec<(String, usize)>> {
+    anyhow::bail!("featureseek was built without SQLite support, rebuild with --features sqlite")
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub fn query_feature<P: AsRef<Path>>(_p: P, _feature: &str) -> Result<Vec<(String, usize)>> {
+    anyhow::bail!("featureseek was built without SQLite support, rebuild with --features sqlite")
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_counter_maps_to_tables() {
+        let report: Report = serde_json::from_value(serde_json::json!({
+            "version": "0.1.0",
+            "reads": 100,
+            "inputs": [],
+            "counters": {
+                "ignored": 7,
+                "ignored_barcodes": {"GGGGGGGGGGGGGGG": 5, "CCTAATGGTCCAGAC": 2},
+                "no_exact_hit": 1,
+                "no_approximate_hit": 0,
+                "multiple": 0,
+                "contains_n": 0,
+                "too_short": 0,
+                "not_whitelisted": 0,
+                "evicted_cells": 0,
+                "evicted_reads": 0,
+                "scan_rescued": {"-1": 3, "2": 4}
+            },
+            "features": [],
+            "throughput": {
+                "wall_seconds": 1.0,
+                "cpu_seconds": 1.0,
+                "reads_per_second_min": 100.0,
+                "reads_per_second_avg": 100.0,
+                "reads_per_second_max": 100.0,
+                "reads_per_minute": []
+            }
+        }))
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("featureseek-sqlite-{}.db", std::process::id()));
+        write_sqlite(&path, &serde_json::json!({}), &report, &[]).unwrap();
+
+        let conn = open(&path).unwrap();
+        let select = |sql: &str| -> Vec<(String, i64)> {
+            let mut query = conn.prepare(sql).unwrap();
+            let rows = query.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+            rows.collect::<Result<_, _>>().unwrap()
+        };
+        assert_eq!(
+            select("SELECT barcode, reads FROM ignored_barcodes ORDER BY barcode"),
+            [("CCTAATGGTCCAGAC".to_owned(), 2), ("GGGGGGGGGGGGGGG".to_owned(), 5)]
+        );
+        assert_eq!(
+            select("SELECT CAST(shift AS TEXT), reads FROM scan_rescued ORDER BY shift"),
+            [("-1".to_owned(), 3), ("2".to_owned(), 4)]
+        );
+        let metrics = select("SELECT name, value FROM metrics ORDER BY name");
+        assert!(metrics.contains(&("ignored".to_owned(), 7)));
+        assert!(metrics.contains(&("reads".to_owned(), 100)));
+        assert!(!metrics.iter().any(|(name, _)| name == "ignored_barcodes" || name == "scan_rescued"));
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+/// A row of the bcl-convert `Demultiplex_Stats.csv`: the read pairs of a sample in a lane
+#[derive(Deserialize)]
+struct DemuxRow {
+    #[serde(rename = "Lane")]
+    lane: u32,
+    #[serde(rename = "SampleID")]
+    sample: String,
+    #[serde(rename = "# Reads")]
+    reads: usize,
+}
+
+/// The read pairs of the sample demultiplexed by bcl-convert, summed over the lanes, or only
+/// over the given lanes.
+pub fn expected_reads<P: AsRef<Path>>(p: P, sample: &str, lanes: Option<&[u32]>) -> Result<usize> {
+    let p = p.as_ref();
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(p)?;
+    let mut found = false;
+    let mut reads = 0;
+    for row in reader.deserialize() {
+        let row: DemuxRow = row?;
+        if row.sample != sample {
+            continue;
+        }
+        found = true;
+        if lanes.is_none_or(|lanes| lanes.contains(&row.lane)) {
+            reads += row.reads;
+        }
+    }
+    if !found {
+        return Err(IoError::new(
+            ErrorKind::InvalidData,
+            format!("Sample {sample} not in the demultiplexing statistics {}", p.display()),
+        )
+        .into());
+    }
+    Ok(reads)
+}
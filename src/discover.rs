@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+/// FastQ file name extensions
+const EXTENSIONS: [&str; 4] = [".fastq.gz", ".fq.gz", ".fastq", ".fq"];
+
+/// Find the read 1 and read 2 FastQ files in a directory, named after the bcl2fastq convention
+/// `<sample>_S<n>_L<lane>_R<read>_001.fastq.gz`. The files are paired by name and the pairs
+/// sorted by name. Index read files are ignored. When sample is given only the files of that
+/// sample are used, otherwise the directory should hold a single sample.
+pub fn fastq_pairs(dir: &Path, sample: Option<&str>) -> Result<Vec<(PathBuf, PathBuf)>> {
+    // the file name with the read number replaced by the read 1 and 2 files
+    let mut pairs: BTreeMap<String, (Option<PathBuf>, Option<PathBuf>)> = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !EXTENSIONS.iter().any(|e| name.ends_with(e)) {
+            continue;
+        }
+        if sample.is_some_and(|s| sample_name(&path) != Some(s)) {
+            continue;
+        }
+        let (key, read) = match (name.rfind("_R1_"), name.rfind("_R2_")) {
+            (Some(i), None) => (format!("{}_R?_{}", &name[..i], &name[i + 4..]), 1),
+            (None, Some(i)) => (format!("{}_R?_{}", &name[..i], &name[i + 4..]), 2),
+            _ => continue,
+        };
+        let pair = pairs.entry(key).or_default();
+        if read == 1 {
+            pair.0 = Some(path);
+        } else {
+            pair.1 = Some(path);
+        }
+    }
+
+    let mut orphans = Vec::new();
+    let mut found = Vec::new();
+    for (key, pair) in pairs {
+        match pair {
+            (Some(r1), Some(r2)) => found.push((r1, r2)),
+            (Some(r), None) | (None, Some(r)) => orphans.push(r.display().to_string()),
+            (None, None) => unreachable!("pair of {key} without files"),
+        }
+    }
+    if !orphans.is_empty() {
+        bail!("FastQ files without a read 1 or read 2 mate in {}: {}", dir.display(), orphans.join(", "));
+    }
+    if found.is_empty() {
+        bail!("No *_R1_* and *_R2_* FastQ files found in {}", dir.display());
+    }
+
+    let mut samples: Vec<_> = found.iter().filter_map(|(r1, _)| sample_name(r1)).collect();
+    samples.sort_unstable();
+    samples.dedup();
+    if sample.is_none() && samples.len() > 1 {
+        bail!("Multiple samples in {}: {}, select one with --sample", dir.display(), samples.join(", "));
+    }
+    Ok(found)
+}
+
+/// The sample name preceding the `_S<n>_` sample number of a bcl2fastq file name
+pub fn sample_name(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+    name.rmatch_indices("_S").find_map(|(i, _)| {
+        let number = name[i + 2..].split('_').next()?;
+        (!number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())).then(|| &name[..i])
+    })
+}
+
+/// The lane number of the `_L<lane>_` part of a bcl2fastq file name
+pub fn lane(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_str()?;
+    name.match_indices("_L").find_map(|(i, _)| {
+        let number = name[i + 2..].split('_').next()?;
+        (number.len() == 3 && number.bytes().all(|b| b.is_ascii_digit())).then(|| number.parse().ok())?
+    })
+}
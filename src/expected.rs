@@ -0,0 +1,105 @@
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+
+use anyhow::Result;
+use cli_table::{
+    format::{Border, Justify, Separator},
+    Cell, Table, TableStruct,
+};
+use serde::Deserialize;
+
+use crate::barcodes::Barcodes;
+use crate::report::{ExpectedFraction, Feature};
+
+/// A feature with its fraction of the pool
+#[derive(Deserialize)]
+struct Row {
+    feature: String,
+    expected_fraction: f64,
+}
+
+/// The expected fractions of the features, by reference id
+pub struct Expected(Vec<(String, f64)>);
+
+impl Expected {
+    /// Read the expected fractions from a csv file with `feature` and `expected_fraction`
+    /// columns, as in the pooling sheet. The features are reference ids or names.
+    pub fn from_csv<P: AsRef<Path>>(p: P, barcodes: &Barcodes) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(p)?;
+        let mut expected = Vec::new();
+        for (i, row) in reader.deserialize().enumerate() {
+            let row: Row = row?;
+            let record = barcodes
+                .records
+                .iter()
+                .find(|r| r.get(0) == Some(row.feature.as_str()) || r.get(1) == Some(row.feature.as_str()));
+            let Some(record) = record else {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Expected feature {} on row {} not in the reference", row.feature, i + 2),
+                )
+                .into());
+            };
+            if row.expected_fraction <= 0.0 {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Expected feature {} on row {} needs a positive fraction", row.feature, i + 2),
+                )
+                .into());
+            }
+            expected.push((record.get(0).unwrap().to_owned(), row.expected_fraction));
+        }
+        Ok(Expected(expected))
+    }
+
+    /// The observed and expected fractions of the listed features, both over the listed
+    /// features, with the fold deviation. Within tolerance when the fold deviation is at most
+    /// tolerance in either direction.
+    pub fn compare(&self, features: &[Feature], tolerance: f64) -> Vec<ExpectedFraction> {
+        let count = |id: &str| features.iter().find(|f| f.id == id).map_or(0, |f| f.count);
+        let total_expected: f64 = self.0.iter().map(|(_, fraction)| fraction).sum();
+        let total_count: usize = self.0.iter().map(|(id, _)| count(id)).sum();
+        self.0
+            .iter()
+            .map(|(id, fraction)| {
+                let expected = fraction / total_expected;
+                let observed = count(id) as f64 / total_count.max(1) as f64;
+                let fold = observed / expected;
+                ExpectedFraction {
+                    id: id.clone(),
+                    name: features.iter().find(|f| &f.id == id).map_or_else(String::new, |f| f.name.clone()),
+                    expected,
+                    observed,
+                    fold,
+                    within_tolerance: fold <= tolerance && fold >= 1.0 / tolerance,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Table of the observed versus expected fractions, flagging the features outside tolerance
+pub fn fractions_table(fractions: &[ExpectedFraction]) -> TableStruct {
+    let mut tabledata = Vec::new();
+    for f in fractions {
+        tabledata.push(vec![
+            f.name.clone().cell(),
+            format!("{:.4}", f.expected).cell().justify(Justify::Right),
+            format!("{:.4}", f.observed).cell().justify(Justify::Right),
+            format!("{:.2}", f.fold).cell().justify(Justify::Right),
+            if f.within_tolerance { "" } else { "OUTSIDE" }.cell(),
+        ]);
+    }
+
+    tabledata
+        .table()
+        .title(vec![
+            "name".cell(),
+            "expected".cell(),
+            "observed".cell(),
+            "fold".cell(),
+            "flag".cell(),
+        ])
+        .border(Border::builder().build())
+        .separator(Separator::builder().row(None).column(None).build())
+}
@@ -0,0 +1,222 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::hash::HashMap;
+
+/// A (cell, feature, count) row of the long format count table
+pub struct LongCount<'a> {
+    pub cell: Cow<'a, [u8]>,
+    pub feature_id: &'a str,
+    pub feature: &'a str,
+    pub count: usize,
+}
+
+/// A row of a long format count table read back from CSV
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct LongRow {
+    pub cell: String,
+    pub feature_id: String,
+    pub feature: String,
+    pub count: usize,
+}
+
+/// The summed counts and number of cells above the minimum reads of a feature
+#[derive(Debug, PartialEq)]
+pub struct FeatureTotal {
+    pub id: String,
+    pub name: String,
+    pub count: usize,
+    pub cells: usize,
+}
+
+/// Write the long format count table as CSV when the file extension is `.csv`, as Arrow IPC
+/// for `.arrow`, `.ipc` or `.feather` and as Parquet otherwise.
+pub fn write_long<'a, P, I>(p: P, rows: I) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = LongCount<'a>>,
+{
+    if p.as_ref().extension().is_some_and(|e| e == "csv") {
+        write_long_csv(File::create(p)?, rows)
+    } else {
+        write_columnar(p, rows)
+    }
+}
+
+/// Write the long format count table as CSV. The counts are integers and the csv crate does
+/// not depend on the locale, so the table reads back the same everywhere.
+pub fn write_long_csv<'a, W, I>(w: W, rows: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = LongCount<'a>>,
+{
+    let mut writer = csv::Writer::from_writer(w);
+    writer.write_record(["cell", "feature_id", "feature", "count"])?;
+    for row in rows {
+        writer.write_record([&row.cell[..], row.feature_id.as_bytes(), row.feature.as_bytes(), row.count.to_string().as_bytes()])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a long format count table written as CSV
+pub fn read_long_csv<R: Read>(r: R) -> Result<Vec<LongRow>> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(r);
+    let rows = reader.deserialize().collect::<Result<_, _>>()?;
+    Ok(rows)
+}
+
+/// The counts per feature of a long format count table summed over the cells with more than
+/// min_reads reads of the feature, as in the summary of the run, sorted by feature id
+pub fn resummarize(rows: &[LongRow], min_reads: usize) -> Vec<FeatureTotal> {
+    let mut totals: HashMap<&str, FeatureTotal> = HashMap::default();
+    for row in rows {
+        let total = totals.entry(&row.feature_id).or_insert_with(|| FeatureTotal {
+            id: row.feature_id.clone(),
+            name: row.feature.clone(),
+            count: 0,
+            cells: 0,
+        });
+        if row.count > min_reads {
+            total.count += row.count;
+            total.cells += 1;
+        }
+    }
+    let mut totals: Vec<_> = totals.into_values().collect();
+    totals.sort_by(|a, b| a.id.cmp(&b.id));
+    totals
+}
+
+/// Write the feature totals as CSV, with the average reads per cell, NA without cells
+pub fn write_totals_csv<W: Write>(w: W, totals: &[FeatureTotal]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(w);
+    writer.write_record(["id", "name", "count", "cells", "reads_per_cell"])?;
+    for t in totals {
+        let reads_per_cell = t.count.checked_div(t.cells).map_or_else(|| "NA".to_owned(), |r| r.to_string());
+        writer.write_record([&t.id, &t.name, &t.count.to_string(), &t.cells.to_string(), &reads_per_cell])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the long format count table as Arrow IPC when the file extension is `.arrow`, `.ipc`
+/// or `.feather` and as Parquet otherwise.
+#[cfg(feature = "parquet")]
+fn write_columnar<'a, P, I>(p: P, rows: I) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = LongCount<'a>>,
+{
+    use std::sync::Arc;
+
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+
+    let (mut cells, mut ids, mut features, mut counts) = (vec![], vec![], vec![], vec![]);
+    for row in rows {
+        cells.push(String::from_utf8_lossy(&row.cell).into_owned());
+        ids.push(row.feature_id);
+        features.push(row.feature);
+        counts.push(row.count as u64);
+    }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("cell", DataType::Utf8, false),
+        Field::new("feature_id", DataType::Utf8, false),
+        Field::new("feature", DataType::Utf8, false),
+        Field::new("count", DataType::UInt64, false),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(cells)),
+        Arc::new(StringArray::from(ids)),
+        Arc::new(StringArray::from(features)),
+        Arc::new(UInt64Array::from(counts)),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let f = File::create(p.as_ref())?;
+    let ipc = p
+        .as_ref()
+        .extension()
+        .is_some_and(|e| e == "arrow" || e == "ipc" || e == "feather");
+    if ipc {
+        let mut writer = arrow_ipc::writer::FileWriter::try_new(f, &schema)?;
+        writer.write(&batch)?;
+        writer.finish()?;
+    } else {
+        let mut writer = parquet::arrow::ArrowWriter::try_new(f, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_columnar<'a, P, I>(_p: P, _rows: I) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = LongCount<'a>>,
+{
+    anyhow::bail!("featureseek was built without Parquet/Arrow support, rebuild with --features parquet or write a .csv file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<LongCount<'static>> {
+        let row = |cell: &'static [u8], feature_id, feature, count| LongCount {
+            cell: Cow::Borrowed(cell),
+            feature_id,
+            feature,
+            count,
+        };
+        vec![
+            row(b"AAACCCAAGAAACACT", "CD3", "CD3, T cells", 120),
+            row(b"AAACCCAAGAAACACT", "CD19", "CD19 \"B\"", 3),
+            row(b"AAACCCAAGAAACCAT-2", "CD3", "CD3, T cells", 7),
+            row(b"AAACCCAAGAAACCAT-2", "CD19", "CD19 \"B\"", 1_000_000),
+        ]
+    }
+
+    #[test]
+    fn long_csv_round_trip() {
+        let mut csv = Vec::new();
+        write_long_csv(&mut csv, rows()).unwrap();
+        let read = read_long_csv(&csv[..]).unwrap();
+        assert_eq!(read.len(), 4);
+        for (row, back) in rows().iter().zip(&read) {
+            assert_eq!(back.cell.as_bytes(), &row.cell[..]);
+            assert_eq!(back.feature_id, row.feature_id);
+            assert_eq!(back.feature, row.feature);
+            assert_eq!(back.count, row.count);
+        }
+    }
+
+    #[test]
+    fn resummarize_sums_the_cells_above_min_reads() {
+        let mut csv = Vec::new();
+        write_long_csv(&mut csv, rows()).unwrap();
+        let totals = resummarize(&read_long_csv(&csv[..]).unwrap(), 5);
+        let total = |id: &str, name: &str, count, cells| FeatureTotal {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            count,
+            cells,
+        };
+        assert_eq!(totals, [total("CD19", "CD19 \"B\"", 1_000_000, 1), total("CD3", "CD3, T cells", 127, 2)]);
+
+        let mut out = Vec::new();
+        write_totals_csv(&mut out, &resummarize(&read_long_csv(&csv[..]).unwrap(), 200)).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "id,name,count,cells,reads_per_cell\nCD19,\"CD19 \"\"B\"\"\",1000000,1,1000000\nCD3,\"CD3, T cells\",0,0,NA\n"
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use memchr::memchr;
+
+/// Initial size of the read buffer, grown for longer records
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// FastQ records parsed in place in a read buffer. A malformed record does not stop the
+/// parser: it is returned with the reason and parsing continues with the next four lines.
+pub struct Records {
+    reader: Box<dyn Read>,
+    path: PathBuf,
+    buffer: Vec<u8>,
+    /// Start of the unparsed data in the buffer
+    start: usize,
+    /// End of the data in the buffer
+    end: usize,
+    /// Header, sequence, separator and quality line of the current record, without line ends
+    lines: [(usize, usize); 4],
+    /// 1-based number of the current record
+    number: usize,
+    /// Why the current record is not valid FastQ
+    malformed: Option<&'static str>,
+    /// Records to pass before the next record, 1 between the reads of an interleaved file
+    skip: usize,
+    /// Records passed between reads
+    stride: usize,
+}
+
+impl Records {
+    pub fn new<P: AsRef<Path>>(reader: Box<dyn Read>, path: P) -> Self {
+        Records {
+            reader,
+            path: path.as_ref().to_owned(),
+            buffer: vec![0; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+            lines: [(0, 0); 4],
+            number: 0,
+            malformed: None,
+            skip: 0,
+            stride: 0,
+        }
+    }
+
+    /// Only the read 1 (first) or read 2 (second) records of an interleaved file, where the
+    /// records of a pair follow each other
+    pub fn interleaved(self, second: bool) -> Self {
+        Records {
+            skip: second as usize,
+            stride: 1,
+            ..self
+        }
+    }
+
+    /// Parse the next record, false at the end of the file
+    pub fn advance(&mut self) -> io::Result<bool> {
+        for _ in 0..self.skip {
+            if !self.next()? {
+                return Ok(false);
+            }
+        }
+        self.skip = self.stride;
+        self.next()
+    }
+
+    /// Whether no record follows the current one. The current record is no longer valid.
+    pub fn at_end(&mut self) -> io::Result<bool> {
+        loop {
+            while self.start < self.end && matches!(self.buffer[self.start], b'\n' | b'\r') {
+                self.start += 1;
+            }
+            if self.start < self.end {
+                return Ok(false);
+            }
+            if !self.fill()? {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn next(&mut self) -> io::Result<bool> {
+        loop {
+            // skip blank lines between the records
+            while self.start < self.end && matches!(self.buffer[self.start], b'\n' | b'\r') {
+                self.start += 1;
+            }
+
+            if let Some(next) = self.parse() {
+                self.start = next;
+                self.number += 1;
+                self.malformed = self.check();
+                return Ok(true);
+            }
+
+            if !self.fill()? {
+                if self.start == self.end {
+                    return Ok(false);
+                }
+                if self.buffer[self.end - 1] != b'\n' {
+                    // the last line has no line end
+                    self.buffer[self.end] = b'\n';
+                    self.end += 1;
+                    continue;
+                }
+                self.start = self.end;
+                self.number += 1;
+                self.lines = [(self.end, self.end); 4];
+                self.malformed = Some("truncated record");
+                return Ok(true);
+            }
+        }
+    }
+
+    /// The read name of the current record, the header up to the first whitespace without a
+    /// /1 or /2 read number
+    pub fn name(&self) -> &[u8] {
+        let (start, end) = self.lines[0];
+        let header = &self.buffer[start..end];
+        let header = header.strip_prefix(b"@").unwrap_or(header);
+        let name = header.split(|b| b.is_ascii_whitespace()).next().unwrap_or(header);
+        match name {
+            [name @ .., b'/', b'1' | b'2'] => name,
+            _ => name,
+        }
+    }
+
+    /// The header line of the current record without the @, including the comment
+    pub fn header(&self) -> &[u8] {
+        let (start, end) = self.lines[0];
+        let header = &self.buffer[start..end];
+        header.strip_prefix(b"@").unwrap_or(header)
+    }
+
+    /// The path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The sequence of the current record
+    pub fn seq(&self) -> &[u8] {
+        let (start, end) = self.lines[1];
+        &self.buffer[start..end]
+    }
+
+    /// The Phred+33 base qualities of the current record
+    pub fn qual(&self) -> &[u8] {
+        let (start, end) = self.lines[3];
+        &self.buffer[start..end]
+    }
+
+    /// Description of the problem with the current record, None for a valid record
+    pub fn malformed(&self) -> Option<String> {
+        self.malformed.map(|reason| self.describe(reason))
+    }
+
+    /// Description of a problem with the current record
+    pub fn describe(&self, reason: &str) -> String {
+        format!("record {} of {}: {reason}", self.number, self.path.display())
+    }
+
+    /// Find the four lines of a record from the start of the data, None when incomplete
+    fn parse(&mut self) -> Option<usize> {
+        let mut pos = self.start;
+        for line in &mut self.lines {
+            let n = memchr(b'\n', &self.buffer[pos..self.end])?;
+            let mut end = pos + n;
+            if n > 0 && self.buffer[end - 1] == b'\r' {
+                end -= 1;
+            }
+            *line = (pos, end);
+            pos += n + 1;
+        }
+        Some(pos)
+    }
+
+    fn check(&self) -> Option<&'static str> {
+        let line = |i: usize| &self.buffer[self.lines[i].0..self.lines[i].1];
+        if !line(0).starts_with(b"@") {
+            Some("header does not start with @")
+        } else if !line(2).starts_with(b"+") {
+            Some("sequence and quality not separated by +")
+        } else if line(1).len() != line(3).len() {
+            Some("sequence and quality lengths differ")
+        } else if !valid_bases(line(1)) {
+            Some("sequence has other characters than ACGTN")
+        } else {
+            None
+        }
+    }
+
+    /// Move the unparsed data to the front of the buffer and read more, growing the buffer
+    /// when it is full. False at the end of the file.
+    fn fill(&mut self) -> io::Result<bool> {
+        self.buffer.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+        // room for the line end of an unterminated last line
+        if self.end + 1 >= self.buffer.len() {
+            self.buffer.resize(2 * self.buffer.len(), 0);
+        }
+
+        loop {
+            match self.reader.read(&mut self.buffer[self.end..]) {
+                Ok(n) => {
+                    self.end += n;
+                    return Ok(n > 0);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Test if the sequence only has ACGTN. A fold instead of an early return, so it vectorizes.
+fn valid_bases(seq: &[u8]) -> bool {
+    seq.iter()
+        .fold(true, |valid, b| valid & matches!(b, b'A' | b'C' | b'G' | b'T' | b'N'))
+}
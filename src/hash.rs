@@ -0,0 +1,22 @@
+//! Hash maps and sets keyed by the data read from the input files. The hasher is chosen at
+//! build time: ahash by default, the `fxhash` feature for the fastest hashing of trusted input,
+//! or the `siphash` feature for the randomly keyed std hasher, which resists hash flooding by
+//! hostile input. `siphash` takes precedence when both are enabled.
+
+#[cfg(feature = "siphash")]
+pub type BuildHasher = std::collections::hash_map::RandomState;
+#[cfg(all(feature = "fxhash", not(feature = "siphash")))]
+pub type BuildHasher = rustc_hash::FxBuildHasher;
+#[cfg(not(any(feature = "fxhash", feature = "siphash")))]
+pub type BuildHasher = ahash::RandomState;
+
+pub type HashMap<K, V> = std::collections::HashMap<K, V, BuildHasher>;
+pub type HashSet<T> = std::collections::HashSet<T, BuildHasher>;
+
+/// Name of the hasher in use
+#[cfg(feature = "siphash")]
+pub const HASHER: &str = "siphash";
+#[cfg(all(feature = "fxhash", not(feature = "siphash")))]
+pub const HASHER: &str = "fxhash";
+#[cfg(not(any(feature = "fxhash", feature = "siphash")))]
+pub const HASHER: &str = "ahash";
@@ -0,0 +1,129 @@
+pub mod bam;
+pub mod barcodes;
+pub mod cellcodes;
+pub mod checkpoint;
+pub mod chemistry;
+pub mod checksum;
+pub mod classify;
+pub mod combine;
+pub mod complexity;
+pub mod counts;
+pub mod database;
+pub mod demux;
+pub mod discover;
+pub mod expected;
+pub mod export;
+pub mod fastq;
+pub mod hash;
+pub mod live;
+pub mod manifest;
+pub mod motif;
+pub mod reader;
+pub mod remote;
+pub mod report;
+pub mod saturation;
+pub mod screen;
+pub mod spikein;
+pub mod structure;
+pub mod throughput;
+pub mod warnings;
+pub mod whitelist;
+
+use std::borrow::Cow;
+
+/// Length of a 10x cell code, the default
+pub const CCLENGTH: usize = 16;
+/// Longest cell code, set with --cc-length
+pub const MAX_CCLENGTH: usize = 30;
+/// Length of a TotalSeq feature barcode, the default
+pub const BCLENGTH: usize = 15;
+/// Longest feature barcode, set with --bc-length
+pub const MAX_BCLENGTH: usize = 31;
+pub const UMILENGTH: usize = 12;
+
+/// A cell code, padded with zeros after the cell code length, followed by its GEM well
+pub type CellCode = [u8; MAX_CCLENGTH + 1];
+/// A feature barcode, padded with zeros after the barcode length
+pub type Barcode = [u8; MAX_BCLENGTH];
+pub type Umi = [u8; UMILENGTH];
+pub type BarcodeRef = usize;
+/// Compact barcode reference used in the per cell counts
+pub type CompactRef = u16;
+
+/// Maximum number of reference barcodes, limited by the compact references
+pub const MAX_BARCODES: usize = CompactRef::MAX as usize + 1;
+
+/// The bases of a cell code, without the padding
+pub fn cell_bases(cellcode: &CellCode) -> &[u8] {
+    let n = cellcode[..MAX_CCLENGTH].iter().position(|&b| b == 0).unwrap_or(MAX_CCLENGTH);
+    &cellcode[..n]
+}
+
+/// The GEM well of a cell code of an aggregated library, kept in the byte after the longest
+/// cell code, 0 when the counts are not partitioned by GEM well
+pub fn gem_well(cellcode: &CellCode) -> u8 {
+    cellcode[MAX_CCLENGTH]
+}
+
+/// The cell code as written in the outputs, its bases followed by the -N suffix of its GEM well
+pub fn cell_name(cellcode: &CellCode) -> Cow<'_, [u8]> {
+    match gem_well(cellcode) {
+        0 => Cow::Borrowed(cell_bases(cellcode)),
+        well => Cow::Owned([cell_bases(cellcode), format!("-{well}").as_bytes()].concat()),
+    }
+}
+
+/// The bases of a feature barcode, without the padding
+pub fn barcode_bases(barcode: &Barcode) -> &[u8] {
+    let n = barcode.iter().position(|&b| b == 0).unwrap_or(MAX_BCLENGTH);
+    &barcode[..n]
+}
+
+/// The complementary base, other characters are kept
+pub fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        other => other,
+    }
+}
+
+/// The feature barcode of these bases, None when longer than the longest barcode
+pub fn to_barcode(bases: &[u8]) -> Option<Barcode> {
+    let mut barcode = [0; MAX_BCLENGTH];
+    barcode.get_mut(..bases.len())?.copy_from_slice(bases);
+    Some(barcode)
+}
+
+/// A uniform draw in [0, 1) for the i-th read pair, reproducible for a seed, by the splitmix64
+/// finalizer
+pub fn uniform(seed: u64, i: u64) -> f64 {
+    let mix = |mut z: u64| {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    // seeds far apart in the sequence, so seeds differing by one are not shifted draws
+    let z = mix(seed).wrapping_add(i).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (mix(z) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_cell_code_keeps_its_gem_well_apart() {
+        let mut cellcode: CellCode = [b'A'; MAX_CCLENGTH + 1];
+        cellcode[MAX_CCLENGTH] = 0;
+        assert_eq!(cell_bases(&cellcode).len(), MAX_CCLENGTH);
+        assert_eq!(gem_well(&cellcode), 0);
+        assert_eq!(&*cell_name(&cellcode), &[b'A'; MAX_CCLENGTH][..]);
+
+        cellcode[MAX_CCLENGTH] = 2;
+        assert_eq!(cell_bases(&cellcode).len(), MAX_CCLENGTH);
+        assert_eq!(&*cell_name(&cellcode), [&[b'A'; MAX_CCLENGTH][..], b"-2"].concat().as_slice());
+    }
+}
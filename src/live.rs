@@ -0,0 +1,54 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use termion::AsyncReader;
+
+/// Set by the SIGUSR1 handler, cleared when the request is handled
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigusr1(_: libc::c_int) {
+    SIGNALLED.store(true, Ordering::Relaxed);
+}
+
+/// Requests for the full table of the counts so far while counting: a SIGUSR1 signal, or Enter
+/// on the terminal
+pub struct Requests {
+    keys: Option<AsyncReader>,
+}
+
+impl Requests {
+    /// Handle SIGUSR1, and with keys the Enter key on the terminal. The terminal is read by a
+    /// thread, so a FastQ file on the standard input is not affected.
+    pub fn listen(keys: bool) -> Self {
+        let handler = on_sigusr1 as extern "C" fn(libc::c_int);
+        unsafe {
+            libc::signal(libc::SIGUSR1, handler as libc::sighandler_t);
+        }
+        Requests {
+            keys: (keys && termion::get_tty().is_ok()).then(termion::async_stdin),
+        }
+    }
+
+    /// Whether the table was requested since the last call
+    pub fn requested(&mut self) -> bool {
+        let mut requested = SIGNALLED.swap(false, Ordering::Relaxed);
+        if let Some(keys) = &mut self.keys {
+            let mut buf = [0; 64];
+            while let Ok(n @ 1..) = keys.read(&mut buf) {
+                requested |= buf[..n].contains(&b'\n');
+            }
+        }
+        requested
+    }
+}
+
+/// Replace the current terminal line with the status
+pub fn print_status(status: &str) {
+    print!("\r{}{status}", termion::clear::CurrentLine);
+    io::stdout().flush().ok();
+}
+
+/// End the status line, so the next output starts on a line of its own
+pub fn end_status() {
+    println!("\r{}", termion::clear::CurrentLine);
+}
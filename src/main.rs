@@ -1,43 +1,226 @@
+use std::borrow::Cow;
 use std::fs::File;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use ahash::AHashSet as HashSet;
 use anyhow::Result;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use flate2::write::GzEncoder;
+use regex::bytes::Regex;
+use serde::Serialize;
 
-mod barcodes;
-mod counts;
-mod reader;
-mod whitelist;
+use featureseek::barcodes::{Barcodes, MatchResult, Precedence, Scratch};
+use featureseek::checkpoint::{self, Checkpoint, Resumed};
+use featureseek::chemistry::{self, Chemistry, Evidence};
+use featureseek::classify::{self, Classifier};
+use featureseek::combine::Combined;
+use featureseek::counts::{Counts, Dedup, Summary};
+use featureseek::expected::{fractions_table, Expected};
+use featureseek::hash::{HashMap, HashSet};
+use featureseek::manifest::{Host, Manifest};
+use featureseek::motif::Motif;
+use featureseek::reader::{Compression, Pairing, ShortReads};
+use featureseek::saturation::{self, Saturation};
+use featureseek::screen::{Roles, Screen};
+use featureseek::spikein::SpikeIns;
+use featureseek::structure::{Preset, Structure};
+use featureseek::throughput::{Throughput, ThroughputSummary};
+use featureseek::whitelist::{self, Distances, HitRate, Whitelist};
+use featureseek::{cellcodes, checksum, database, demux, discover, export, live, reader, report};
+use featureseek::{barcode_bases, cell_bases, cell_name, CellCode, BCLENGTH, MAX_BCLENGTH, MAX_CCLENGTH, UMILENGTH};
 
-use barcodes::{Barcodes, MatchResult};
-use counts::{Counts, Summary};
-use whitelist::Whitelist;
+/// Exit code when the input contained no reads
+pub const EXIT_NO_READS: i32 = 2;
+/// Exit code when no barcode passed the thresholds
+pub const EXIT_NO_FEATURES: i32 = 3;
 
-pub const CCLENGTH: usize = 16;
-pub const BCLENGTH: usize = 15;
-
-pub type CellCode = [u8; CCLENGTH];
-pub type Barcode = [u8; BCLENGTH];
-pub type BarcodeRef = usize;
+/// Number of whitelist checks per whitelist hit rate window
+const HIT_RATE_WINDOW: usize = 1_000_000;
+/// Number of cell codes of the first reads with their distance to the whitelist reported
+const WHITELIST_DISTANCE_READS: usize = 10_000;
+/// Warn when the whitelist hit rate of a window drops by more than this fraction
+const HIT_RATE_TOLERANCE: f64 = 0.1;
+/// Warn when the read pairs counted differ by more than this fraction from the demultiplexed
+const DEMUX_TOLERANCE: f64 = 0.001;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    config: Option<Config>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verify that the inputs of earlier results have not changed.
+    Verify {
+        /// Result directory or input checksum file
+        path: PathBuf,
+    },
+    /// Run the full pipeline on bundled test data to verify the installation.
+    Selftest,
+    /// Replay a run with the arguments of its manifest, after verifying that the inputs have
+    /// not changed.
+    Rerun {
+        /// Manifest written with --manifest
+        manifest: PathBuf,
+    },
+    /// Combine the JSON summaries of multiple samples into one report.
+    Combine {
+        /// Sample sheet csv file with sample and json columns
+        samplesheet: PathBuf,
+
+        /// Write the features by samples counts and cells to a csv file
+        #[arg(long, value_name = "FILE")]
+        csv: Option<PathBuf>,
+
+        /// Write an HTML report with a tab per sample
+        #[arg(long, value_name = "FILE")]
+        html: Option<PathBuf>,
+
+        /// Write the counts per million reads and slopes over the concentration series to a csv file
+        #[arg(long, value_name = "FILE")]
+        titration: Option<PathBuf>,
+    },
+    /// Look up the counts of a cell or a feature in a results database.
+    Query {
+        /// SQLite results database written with --sqlite
+        database: PathBuf,
+
+        /// Print the feature counts of this cell code
+        #[arg(long, required_unless_present = "feature")]
+        cell: Option<String>,
+
+        /// Print the cell count distribution of this feature id or name
+        #[arg(long, conflicts_with = "cell")]
+        feature: Option<String>,
+    },
+    /// Check a feature reference for Cell Ranger and downstream tools, with a message per
+    /// problem row.
+    CheckRef {
+        /// TotalSeq csv files with the antibody barcodes, combined as with --csv
+        #[arg(required = true)]
+        csv: Vec<PathBuf>,
+
+        /// Length of the feature barcode
+        #[arg(long, value_name = "N", default_value_t = BCLENGTH as u8, value_parser = clap::value_parser!(u8).range(1..=MAX_BCLENGTH as i64))]
+        bc_length: u8,
+    },
+    /// Write a cell code whitelist as a packed binary index, which --whitelist reads faster
+    /// than the text file.
+    Index {
+        /// Whitelist with one cell code per line
+        whitelist: PathBuf,
+
+        /// Index file to write
+        #[arg(long, short, value_name = "FILE")]
+        out: PathBuf,
+    },
+    /// Print the whitelist search directories, with the whitelists in them, and what the
+    /// whitelist names resolve to.
+    Config {
+        /// Print the configuration
+        #[arg(long, required = true)]
+        show: bool,
+
+        /// Also resolve this whitelist as --whitelist does
+        #[arg(long, value_name = "NAME")]
+        whitelist: Option<PathBuf>,
+    },
+    /// Sum the counts per feature of a --long table written as CSV, as in the summary of the
+    /// run, and print them as CSV.
+    Resummarize {
+        /// Long format count table written with --long to a .csv file
+        long: PathBuf,
+
+        /// Minimum barcode reads per cell code, as in the run.
+        #[arg(long, short = 'b', value_name = "B", default_value_t = 5)]
+        min_reads: usize,
+    },
+}
+
+#[derive(Args, Debug, Serialize)]
 struct Config {
-    /// Provide the TotalSeq csv file with the antibody barcodes
-    #[arg(long)]
-    csv: PathBuf,
+    /// Provide the TotalSeq csv file with the antibody barcodes. Repeat to combine the
+    /// references of multiple files.
+    #[arg(long, required = true)]
+    csv: Vec<PathBuf>,
+
+    /// How a sequence on more than one reference row is resolved: the first row in the order
+    /// of the --csv files is used, fail, or the first row is used with the ids and names of
+    /// all rows merged.
+    #[arg(long, value_name = "PRECEDENCE", value_enum, default_value_t)]
+    duplicate_sequences: Precedence,
+
+    /// Only count these features of the reference, by id or name. The reads of the other
+    /// features are counted as ignored.
+    #[arg(long, value_name = "NAME,NAME,...", value_delimiter = ',', conflicts_with = "exclude_features")]
+    use_features: Vec<String>,
+
+    /// Do not count these features of the reference, by id or name. Their reads are counted as
+    /// ignored.
+    #[arg(long, value_name = "NAME,NAME,...", value_delimiter = ',')]
+    exclude_features: Vec<String>,
 
     /// Provide the 10X barcodes whitelist file
     #[arg(long, value_name = "FILE")]
     whitelist: Option<PathBuf>,
 
-    /// The feature barcode read 1 FastQ file containing the cell codes.
-    r1: PathBuf,
+    /// The feature barcode read 1 FastQ file containing the cell codes, `-` for the standard
+    /// input.
+    #[arg(
+        required_unless_present_any = ["r1_lanes", "fastq_dir", "interleaved", "bam", "single"],
+        conflicts_with_all = ["r1_lanes", "fastq_dir", "interleaved", "bam", "single"],
+        requires = "r2"
+    )]
+    r1: Option<PathBuf>,
+
+    /// The feature barcode read 2 FastQ file containing the barcodes, `-` for the standard
+    /// input.
+    #[arg(conflicts_with = "r2_lanes")]
+    r2: Option<PathBuf>,
+
+    /// Read 1 FastQ files of multiple lanes, counted together.
+    /// Paired in order with the --r2 files, instead of the R1 and R2 arguments.
+    #[arg(long = "r1", value_name = "FILE", num_args = 1.., requires = "r2_lanes")]
+    r1_lanes: Vec<PathBuf>,
+
+    /// Read 2 FastQ files of multiple lanes, in the order of the --r1 files.
+    #[arg(long = "r2", value_name = "FILE", num_args = 1.., requires = "r1_lanes")]
+    r2_lanes: Vec<PathBuf>,
+
+    /// Directory with the FastQ files of all lanes, named <sample>_S1_L001_R1_001.fastq.gz
+    /// as written by bcl2fastq. The read 1 and read 2 files are paired by name.
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["r2", "r1_lanes", "r2_lanes"])]
+    fastq_dir: Option<PathBuf>,
+
+    /// A single FastQ file with the read 1 and read 2 records of each pair following each
+    /// other, instead of the R1 and R2 arguments.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["r2", "r1_lanes", "r2_lanes", "fastq_dir"])]
+    interleaved: Option<PathBuf>,
+
+    /// A BAM file instead of FastQ files: a 10x BAM, as written by cellranger, with the
+    /// corrected cell code taken from the CB tag, or an unaligned BAM of the read pairs.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["r2", "r1_lanes", "r2_lanes", "fastq_dir", "interleaved"])]
+    bam: Option<PathBuf>,
+
+    /// A single FastQ file with both the cell code and the feature barcode in each record, for
+    /// single-read libraries. Needs a --structure with all codes in R1, as R1:16C12U10X15B.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "structure",
+        conflicts_with_all = ["r2", "r1_lanes", "r2_lanes", "fastq_dir", "interleaved", "bam", "swap", "auto_swap", "cb_anchor", "bc_anchor", "cb_from_name"]
+    )]
+    single: Option<PathBuf>,
 
-    /// The feature barcode read 2 FastQ file containing the barcodes.
-    r2: PathBuf,
+    /// Only use the FastQ files of this sample in the --fastq-dir directory.
+    #[arg(long, value_name = "NAME", requires = "fastq_dir")]
+    sample: Option<String>,
 
     /// Minimum barcode reads per cellcode.
     /// Only count the barcodes that are found more than <B> times for a cell code.
@@ -58,111 +241,1731 @@ struct Config {
     #[arg(long, short = 'o')]
     out: Option<PathBuf>,
 
+    /// Print the 90th and 99th percentile, background mode and threshold of the per cell
+    /// counts of each barcode.
+    #[arg(long)]
+    quantiles: bool,
+
+    /// Method to separate the background from the signal cells of a barcode for the
+    /// presence calls and thresholds.
+    #[arg(long, value_enum, default_value_t)]
+    classifier: Classifier,
+
+    /// Strict Cell Ranger compatibility.
+    /// Fail before counting when the reference, and so the output CSV, would not be accepted
+    /// by `cellranger count`.
+    #[arg(long)]
+    strict_cellranger: bool,
+
+    /// Count at most N reads of each (cell, barcode) pair, to damp PCR jackpots when the UMIs
+    /// are not used.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    cap_reads_per_cell_feature: Option<u32>,
+
+    /// Write the per cell presence calls of the accepted barcodes to a CSV file.
+    #[arg(long, value_name = "FILE")]
+    calls: Option<PathBuf>,
+
+    /// Write the most likely barcode of each cell to a CSV file, with its posterior against the
+    /// estimated ambient background and the runner-up.
+    #[arg(long, value_name = "FILE")]
+    assignments: Option<PathBuf>,
+
+    /// Write the (cell, feature, count) table of the cells with a barcode above min_reads.
+    /// Written as CSV for .csv files, as Arrow IPC for .arrow, .ipc or .feather files and as
+    /// Parquet otherwise.
+    #[arg(long, value_name = "FILE")]
+    long: Option<PathBuf>,
+
+    /// Collapse the cell codes within one mismatch of a code with at least 10 times the reads
+    /// into that code, an alternative to whitelist correction.
+    #[arg(long)]
+    collapse_cells: bool,
+
+    /// Write the cell codes ranked by reads to a CSV file, with the more frequent cell code
+    /// within one mismatch for likely sequencing errors.
+    #[arg(long, value_name = "FILE")]
+    cells: Option<PathBuf>,
+
+    /// Write the saturation curve to a CSV file: the assigned reads, the cells with a barcode
+    /// above min_reads and the distinct UMIs of read subsamples, taken in the same pass.
+    #[arg(long, value_name = "FILE", conflicts_with = "bulk")]
+    saturation: Option<PathBuf>,
+
+    /// Read fractions of the saturation curve subsamples.
+    #[arg(long, value_name = "F,F,...", value_delimiter = ',', value_parser = parse_fraction, default_values_t = saturation::FRACTIONS, requires = "saturation")]
+    saturation_fractions: Vec<f64>,
+
+    /// Write a JSON summary of the run.
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+
+    /// Write the parameters, read counters, features and per cell counts to a new SQLite
+    /// database.
+    #[arg(long, value_name = "FILE")]
+    sqlite: Option<PathBuf>,
+
+    /// Write a manifest of the arguments, input checksums, detected chemistry, version and
+    /// host of the run, to replay it with `featureseek rerun`.
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
     /// Barcode ignore list.
     #[arg(long, short = 'x', value_name = "BC,BC,...", value_parser = parse_ignores, default_value = "GGGGGGGGGGGGGGG,CCTAATGGTCCAGAC")]
+    #[serde(serialize_with = "serialize_ignores")]
     ignore: HashSet<Vec<u8>>,
 
+    /// Discard the reads with this motif, in IUPAC codes, in the feature barcode window.
+    /// Can be repeated.
+    #[arg(long, value_name = "IUPAC", value_parser = Motif::parse)]
+    reject_motif: Vec<Motif>,
+
     /// Count unknown.
     /// Count the barcodes not matching to the reference and summarize at end.
     #[arg(long, short = 'u')]
     unknown: bool,
 
+    /// Full distance scan.
+    /// Search every read in the BK-tree, also when an exact match exists, and report the
+    /// distance distribution of the accepted reads. This is slower.
+    #[arg(long)]
+    full_distance_scan: bool,
+
+    /// Maximum number of tracked unknown barcodes.
+    /// When more distinct unknown barcodes are found the least frequent ones are evicted and
+    /// the unknown summary becomes approximate.
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    max_unknown: usize,
+
+    /// Maximum number of tracked cell codes.
+    /// When more distinct cell codes are found the cells with the lowest read counts are
+    /// evicted. This protects against running out of memory on corrupt libraries.
+    #[arg(long, value_name = "N")]
+    max_cells: Option<usize>,
+
     /// Approximate matching.
     /// Count the barcodes allowing a levenshtein distance up to 2 to the reference.
     #[arg(long, short = 'a')]
     approximate: bool,
+
+    /// List the N most frequent read sequences corrected into each barcode by approximate
+    /// matching, to audit the corrections.
+    #[arg(long, value_name = "N", requires = "approximate")]
+    corrections: Option<usize>,
+
+    /// When the feature barcode at its position has no hit, search the whole read for a
+    /// reference barcode, exactly or within distance 1, and count the read when a single
+    /// barcode is found. The reads rescued are reported per shift from the position.
+    #[arg(long)]
+    scan_read2: bool,
+
+    /// Estimate the bleed through between nearby barcodes from the ambiguous reads nearest
+    /// to one barcode and within distance 2 of another.
+    #[arg(long, requires = "approximate")]
+    bleed: bool,
+
+    /// Track the UMIs following the cell codes and report the reads per UMI of each barcode,
+    /// to tell PCR over-amplification from abundance.
+    #[arg(long)]
+    umi_stats: bool,
+
+    /// Count the distinct UMIs of each cell and barcode instead of the reads, as Cell Ranger
+    /// does. The thresholds then apply to the UMI counts.
+    #[arg(long, conflicts_with = "cap_reads_per_cell_feature")]
+    umi: bool,
+
+    /// Merge the UMIs within one mismatch of a UMI with at least twice the reads, minus one,
+    /// of the same cell and barcode before counting them, as the directional method of
+    /// UMI-tools.
+    #[arg(long, requires = "umi")]
+    umi_directional: bool,
+
+    /// Drop the read pairs duplicating an earlier read pair of the same cell, as optical or
+    /// ExAmp duplicates: with the same feature barcode and UMI, or the same read sequences.
+    #[arg(long, value_name = "MODE")]
+    dedup: Option<Dedup>,
+
+    /// Spike-in csv file with the id and input molecules of known barcodes. Estimates the
+    /// capture efficiency and the molecules of all barcodes.
+    #[arg(long, value_name = "FILE")]
+    spike_ins: Option<PathBuf>,
+
+    /// Csv file with the feature, id or name, and expected_fraction of the features in the
+    /// pool. Reports the observed versus expected fractions over these features, flagging
+    /// those outside --expected-tolerance.
+    #[arg(long, value_name = "FILE")]
+    expected: Option<PathBuf>,
+
+    /// Largest fold deviation of an observed from an expected fraction, in either direction
+    #[arg(long, value_name = "FOLD", default_value_t = 1.5, value_parser = parse_fold, requires = "expected")]
+    expected_tolerance: f64,
+
+    /// Positions of the codes in the reads, as a number of bases followed by C for the cell
+    /// code, U for the UMI, B for the feature barcode or X for skipped bases. By default
+    /// detected from the read 1 length: R1:16C12U,R2:10X15B or R1:16C10U,R2:10X15B.
+    #[arg(long, value_name = "STRUCTURE")]
+    structure: Option<Structure>,
+
+    /// Library preset setting the positions of the codes. The UMI length is still detected
+    /// from the read 1 length, except for 5p, which also finds the 737K-august-2016 whitelist
+    /// when none is given.
+    #[arg(long, value_enum, value_name = "PRESET", conflicts_with = "structure")]
+    chemistry: Option<Preset>,
+
+    /// Length of the cell code at the start of read 1, for other platforms than 10x. The UMI
+    /// follows the cell code.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=MAX_CCLENGTH as i64), conflicts_with = "structure")]
+    cc_length: Option<u8>,
+
+    /// 0-based start of the feature barcode in read 2.
+    #[arg(long, value_name = "N", conflicts_with = "structure")]
+    bc_offset: Option<usize>,
+
+    /// Length of the feature barcode. The reference sequences should all be of this length.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=MAX_BCLENGTH as i64), conflicts_with = "structure")]
+    bc_length: Option<u8>,
+
+    /// The cell code and UMI are in read 2 and the feature barcode in read 1, as in some custom
+    /// libraries. The positions of --structure then apply to the swapped reads.
+    #[arg(long)]
+    swap: bool,
+
+    /// Count read 1 and read 2 swapped when the first records have the cell codes and feature
+    /// barcodes in the other reads than expected, instead of only warning.
+    #[arg(long)]
+    auto_swap: bool,
+
+    /// Constant sequence preceding the cell code in read 1. The cell code is taken after the
+    /// first match of the anchor, allowing one mismatch, instead of at the start of the read.
+    #[arg(long, value_name = "SEQ", value_parser = parse_sequence)]
+    cb_anchor: Option<String>,
+
+    /// Constant sequence preceding the feature barcode in read 2, such as the capture sequence
+    /// or a linker. The feature barcode is taken after the first match of the anchor, allowing
+    /// one mismatch, so insertions upstream do not shift it out of frame. --bc-offset then
+    /// counts from the end of the anchor.
+    #[arg(long, value_name = "SEQ", value_parser = parse_sequence)]
+    bc_anchor: Option<String>,
+
+    /// Trim read 2 at this adapter sequence, or its reverse complement, allowing one mismatch:
+    /// tso for the 10x template switch oligo, nextera, truseq or a sequence. Read pairs trimmed
+    /// before the end of the feature barcode are counted as trimmed. Can be repeated.
+    #[arg(long, value_name = "ADAPTER", value_parser = parse_adapter)]
+    trim_adapter: Vec<String>,
+
+    /// Take the cell code from the read 1 header instead of the sequence, as the first group of
+    /// this regular expression or its whole match, for FastQ files with the cell code moved
+    /// into the read name. Read 1 is then only used for the UMI, which starts the cell code
+    /// length earlier.
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex, conflicts_with = "cb_anchor")]
+    cb_from_name: Option<String>,
+
+    /// Take the UMI from the read 1 header instead of the sequence, as the first group of this
+    /// regular expression or its whole match, for FastQ files with the UMI moved into the read
+    /// name, as by umi_tools extract.
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    umi_from_name: Option<String>,
+
+    /// Count the cells of each GEM well of an aggregated library apart, by the -N suffix of the
+    /// cell code: following the --cb-from-name match, or the CB:Z: tag in the read 1 header,
+    /// as written for a BAM file. The outputs name the cells with their suffix and the summary
+    /// lists the cells and reads per well.
+    #[arg(long, conflicts_with = "bulk")]
+    gem_wells: bool,
+
+    /// Bulk mode.
+    /// Ignore the cell codes and count the barcodes over the whole library. The output CSV
+    /// becomes a table of the id, name, barcode and count of the passing barcodes.
+    #[arg(long, conflicts_with_all = ["whitelist", "max_cells", "calls", "assignments", "quantiles", "cap_reads_per_cell_feature"])]
+    bulk: bool,
+
+    /// Count the read pairs with a malformed FastQ record and continue, instead of failing on
+    /// the first. Fails at the end when more than this fraction, 0.01 when not given, of the
+    /// reads is malformed.
+    #[arg(long, value_name = "FRACTION", num_args = 0..=1, require_equals = true, default_missing_value = "0.01")]
+    skip_malformed: Option<f64>,
+
+    /// What to do with a read pair too short to contain the cell code and feature barcode:
+    /// count it as too short, stop, or pad the reads with N and count the codes.
+    #[arg(long, value_name = "POLICY", value_enum, default_value_t)]
+    short_read_policy: ShortReads,
+
+    /// Compare the read names of read 1 and read 2 and stop at the first pair that differs,
+    /// or with =warn count the pairs that differ and warn at the end.
+    #[arg(long, value_name = "MODE", value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "error")]
+    check_pairing: Option<Pairing>,
+
+    /// The Demultiplex_Stats.csv of bcl-convert. Warns when the read pairs read differ from
+    /// those demultiplexed for the sample, of --sample or the FastQ file names, in the lanes
+    /// counted.
+    #[arg(long, value_name = "FILE")]
+    demux_stats: Option<PathBuf>,
+
+    /// Compression of the FastQ files, by default detected from the first bytes.
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t)]
+    compression: Compression,
+
+    /// Write the JSON summary of the reads counted so far to a gzipped file in this directory
+    /// every --snapshot-every million reads, to triage runs that do not finish.
+    #[arg(long, value_name = "DIR")]
+    snapshot_dir: Option<PathBuf>,
+
+    /// Million reads between the snapshots of --snapshot-dir.
+    #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..), requires = "snapshot_dir")]
+    snapshot_every: u64,
+
+    /// Write the counts and the position in the input to a checkpoint file every
+    /// --checkpoint-every million reads, to continue an interrupted run with --resume.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["saturation", "dedup"])]
+    checkpoint: Option<PathBuf>,
+
+    /// Million reads between the checkpoints of --checkpoint.
+    #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..), requires = "checkpoint")]
+    checkpoint_every: u64,
+
+    /// Continue after the reads of the --checkpoint file, written by an interrupted run with the
+    /// same arguments. Without the file counting starts at the beginning.
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+
+    /// On a SIGUSR1 signal write the JSON summary of the reads counted so far to this file,
+    /// instead of printing the table of the counts so far.
+    #[arg(long, value_name = "FILE")]
+    signal_json: Option<PathBuf>,
+
+    /// Show a status line with the reads counted so far while counting on a terminal. Enter,
+    /// or a SIGUSR1 signal, prints the table of the counts so far.
+    #[arg(long, conflicts_with = "fullscreen")]
+    status_line: bool,
+
+    /// Clear the terminal and redraw the table of the counts in place while counting.
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Print the read structure and the codes of the first reads without counting.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Stop after N read pairs, for a quick look at a fresh sequencing run. The summary then
+    /// states that the input was truncated.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_reads: Option<u64>,
+
+    /// Count a random fraction of the read pairs, for a quick look at the thresholds of a large
+    /// library. The same --seed selects the same read pairs.
+    #[arg(long, value_name = "F", value_parser = parse_fraction)]
+    subsample: Option<f64>,
+
+    /// Seed of the --subsample draws.
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "subsample")]
+    seed: u64,
+}
+
+impl Config {
+    /// The arrangement of the reads in the input files
+    fn layout(&self) -> reader::Layout {
+        if self.bam.is_some() {
+            reader::Layout::Bam
+        } else if self.interleaved.is_some() {
+            reader::Layout::Interleaved
+        } else if self.single.is_some() {
+            reader::Layout::Single
+        } else {
+            reader::Layout::Paired
+        }
+    }
+
+    /// The read 1 and read 2 FastQ files of each lane
+    fn fastq_pairs(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        if let Some(p) = self.interleaved.as_ref().or(self.bam.as_ref()).or(self.single.as_ref()) {
+            return Ok(vec![(p.clone(), p.clone())]);
+        }
+        if let Some(dir) = &self.fastq_dir {
+            return discover::fastq_pairs(dir, self.sample.as_deref());
+        }
+        if self.r1_lanes.len() != self.r2_lanes.len() {
+            anyhow::bail!(
+                "--r1 has {} files and --r2 has {}, expected a read 2 file for every read 1 file",
+                self.r1_lanes.len(),
+                self.r2_lanes.len()
+            );
+        }
+        match (&self.r1, &self.r2) {
+            (Some(r1), Some(r2)) => Ok(vec![(r1.clone(), r2.clone())]),
+            _ => Ok(self.r1_lanes.iter().cloned().zip(self.r2_lanes.iter().cloned()).collect()),
+        }
+    }
+}
+
+fn parse_sequence(s: &str) -> Result<String> {
+    if s.is_empty() || !s.bytes().all(|b| b"ACGT".contains(&b)) {
+        anyhow::bail!("expected a sequence of A, C, G and T");
+    }
+    Ok(s.to_owned())
+}
+
+/// An adapter sequence or the name of a common adapter
+fn parse_adapter(s: &str) -> Result<String> {
+    match s {
+        "tso" => Ok("AAGCAGTGGTATCAACGCAGAGTACATGGG".to_owned()),
+        "nextera" => Ok("CTGTCTCTTATACACATCT".to_owned()),
+        "truseq" => Ok("AGATCGGAAGAGC".to_owned()),
+        _ => parse_sequence(s).map_err(|_| anyhow::anyhow!("expected tso, nextera, truseq or a sequence of A, C, G and T")),
+    }
+}
+
+/// A fraction above 0 and at most 1
+fn parse_fraction(s: &str) -> Result<f64> {
+    match s.parse() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => anyhow::bail!("expected a fraction above 0 and at most 1"),
+    }
+}
+
+/// A fold change of at least 1
+fn parse_fold(s: &str) -> Result<f64> {
+    match s.parse() {
+        Ok(fold) if fold >= 1.0 => Ok(fold),
+        _ => anyhow::bail!("expected a fold of at least 1"),
+    }
+}
+
+fn parse_regex(s: &str) -> Result<String> {
+    Regex::new(s)?;
+    Ok(s.to_owned())
 }
 
 fn parse_ignores(s: &str) -> Result<HashSet<Vec<u8>>> {
     Ok(s.split(',').map(|p| p.as_bytes().to_vec()).collect())
 }
 
+fn serialize_ignores<S: serde::Serializer>(
+    ignore: &HashSet<Vec<u8>>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    let mut ignore: Vec<_> = ignore.iter().map(|i| String::from_utf8_lossy(i)).collect();
+    ignore.sort();
+    s.collect_str(&ignore.join(","))
+}
+
 fn main() -> Result<()> {
-    let config = Config::parse();
-    let has_ignore = !config.ignore.is_empty();
+    let cli = Cli::parse();
+
+    let code = match (cli.command, cli.config) {
+        (Some(Command::Verify { path }), _) => {
+            if checksum::verify(path)? {
+                0
+            } else {
+                1
+            }
+        }
+        (Some(Command::Selftest), _) => selftest()?,
+        (Some(Command::Rerun { manifest }), _) => rerun(manifest)?,
+        (Some(Command::Combine { samplesheet, csv, html, titration }), _) => {
+            combine(samplesheet, csv, html, titration)?;
+            0
+        }
+        (Some(Command::Query { database, cell, feature }), _) => query(database, cell, feature)?,
+        (Some(Command::CheckRef { csv, bc_length }), _) => check_ref(&csv, bc_length)?,
+        (Some(Command::Index { whitelist, out }), _) => {
+            let n = Whitelist::from_path(whitelist::resolve(&whitelist)?)?.write_index(&out)?;
+            println!("Wrote {n} cell codes to {}", out.display());
+            0
+        }
+        (Some(Command::Config { whitelist, .. }), _) => show_config(whitelist.as_deref())?,
+        (Some(Command::Resummarize { long, min_reads }), _) => {
+            let rows = export::read_long_csv(File::open(long)?)?;
+            export::write_totals_csv(io::stdout().lock(), &export::resummarize(&rows, min_reads))?;
+            0
+        }
+        (None, Some(config)) => {
+            let args: Vec<_> = std::env::args_os().skip(1).map(|a| a.to_string_lossy().into_owned()).collect();
+            run(config, &args)?
+        }
+        (None, None) => unreachable!("clap requires the count arguments"),
+    };
+
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Write the cell codes ranked by decreasing reads, with the parent of likely errors
+fn write_cells<W: io::Write>(
+    w: W,
+    totals: &HashMap<CellCode, usize>,
+    parents: &HashMap<CellCode, CellCode>,
+) -> Result<()> {
+    let mut ranked: Vec<_> = totals.iter().collect();
+    ranked.sort_by_key(|&(cellcode, &reads)| (std::cmp::Reverse(reads), cellcode));
+
+    let mut writer = csv::Writer::from_writer(w);
+    writer.write_record(["cell", "reads", "rank", "error_of"])?;
+    for (rank, (cellcode, reads)) in ranked.into_iter().enumerate() {
+        writer.write_record([
+            &cell_name(cellcode),
+            reads.to_string().as_bytes(),
+            (rank + 1).to_string().as_bytes(),
+            &parents.get(cellcode).map_or(Cow::Borrowed(&[][..]), cell_name),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read pairs whose cell codes are looked up in the whitelist together
+const READ_BATCH: usize = 64;
+
+/// Time between the updates of the status line
+const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Number of read pairs shown by a dry run
+const DRY_RUN_READS: usize = 10;
+
+/// Write a snapshot of the summary as `snapshot_<reads>.json.gz`, through a temporary file so
+/// an interrupted write leaves no partial snapshot
+fn write_snapshot(dir: &Path, count: usize, report: &report::Report) -> Result<()> {
+    let path = dir.join(format!("snapshot_{count:012}.json.gz"));
+    let partial = path.with_extension("gz.partial");
+    let mut f = GzEncoder::new(File::create(&partial)?, flate2::Compression::default());
+    report.write(&mut f)?;
+    f.finish()?;
+    std::fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// Write the JSON summary to a file, replacing it at once so readers never see a partial file
+fn write_replacing(path: &Path, report: &report::Report) -> Result<()> {
+    let partial = path.with_extension("partial");
+    report.write(File::create(&partial)?)?;
+    std::fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// Print the read structure and the codes extracted from the first reads with their matches
+fn dry_run(mut reader: reader::Reader, barcodes: &Barcodes, ws: Option<&Whitelist>, approximate: bool) -> Result<i32> {
+    println!("Read structure\n{}", reader.structure_diagram());
+
+    let mut cc = [0u8; MAX_CCLENGTH + 1];
+    let mut bc = [0u8; MAX_BCLENGTH];
+    let mut scratch = Scratch::default();
+    for _ in 0..DRY_RUN_READS {
+        let Some(result) = reader.read_code(&mut cc, &mut bc) else {
+            break;
+        };
+        let extract = result?;
+        match extract {
+            reader::Extract::TooShort => {
+                println!("too short");
+                continue;
+            }
+            reader::Extract::NoAnchor => {
+                println!("cell code anchor not found");
+                continue;
+            }
+            reader::Extract::NoBcAnchor => {
+                println!("feature barcode anchor not found");
+                continue;
+            }
+            reader::Extract::NoNameMatch => {
+                println!("cell code not found in the read name");
+                continue;
+            }
+            reader::Extract::NoUmiNameMatch => {
+                println!("UMI not found in the read name");
+                continue;
+            }
+            reader::Extract::Trimmed => {
+                println!("trimmed at an adapter");
+                continue;
+            }
+            reader::Extract::Malformed => {
+                println!("malformed");
+                continue;
+            }
+            reader::Extract::Padded | reader::Extract::Complete => {}
+        }
+
+        let whitelisted = match ws {
+            Some(l) if !l.contains(cell_bases(&cc)) => " (not whitelisted)",
+            _ => "",
+        };
+        let padded = match extract {
+            reader::Extract::Padded => " (padded)",
+            _ => "",
+        };
+        let name = |pos: usize| barcodes.records[pos].get(1).unwrap().to_owned();
+        let hit = match barcodes.find(&bc, approximate, &mut scratch) {
+            MatchResult::Unique(pos) => name(pos),
+            MatchResult::Dist(pos, d) => format!("{} (distance {d})", name(pos)),
+            MatchResult::Multiple => "multiple".to_owned(),
+            MatchResult::NoHit => "no hit".to_owned(),
+        };
+        println!(
+            "{}  {}  {hit}{whitelisted}{padded}",
+            String::from_utf8_lossy(&cell_name(&cc)),
+            String::from_utf8_lossy(barcode_bases(&bc))
+        );
+    }
+    Ok(0)
+}
+
+/// Print the combined table of the samples in the sample sheet and write the requested outputs
+fn combine(
+    samplesheet: PathBuf,
+    csv: Option<PathBuf>,
+    html: Option<PathBuf>,
+    titration: Option<PathBuf>,
+) -> Result<()> {
+    let combined = Combined::from_samplesheet(samplesheet)?;
+    print!("{}", combined.table().display()?);
+    combined.print_reference_changes();
+    combined.print_size_factors();
+    combined.print_replicates();
+    combined.print_titration();
+
+    if let Some(csv) = csv {
+        combined.write_csv(File::create(csv)?)?;
+    }
+    if let Some(titration) = titration {
+        combined.write_titration(File::create(titration)?)?;
+    }
+    if let Some(html) = html {
+        combined.write_html(io::BufWriter::new(File::create(html)?))?;
+    }
+    Ok(())
+}
+
+/// Number of cells listed by a feature query
+const QUERY_TOP_CELLS: usize = 20;
+
+/// Print the counts of a cell or the cell distribution of a feature from a results database
+fn query(path: PathBuf, cell: Option<String>, feature: Option<String>) -> Result<i32> {
+    let (rows, what) = match (&cell, &feature) {
+        (Some(cell), _) => (database::query_cell(&path, cell)?, cell),
+        (None, Some(feature)) => (database::query_feature(&path, feature)?, feature),
+        (None, None) => unreachable!("clap requires a cell or feature"),
+    };
+    if rows.is_empty() {
+        println!("No counts found for {what}");
+        return Ok(1);
+    }
+
+    if feature.is_some() {
+        let mut counts: Vec<_> = rows.iter().map(|&(_, count)| count).collect();
+        counts.sort_unstable();
+        println!(
+            "{what}: {} cells, {} reads, median {:.1}, p90 {:.1}, p99 {:.1}",
+            counts.len(),
+            counts.iter().sum::<usize>(),
+            classify::quantile(&counts, 0.5),
+            classify::quantile(&counts, 0.9),
+            classify::quantile(&counts, 0.99)
+        );
+    }
+    let n = if feature.is_some() { QUERY_TOP_CELLS } else { rows.len() };
+    for (name, count) in rows.iter().take(n) {
+        println!("{name}\t{count}");
+    }
+    Ok(0)
+}
+
+/// Print the problems of the reference for Cell Ranger and downstream tools, and the sequences
+/// on more than one row. Exit code 1 with problems.
+fn check_ref(csv: &[PathBuf], bc_length: u8) -> Result<i32> {
+    let barcodes = Barcodes::from_csv(csv, Precedence::First, bc_length as usize)?;
+    let mut problems = barcodes.lint();
+    for d in &barcodes.duplicates {
+        problems.push(format!(
+            "sequence {} on the rows of ids {}, only {} is counted, remove the other rows",
+            d.sequence,
+            d.ids.join(", "),
+            d.kept
+        ));
+    }
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if problems.is_empty() {
+        println!("{} features, no problems found", barcodes.records.len());
+        Ok(0)
+    } else {
+        println!("{} features, {} problems found", barcodes.records.len(), problems.len());
+        Ok(1)
+    }
+}
+
+/// Print the whitelist search directories with the whitelists in them, the whitelists of the
+/// 10x chemistries and what a whitelist name resolves to
+fn show_config(name: Option<&Path>) -> Result<i32> {
+    match std::env::var_os(whitelist::WHITELIST_DIR_VAR) {
+        Some(var) => println!("{}={}", whitelist::WHITELIST_DIR_VAR, var.to_string_lossy()),
+        None => println!("{} is not set", whitelist::WHITELIST_DIR_VAR),
+    }
+    println!("Whitelist directories, searched in order for a --whitelist name:");
+    for (dir, source) in whitelist::search_dirs() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            println!("  {} ({source}, not found)", dir.display());
+            continue;
+        };
+        println!("  {} ({source})", dir.display());
+        let mut names: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+            .map(|e| e.file_name())
+            .collect();
+        names.sort();
+        for name in names {
+            println!("    {}", name.to_string_lossy());
+        }
+    }
+    println!("10x whitelists:");
+    for chemistry in [Chemistry::V2, Chemistry::V3, Chemistry::FivePrime] {
+        let name = chemistry.whitelist();
+        let found = whitelist::find(Path::new(name));
+        let found = found.map_or_else(|| "not found".to_owned(), |p| p.display().to_string());
+        println!("  {chemistry} {name}: {found}");
+    }
+    if let Some(p) = name {
+        println!("--whitelist {} resolves to {}", p.display(), whitelist::resolve(p)?.display());
+    }
+    Ok(0)
+}
+
+/// Test data and expected results for the selftest
+const SELFTEST_FILES: [(&str, &str); 4] = [
+    ("reference.csv", include_str!("../fixtures/selftest/reference.csv")),
+    ("whitelist.txt", include_str!("../fixtures/selftest/whitelist.txt")),
+    ("R1.fastq", include_str!("../fixtures/selftest/R1.fastq")),
+    ("R2.fastq", include_str!("../fixtures/selftest/R2.fastq")),
+];
+const SELFTEST_EXPECTED: &str = include_str!("../fixtures/selftest/expected.csv");
+const SELFTEST_EXPECTED_JSON: &str = include_str!("../fixtures/selftest/expected.json");
+const SELFTEST_ARGS: &[&str] = &[
+    "--csv=reference.csv",
+    "--whitelist=whitelist.txt",
+    "--min-cells=2",
+    "--approximate",
+    "--unknown",
+    "--out=out.csv",
+    "--json=out.json",
+    "R1.fastq",
+    "R2.fastq",
+];
+
+/// Count the bundled test data and compare the output with the expected results
+fn selftest() -> Result<i32> {
+    let dir = std::env::temp_dir().join(format!("featureseek-selftest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    for (name, content) in SELFTEST_FILES {
+        std::fs::write(dir.join(name), content)?;
+    }
+
+    let args: Vec<_> = SELFTEST_ARGS
+        .iter()
+        .map(|a| match a.split_once('=') {
+            Some((opt, file)) if opt != "--min-cells" => format!("{opt}={}", dir.join(file).display()),
+            _ if !a.starts_with('-') => dir.join(a).display().to_string(),
+            _ => a.to_string(),
+        })
+        .collect();
+    let config = Cli::try_parse_from(std::iter::once("featureseek".to_owned()).chain(args.clone()))?
+        .config
+        .expect("selftest arguments are count arguments");
+
+    let code = run(config, &args)?;
+    let out = std::fs::read_to_string(dir.join("out.csv"))?;
+    let json: serde_json::Value = serde_json::from_reader(File::open(dir.join("out.json"))?)?;
+    std::fs::remove_dir_all(&dir)?;
+
+    let expected_json: serde_json::Value = serde_json::from_str(SELFTEST_EXPECTED_JSON)?;
+    if code == 0 && out == SELFTEST_EXPECTED && json_contains(&json, &expected_json) {
+        println!("Selftest passed");
+        Ok(0)
+    } else {
+        println!("Selftest FAILED, expected output:\n{SELFTEST_EXPECTED}\nfound:\n{out}");
+        println!("expected summary:\n{expected_json:#}\nfound:\n{json:#}");
+        Ok(1)
+    }
+}
+
+/// Replay the run of a manifest in its directory, when the inputs and version are unchanged
+fn rerun(path: PathBuf) -> Result<i32> {
+    let manifest = Manifest::read(&path)?;
+    println!("{}:", path.display());
+    if !manifest.verify()? {
+        println!("The inputs of the run changed, not replaying it");
+        return Ok(1);
+    }
+
+    std::env::set_current_dir(&manifest.directory)?;
+    let cli = Cli::try_parse_from(std::iter::once("featureseek".to_owned()).chain(manifest.args.clone()))?;
+    let Some(config) = cli.config else {
+        anyhow::bail!("{}: not the arguments of a count run", path.display());
+    };
+    run(config, &manifest.args)
+}
+
+/// The arguments with the --whitelist value replaced by the resolved whitelist, added when it
+/// was found for the chemistry, so a replay reads the whitelist whose checksum was recorded
+fn with_whitelist(args: &[String], whitelist: Option<&Path>) -> Vec<String> {
+    let Some(whitelist) = whitelist else {
+        return args.to_vec();
+    };
+    let whitelist = format!("--whitelist={}", whitelist.display());
+    // options are not parsed after --
+    let (options, positionals) = args.split_at(args.iter().position(|a| a == "--").unwrap_or(args.len()));
+    let mut result = Vec::with_capacity(args.len() + 1);
+    let mut found = false;
+    let mut options = options.iter();
+    while let Some(arg) = options.next() {
+        if arg == "--whitelist" || arg.starts_with("--whitelist=") {
+            if arg == "--whitelist" {
+                options.next();
+            }
+            result.push(whitelist.clone());
+            found = true;
+        } else {
+            result.push(arg.clone());
+        }
+    }
+    if !found {
+        result.push(whitelist);
+    }
+    result.extend_from_slice(positionals);
+    result
+}
+
+/// Test if all fields of the expected JSON value are found in the value
+fn json_contains(value: &serde_json::Value, expected: &serde_json::Value) -> bool {
+    use serde_json::Value;
+
+    match (value, expected) {
+        (Value::Object(v), Value::Object(e)) => e
+            .iter()
+            .all(|(k, e)| v.get(k).is_some_and(|v| json_contains(v, e))),
+        (Value::Array(v), Value::Array(e)) => {
+            v.len() == e.len() && v.iter().zip(e).all(|(v, e)| json_contains(v, e))
+        }
+        _ => value == expected,
+    }
+}
+
+/// Count the reads of a configuration, with the arguments it was parsed from recorded in the
+/// manifest
+fn run(mut config: Config, args: &[String]) -> Result<i32> {
+    if config.bulk {
+        // the whole library is a single cell
+        config.min_cells = 0;
+    }
+    // a whitelist name is looked up in the whitelist directories, the path is recorded
+    if let Some(p) = &config.whitelist {
+        config.whitelist = Some(whitelist::resolve(p)?);
+    }
+    // 5' v2 is only checked against the whitelist, its 10x whitelist is used when none is given
+    let five_prime = config.chemistry.and_then(Preset::chemistry) == Some(Chemistry::FivePrime);
+    let found_whitelist = match &config.whitelist {
+        None if five_prime => whitelist::find(Path::new(Chemistry::FivePrime.whitelist())),
+        _ => None,
+    };
+    if found_whitelist.is_some() {
+        config.whitelist.clone_from(&found_whitelist);
+    }
 
     let tty = termion::is_tty(&io::stdout());
-    if tty {
+    let fullscreen = tty && config.fullscreen;
+    if fullscreen {
         println!("{}", termion::clear::All);
     }
 
-    // open the FastQ pair
-    let mut reader = reader::Reader::from_paths(&config.r1, &config.r2)?;
+    // open the FastQ pairs
+    let pairs = config.fastq_pairs()?;
+    let mut reader = match config.layout() {
+        reader::Layout::Paired => reader::Reader::from_pairs(&pairs, config.compression)?,
+        reader::Layout::Interleaved => reader::Reader::interleaved(&pairs[0].0, config.compression)?,
+        reader::Layout::Bam => reader::Reader::from_bam(&pairs[0].0, config.compression)?,
+        reader::Layout::Single => reader::Reader::single(&pairs[0].0, config.compression)?,
+    };
+    if let Some(anchor) = &config.cb_anchor {
+        reader = reader.with_cb_anchor(anchor.clone().into_bytes());
+    }
+    if let Some(anchor) = &config.bc_anchor {
+        reader = reader.with_bc_anchor(anchor.clone().into_bytes());
+    }
+    if let Some(pattern) = &config.cb_from_name {
+        reader = reader.with_cb_from_name(Regex::new(pattern)?);
+    }
+    if let Some(pattern) = &config.umi_from_name {
+        reader = reader.with_umi_from_name(Regex::new(pattern)?);
+    }
+    if config.gem_wells {
+        reader = reader.with_gem_wells();
+    }
+    if let Some(Dedup::Reads) = config.dedup {
+        reader = reader.with_pair_hashes();
+    }
+    if let Some(fraction) = config.subsample {
+        reader = reader.with_subsample(fraction, config.seed);
+    }
+    if config.scan_read2 {
+        reader = reader.with_barcode_reads();
+    }
+    if !config.trim_adapter.is_empty() {
+        let adapters: Vec<_> = config.trim_adapter.iter().map(|a| a.clone().into_bytes()).collect();
+        reader = reader.with_adapters(&adapters);
+    }
+    let structure = config.structure.unwrap_or_else(|| {
+        let mut structure = config.chemistry.map_or_else(Structure::default, Preset::structure);
+        if let Some(length) = config.cc_length {
+            structure = structure.with_cc_length(length as usize);
+        }
+        // the barcode directly follows an anchor
+        if let Some(offset) = config.bc_offset.or(config.bc_anchor.as_ref().map(|_| 0)) {
+            structure = structure.with_bc_start(offset);
+        }
+        if let Some(length) = config.bc_length {
+            structure = structure.with_bc_length(length as usize);
+        }
+        structure
+    });
+    if structure.single != config.single.is_some() {
+        anyhow::bail!(if structure.single {
+            "The feature barcode B is in R1 of --structure, expected a single-read FastQ file with --single"
+        } else {
+            "--single needs a --structure with the feature barcode B in R1, as R1:16C12U10X15B"
+        });
+    }
+    reader = reader.with_structure(structure);
+    if config.skip_malformed.is_some() {
+        reader = reader.with_skip_malformed();
+    }
+    if let Some(pairing) = config.check_pairing {
+        reader = reader.with_check_pairing(pairing);
+    }
+    reader = reader.with_short_reads(config.short_read_policy);
+
+    // the read pairs demultiplexed for the sample in the lanes counted
+    let demultiplexed = match &config.demux_stats {
+        Some(stats) => {
+            let Some(sample) = config.sample.as_deref().or_else(|| discover::sample_name(&pairs[0].0)) else {
+                anyhow::bail!(
+                    "No sample name in {} for --demux-stats, expected <sample>_S1_L001_R1_001.fastq.gz",
+                    pairs[0].0.display()
+                );
+            };
+            let lanes: Option<Vec<u32>> = pairs.iter().map(|(r1, _)| discover::lane(r1)).collect();
+            Some((sample, demux::expected_reads(stats, sample, lanes.as_deref())?))
+        }
+        None => None,
+    };
 
     // initialize the count structs
-    let barcodes = Barcodes::from_csv(&config.csv)?;
-    let mut counts = Counts::default();
+    let mut barcodes = Barcodes::from_csv(&config.csv, config.duplicate_sequences, structure.bc_length)?;
+    let excluded = match (config.use_features.is_empty(), config.exclude_features.is_empty()) {
+        (false, _) => barcodes.select(&config.use_features, false)?,
+        (_, false) => barcodes.select(&config.exclude_features, true)?,
+        _ => Vec::new(),
+    };
+    // the reads of the excluded features are not matched to a nearby feature
+    let mut ignore = config.ignore.clone();
+    ignore.extend(excluded.iter().map(|r| r.get(4).unwrap().as_bytes().to_vec()));
+    let has_ignore = !ignore.is_empty();
+    if config.strict_cellranger {
+        barcodes.check_cellranger()?;
+    }
+    let mut counts = if config.bulk { Counts::bulk() } else { Counts::default() };
+    counts = counts.with_ignores(ignore.iter().map(Vec::as_slice));
+    let track_umis = config.umi_stats || config.umi;
+    if track_umis {
+        counts = counts.with_umis();
+    }
+    if config.approximate || config.scan_read2 || config.collapse_cells {
+        counts = counts.with_lenient();
+    }
+    if config.corrections.is_some() {
+        counts = counts.with_corrections();
+    }
+    if config.dedup.is_some() {
+        counts = counts.with_dedup();
+    }
+    if config.bleed {
+        counts = counts.with_bleed();
+    }
+
+    let spike_ins = config
+        .spike_ins
+        .as_ref()
+        .map(|p| SpikeIns::from_csv(p, &barcodes))
+        .transpose()?;
+    let expected = config
+        .expected
+        .as_ref()
+        .map(|p| Expected::from_csv(p, &barcodes))
+        .transpose()?;
 
     // optionally read the whitelist
     let ws = config
         .whitelist
+        .as_ref()
         .map(Whitelist::from_path)
         .transpose()?;
 
+    // the cell codes and barcodes in the reads they are expected in
+    // the standard input cannot be read twice
+    let r1_stdin = reader::is_stdin(&pairs[0].0);
+    let roles = if r1_stdin || reader::is_stdin(&pairs[0].1) || structure.single {
+        None
+    } else {
+        let pair = (&pairs[0].0, &pairs[0].1);
+        Some(Roles::run(pair, config.layout(), config.compression, structure, &barcodes, ws.as_ref())?)
+    };
+    let swapped = roles.as_ref().is_some_and(|r| r.swapped(config.swap));
+    let swap = config.swap ^ (swapped && config.auto_swap);
+    if swap {
+        reader = reader.with_swap();
+    }
+
+    // the UMI length depends on the chemistry, an anchor shifts the codes and codes from the
+    // read name shorten read 1
+    let detection = match config.cb_anchor.iter().chain(&config.cb_from_name).chain(&config.umi_from_name).next() {
+        Some(_) => None,
+        None if r1_stdin || swap || config.structure.is_some() || config.cc_length.is_some() => None,
+        None => Chemistry::detect(
+            &pairs[0].0,
+            config.layout(),
+            config.compression,
+            ws.as_ref(),
+            config.chemistry.and_then(Preset::chemistry),
+        )?,
+    };
+    if let Some(d) = &detection {
+        reader = reader.with_umi_length(d.chemistry.umi_length());
+    }
+
+    // the orientation and position of the barcodes in the first read 2 records
+    let screen = if swap || config.bc_anchor.is_some() || reader::is_stdin(&pairs[0].1) {
+        None
+    } else {
+        let bc_start = reader.structure().bc_start;
+        Some(Screen::run(&pairs[0].1, config.layout(), config.compression, &barcodes, bc_start)?)
+    };
+
+    let inputs = checksum::Inputs::from_paths(
+        config.csv.iter().map(PathBuf::as_path).chain(config.whitelist.as_deref()),
+    )?;
+
+    if config.dry_run {
+        return dry_run(reader, &barcodes, ws.as_ref(), config.approximate);
+    }
+
+    // the summary JSON of the reads counted so far, also written as snapshots while counting
+    let uncorrected = ws.is_none() && !config.bulk && !config.collapse_cells;
+    let report_of = |counts: &Counts,
+                     reader: &reader::Reader,
+                     count: usize,
+                     throughput: ThroughputSummary,
+                     hit_rate: &HitRate,
+                     distances: &Distances| {
+        let mut total = reader::LaneStats::default();
+        for lane in reader.lanes() {
+            total.add(lane);
+        }
+        let summary = Summary::new(&barcodes, counts);
+        report::Report {
+            version: clap::crate_version!().to_owned(),
+            hasher: featureseek::hash::HASHER.to_owned(),
+            read_structure: reader.structure_diagram(),
+            reads: count,
+            chemistry: detection.as_ref().map(|d| d.chemistry),
+            uncorrected,
+            swapped: swap,
+            umi_counts: counts.counts_umis(),
+            truncated: false,
+            resumed_after: None,
+            subsample: config.subsample.map(|fraction| report::Subsample {
+                fraction,
+                seed: config.seed,
+                read_pairs: reader.pairs_read(),
+            }),
+            inputs: inputs
+                .iter()
+                .map(|(sum, path)| report::Input {
+                    path: path.clone(),
+                    sha256: sum.clone(),
+                })
+                .collect(),
+            lanes: pairs
+                .iter()
+                .zip(reader.lanes())
+                .map(|((r1, r2), lane)| report::Lane {
+                    r1: r1.clone(),
+                    r2: r2.clone(),
+                    reads: lane.reads,
+                    q30: report::Q30::from_fractions(lane.q30()),
+                })
+                .collect(),
+            q30: report::Q30::from_fractions(total.q30()),
+            counters: counts.counters(),
+            features: summary.features(
+                config.min_reads,
+                config.min_cells,
+                config.reads_per_cell,
+                config.classifier,
+            ),
+            throughput,
+            whitelist_hit_rate: ws.is_some().then(|| hit_rate.rates()),
+            whitelist_distances: ws.is_some().then(|| distances.counts()),
+            gem_wells: counts.gem_wells(config.min_reads),
+            expected_fractions: Vec::new(),
+            complexity: counts.complexity(),
+            duplicate_sequences: barcodes.duplicates.clone(),
+            reference: barcodes
+                .records
+                .iter()
+                .map(report::ReferenceFeature::from_record)
+                .collect(),
+            excluded_features: excluded.iter().map(report::ReferenceFeature::from_record).collect(),
+        }
+    };
+    if let Some(dir) = &config.snapshot_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
     let mut count = 0;
+    let mut evicting = false;
+    let mut throughput = Throughput::new();
+    let mut hit_rate = HitRate::new(HIT_RATE_WINDOW);
+    let mut distances = Distances::new(WHITELIST_DISTANCE_READS);
 
-    let mut cc = [0u8; CCLENGTH];
-    let mut bc = [0u8; BCLENGTH];
+    // continue an interrupted run after the read pairs of its checkpoint
+    let parameters = serde_json::to_value(&config)?;
+    let checked: Vec<_> = inputs
+        .iter()
+        .map(|(sum, path)| report::Input {
+            path: path.clone(),
+            sha256: sum.clone(),
+        })
+        .collect();
+    let mut resumed_after = None;
+    if let (Some(path), true) = (&config.checkpoint, config.resume) {
+        if path.exists() {
+            let resumed = Resumed::read(path, &parameters, &checked)?;
+            reader.resume(resumed.pairs, resumed.lanes)?;
+            count = resumed.count;
+            counts = resumed.counts;
+            hit_rate = resumed.hit_rate;
+            distances = resumed.distances;
+            throughput = Throughput::resumed(count);
+            println!("Resuming after {count} reads from {}", path.display());
+            resumed_after = Some(count);
+        } else {
+            println!("No checkpoint {} yet, counting from the start", path.display());
+        }
+    }
+    let checkpoint_reads = config.checkpoint_every as usize * 1_000_000;
+    let mut next_checkpoint = (count / checkpoint_reads + 1) * checkpoint_reads;
 
-    while let Some(result) = reader.read_code(&mut cc, &mut bc) {
-        result?;
-        count += 1;
+    // the subsamples of the molecules are kept in a byte
+    if config.saturation_fractions.len() > 256 {
+        anyhow::bail!("--saturation-fractions takes at most 256 fractions");
+    }
+    let has_umis = reader.structure().umi_length > 0 || config.umi_from_name.is_some();
+    if let (Some(Dedup::Codes), false) = (config.dedup, has_umis) {
+        anyhow::bail!("--dedup codes needs UMIs to tell duplicates from reads of the same barcode, use --dedup reads");
+    }
+    let mut saturation = config
+        .saturation
+        .as_ref()
+        .map(|_| Saturation::new(&config.saturation_fractions, has_umis));
 
-        //check whitelisted
+    let mut scratch = Scratch::default();
+    let bc_start = reader.structure().bc_start;
+    let mut batch = Vec::with_capacity(READ_BATCH);
+    let mut whitelisted = Vec::with_capacity(READ_BATCH);
+
+    // the status line, and the counts so far on request
+    let status_line = tty && config.status_line;
+    let mut requests = live::Requests::listen(status_line);
+    let mut last_status = Instant::now();
+
+    let max_reads = config.max_reads.map_or(usize::MAX, |max| max as usize);
+    loop {
+        reader.read_batch(&mut batch, READ_BATCH.min(max_reads - count))?;
+        if batch.is_empty() {
+            break;
+        }
         if let Some(l) = &ws {
-            if !l.contains(cc.as_slice()) {
-                counts.not_whitelisted();
+            l.contains_batch(batch.iter().map(|codes| cell_bases(&codes.cc)), &mut whitelisted);
+        }
+
+        for (i, &reader::Codes { extract, cc, bc, umi, pair, low_quality }) in batch.iter().enumerate() {
+            count += 1;
+            if let Some(s) = &mut saturation {
+                s.read(count);
+            }
+            if count % 4096 == 0 {
+                throughput.update(count);
+            }
+
+            match extract {
+                reader::Extract::TooShort => {
+                    counts.too_short();
+                    continue;
+                }
+                reader::Extract::NoAnchor => {
+                    counts.no_anchor();
+                    continue;
+                }
+                reader::Extract::NoBcAnchor => {
+                    counts.no_bc_anchor();
+                    continue;
+                }
+                reader::Extract::NoNameMatch => {
+                    counts.no_name_match();
+                    continue;
+                }
+                reader::Extract::NoUmiNameMatch => {
+                    counts.no_umi_name_match();
+                    continue;
+                }
+                reader::Extract::Trimmed => {
+                    counts.trimmed();
+                    continue;
+                }
+                reader::Extract::Malformed => {
+                    counts.malformed();
+                    continue;
+                }
+                reader::Extract::Padded => counts.padded(),
+                reader::Extract::Complete => {}
+            }
+
+            //check whitelisted
+            if let Some(l) = &ws {
+                let hit = whitelisted[i];
+                hit_rate.record(hit);
+                distances.record(l, cell_bases(&cc), hit);
+                if !hit {
+                    counts.not_whitelisted();
+                    continue;
+                }
+            }
+
+            if counts.is_duplicate(cc, &bc, umi, pair) {
                 continue;
             }
-        }
 
-        if has_ignore && config.ignore.contains(bc.as_slice()) {
-            counts.ignored();
-            continue;
+            if has_ignore && ignore.contains(barcode_bases(&bc)) {
+                counts.ignored(bc);
+                continue;
+            }
+
+            if config.reject_motif.iter().any(|m| m.find(barcode_bases(&bc))) {
+                counts.rejected();
+                continue;
+            }
+
+            let result = if config.full_distance_scan {
+                let (result, neighbors) = barcodes.scan(&bc, config.approximate, &mut scratch);
+                if neighbors > 0 {
+                    counts.exact_neighbors();
+                }
+                result
+            } else {
+                barcodes.find(&bc, config.approximate, &mut scratch)
+            };
+            let assigned = match result {
+                MatchResult::Unique(pos) => {
+                    counts.count_barcode(cc, pos);
+                    counts.count_distance(0);
+                    if let Some(umi) = umi.filter(|_| track_umis) {
+                        counts.count_molecule(cc, pos, umi);
+                    }
+                    Some(pos)
+                }
+                MatchResult::Dist(pos, dist) => {
+                    counts.count_rescued(cc, pos);
+                    counts.count_correction(pos, bc, dist);
+                    counts.count_distance(dist);
+                    if let Some(umi) = umi.filter(|_| track_umis) {
+                        counts.count_molecule(cc, pos, umi);
+                    }
+                    Some(pos)
+                }
+                MatchResult::NoHit => {
+                    // the barcode may be shifted out of its window
+                    let scanned = config
+                        .scan_read2
+                        .then(|| barcodes.scan_read(reader.barcode_read(i), bc_start, &mut scratch))
+                        .flatten();
+                    if let Some((pos, start)) = scanned {
+                        counts.count_scan_rescued(cc, pos, start as isize - bc_start as isize);
+                        if let Some(umi) = umi.filter(|_| track_umis) {
+                            counts.count_molecule(cc, pos, umi);
+                        }
+                    } else {
+                        if config.unknown {
+                            counts.count_unknown(cc, bc, umi, config.max_unknown);
+                        }
+                        counts.nohit(&bc, low_quality, config.approximate);
+                    }
+                    scanned.map(|(pos, _)| pos)
+                }
+                MatchResult::Multiple => {
+                    counts.multiple();
+                    if config.bleed {
+                        counts.count_bleed(barcodes.hits(&bc, &mut scratch));
+                    }
+                    None
+                }
+            };
+            if let (Some(s), Some(pos)) = (&mut saturation, assigned) {
+                s.assign(count, cc, pos, umi);
+            }
+
+            // protect against corrupt cell code reads
+            if let Some(max) = config.max_cells {
+                if counts.n_cells() > max {
+                    if !evicting {
+                        println!(
+                            "WARNING: more than {max} distinct cell codes found after {count} reads, \
+                            the library looks corrupt. Evicting the cell codes with the lowest counts."
+                        );
+                        evicting = true;
+                    }
+                    counts.evict_cells(max - max / 10);
+                }
+            }
+
+            //update live stats if interactive tty
+            if fullscreen && count % 500_000 == 0 {
+                let summary = Summary::new(&barcodes, &counts);
+                summary.print_matches(
+                    config.min_reads,
+                    config.min_cells,
+                    config.reads_per_cell,
+                    fullscreen,
+                    );
+            }
+
+            if let Some(dir) = &config.snapshot_dir {
+                if count % (config.snapshot_every as usize * 1_000_000) == 0 {
+                    let report = report_of(&counts, &reader, count, throughput.finish(count), &hit_rate, &distances);
+                    write_snapshot(dir, count, &report)?;
+                }
+            }
         }
 
-        let result = barcodes.find(&bc, config.approximate);
-        match result {
-            MatchResult::Unique(pos) => counts.count_barcode(cc, pos),
-            MatchResult::Dist(pos, _dist) => counts.count_barcode(cc, pos),
-            MatchResult::NoHit => {
-                if config.unknown {
-                    counts.count_unknown(cc, bc);
+        if last_status.elapsed() >= STATUS_INTERVAL {
+            if requests.requested() {
+                if let Some(path) = &config.signal_json {
+                    let report = report_of(&counts, &reader, count, throughput.finish(count), &hit_rate, &distances);
+                    write_replacing(path, &report)?;
+                } else {
+                    if status_line {
+                        live::end_status();
+                    }
+                    println!("Counts after {count} reads:");
+                    let summary = Summary::new(&barcodes, &counts);
+                    summary.print_matches(config.min_reads, config.min_cells, config.reads_per_cell, false);
+                    println!();
                 }
-                counts.nohit();
             }
-            MatchResult::Multiple => counts.multiple(),
+            if status_line {
+                let counted = counts.counted();
+                live::print_status(&format!(
+                    "{count} reads, {counted} counted ({:.1}%), {} cells, {:.0} reads/s. Enter: counts so far",
+                    100.0 * counted as f64 / count as f64,
+                    counts.n_cells(),
+                    throughput.rate(count)
+                ));
+            }
+            last_status = Instant::now();
         }
 
-        //update live stats if interactive tty
-        if tty && count % 500_000 == 0 {
-            let summary = Summary::new(&barcodes, &counts);
-            summary.print_matches(
-                config.min_reads,
-                config.min_cells,
-                config.reads_per_cell,
-                tty,
-                );
+        // at the end of a batch all read pairs read are counted
+        if let (Some(path), true) = (&config.checkpoint, count >= next_checkpoint) {
+            let checkpoint = Checkpoint {
+                format: checkpoint::FORMAT,
+                version: clap::crate_version!(),
+                parameters: &parameters,
+                inputs: &checked,
+                count,
+                pairs: reader.pairs_read(),
+                lanes: reader.lanes(),
+                counts: &counts,
+                hit_rate: &hit_rate,
+                distances: &distances,
+            };
+            checkpoint.write(path)?;
+            next_checkpoint += checkpoint_reads;
         }
     }
 
+    // the input continues after --max-reads
+    let truncated = count == max_reads && !reader.at_end()?;
+
+    // tolerate a small fraction of malformed records
+    for warning in reader.warnings().iter() {
+        println!("{warning}");
+    }
+    if let Some(max) = config.skip_malformed {
+        let malformed = counts.counters().malformed;
+        if malformed as f64 > max * count as f64 {
+            anyhow::bail!(
+                "{malformed} of {count} read pairs are malformed, more than the tolerated fraction {max}"
+            );
+        }
+    }
+
+    // likely sequencing errors of the cell codes
+    let totals = counts.cell_totals();
+    let parents = cellcodes::error_parents(&totals);
+    if config.collapse_cells {
+        counts.collapse_cells(&parents);
+    }
+    if let Some(max) = config.cap_reads_per_cell_feature {
+        counts.cap(max);
+    }
+    if config.umi_directional {
+        counts.correct_umis();
+    }
+    if config.umi {
+        counts.count_umis();
+    }
+
+    if status_line {
+        live::end_status();
+    }
     let summary = Summary::new(&barcodes, &counts);
     summary.print_matches(
         config.min_reads,
         config.min_cells,
         config.reads_per_cell,
-        tty,
+        fullscreen,
     );
+    let throughput = throughput.finish(count);
     println!("Examined {count} reads");
+    if truncated {
+        println!("WARNING: stopped after --max-reads {count} read pairs, the counts are of a truncated input");
+    }
+    if let Some(fraction) = config.subsample {
+        println!(
+            "Subsampled {count} of {} read pairs (--subsample {fraction} --seed {})",
+            reader.pairs_read(),
+            config.seed
+        );
+    }
+    let mut total = reader::LaneStats::default();
+    for lane in reader.lanes() {
+        total.add(lane);
+    }
+    if let Some((cc, bc)) = total.q30() {
+        println!("Q30 bases: cell code {:.1}%, feature barcode {:.1}%", 100.0 * cc, 100.0 * bc);
+    }
+    if pairs.len() > 1 {
+        for (i, ((r1, _), lane)) in pairs.iter().zip(reader.lanes()).enumerate() {
+            print!("Lane {}: {} reads from {}", i + 1, lane.reads, r1.display());
+            match lane.q30() {
+                Some((cc, bc)) => println!(", Q30 cell code {:.1}%, feature barcode {:.1}%", 100.0 * cc, 100.0 * bc),
+                None => println!(),
+            }
+        }
+    }
+    throughput.print();
+    for (sum, path) in inputs.iter() {
+        println!("SHA-256 {sum} {}", path.display());
+    }
+    if !excluded.is_empty() {
+        let ids: Vec<_> = excluded.iter().map(|r| r.get(0).unwrap()).collect();
+        println!("Features excluded from the reference: {}", ids.join(", "));
+    }
+    if let Some(d) = &detection {
+        let mut source = match d.evidence {
+            Evidence::Preset => "--chemistry".to_owned(),
+            Evidence::ReadLength => format!("the read 1 length {}", d.r1_length),
+            Evidence::Whitelist => {
+                format!("the {} code whitelist, read 1 length {}", d.chemistry.whitelist_codes(), d.r1_length)
+            }
+        };
+        if let Some(rate) = d.whitelist_hit_rate {
+            source += &format!(", {:.1}% of the first cell codes whitelisted", 100.0 * rate);
+        }
+        println!("Chemistry: 10x {} from {source}, UMI length {}", d.chemistry, d.chemistry.umi_length());
+        if let Some(p) = &found_whitelist {
+            println!("Whitelist: {} for 10x {}, found at {}", d.chemistry.whitelist(), d.chemistry, p.display());
+        } else if five_prime && d.whitelist_hit_rate.is_none() {
+            println!(
+                "Whitelist: {} for 10x {} not found in the directories of `featureseek config`",
+                d.chemistry.whitelist(),
+                d.chemistry
+            );
+        }
+        let v3_codes = Chemistry::V3.whitelist_codes();
+        if d.chemistry != Chemistry::V3 && ws.as_ref().is_some_and(|w| w.codes() == v3_codes) {
+            println!(
+                "WARNING: the whitelist has the {v3_codes} codes of {}, 10x {} uses {}",
+                Chemistry::V3.whitelist(),
+                d.chemistry,
+                d.chemistry.whitelist()
+            );
+        }
+        if let Some(rate) = d.whitelist_hit_rate.filter(|&r| r < chemistry::MIN_HIT_RATE) {
+            println!(
+                "WARNING: only {:.1}% of the first cell codes are whitelisted, check that the \
+                whitelist matches the chemistry, 10x {} uses {}",
+                100.0 * rate,
+                d.chemistry,
+                d.chemistry.whitelist()
+            );
+        }
+    } else if r1_stdin
+        && !swap
+        && config.cb_anchor.is_none()
+        && config.cb_from_name.is_none()
+        && config.umi_from_name.is_none()
+        && config.structure.is_none()
+        && config.cc_length.is_none()
+        && config.chemistry.and_then(Preset::chemistry).is_none()
+    {
+        println!("Chemistry: not detected for read 1 from the standard input, UMI length {UMILENGTH}");
+    }
+    if ws.is_some() {
+        let counts = distances.counts();
+        let sampled = counts.iter().sum::<usize>().max(1) as f64;
+        let [exact, one, two, more] = counts.map(|n| 100.0 * n as f64 / sampled);
+        println!(
+            "Whitelist distance of the first {} cell codes: 0 {exact:.1}%, 1 {one:.1}%, 2 {two:.1}%, >2 {more:.1}%",
+            counts.iter().sum::<usize>()
+        );
+    }
+    for drop in hit_rate.drops(HIT_RATE_TOLERANCE) {
+        println!(
+            "WARNING: whitelist hit rate dropped from {:.1}% to {:.1}% in whitelist checks {}-{}M, \
+            the input may be corrupt or contain a bad lane",
+            100.0 * drop.before,
+            100.0 * drop.rate,
+            drop.window,
+            drop.window + 1
+        );
+    }
+
+    if let Some(roles) = roles.as_ref().filter(|_| swapped) {
+        if config.auto_swap {
+            println!("Read 1 and read 2 counted swapped, the first records have {}", roles.describe());
+        } else {
+            println!(
+                "WARNING: the first records have {}, read 1 and read 2 may be swapped. --auto-swap counts them swapped",
+                roles.describe()
+            );
+        }
+    }
+    if let Some(diagnosis) = screen.as_ref().and_then(Screen::diagnosis) {
+        println!("WARNING: {diagnosis}");
+    }
+    if let Some((sample, expected)) = demultiplexed {
+        // all read pairs, also those left out of a subsample, and not compared after --max-reads
+        let read = reader.pairs_read();
+        if truncated {
+            println!("Read pairs demultiplexed for {sample}: {expected}, not compared as --max-reads stopped early");
+        } else {
+            println!("Read pairs demultiplexed for {sample}: {expected}");
+            if read.abs_diff(expected) as f64 > DEMUX_TOLERANCE * expected as f64 {
+                println!(
+                    "WARNING: read {read} read pairs but {expected} were demultiplexed for {sample}, \
+                    the FastQ files may be truncated or incomplete"
+                );
+            }
+        }
+    }
+    for duplicate in &barcodes.duplicates {
+        println!(
+            "WARNING: sequence {} on the reference rows of {}, counted as {}",
+            duplicate.sequence,
+            duplicate.ids.join(", "),
+            duplicate.kept
+        );
+    }
+    if uncorrected {
+        println!("WARNING: no whitelist provided, the cell codes are uncorrected");
+    }
+    if uncorrected && !parents.is_empty() {
+        println!(
+            "Likely erroneous cell codes within one mismatch of a {}x more frequent code: {} ({} reads)",
+            cellcodes::ERROR_RATIO,
+            parents.len(),
+            parents.keys().map(|c| totals[c]).sum::<usize>()
+        );
+    }
+
+    if let Some(cells) = &config.cells {
+        write_cells(File::create(cells)?, &totals, &parents)?;
+    }
+
+    // the rescued reads do not compare to UMI counts
+    if config.approximate && !config.umi {
+        summary.print_rescues();
+    }
+    summary.print_lenient(config.min_reads, config.min_cells, config.reads_per_cell);
+    summary.print_gem_wells(config.min_reads);
+
+    if let Some(top) = config.corrections {
+        summary.print_corrections(top);
+    }
+
+    if config.bleed {
+        summary.print_bleed();
+    }
+
+    if config.approximate || config.full_distance_scan {
+        summary.print_distances(config.full_distance_scan);
+    }
+
+    if config.quantiles {
+        summary.print_distributions(config.min_reads, config.classifier);
+    }
+
+    if config.umi_stats {
+        summary.print_umis();
+        summary.print_complexity();
+    }
 
     if config.unknown {
         summary.print_unknown(config.min_reads);
     }
 
-    if let Some(out) = config.out {
+    if let (Some(out), true) = (&config.out, config.bulk) {
         let f = File::create(out)?;
-        summary.write_csv(f, config.min_reads, config.min_cells, config.reads_per_cell)?;
+        summary.write_bulk(f, config.min_reads, config.min_cells, config.reads_per_cell)?;
+        inputs.write_for(out)?;
+    } else if let Some(out) = &config.out {
+        let problems = barcodes.lint();
+        if !problems.is_empty() {
+            println!("WARNING: the reference has problems for Cell Ranger or downstream tools:");
+            for problem in &problems {
+                println!("  {problem}");
+            }
+        }
+        let f = File::create(out)?;
+        let renames =
+            summary.write_csv(f, config.min_reads, config.min_cells, config.reads_per_cell)?;
+        for rename in renames {
+            println!(
+                "Renamed duplicate {} {} to {} in {}",
+                rename.column,
+                rename.from,
+                rename.to,
+                out.display()
+            );
+        }
+        inputs.write_for(out)?;
     }
 
-    Ok(())
+    if let Some(calls) = &config.calls {
+        let f = File::create(calls)?;
+        summary.write_calls(
+            f,
+            config.min_reads,
+            config.min_cells,
+            config.reads_per_cell,
+            config.classifier,
+        )?;
+    }
+
+    if let Some(assignments) = &config.assignments {
+        let f = File::create(assignments)?;
+        let ambient =
+            summary.write_assignments(f, config.min_reads, config.min_cells, config.reads_per_cell)?;
+        println!("Ambient fraction of the reads: {:.1}%", 100.0 * ambient);
+    }
+
+    if let (Some(path), Some(s)) = (&config.saturation, &saturation) {
+        s.write_csv(File::create(path)?, config.min_reads)?;
+    }
+
+    if let Some(long) = &config.long {
+        export::write_long(long, summary.long_counts(config.min_reads))?;
+    }
+
+    let mut report = report_of(&counts, &reader, count, throughput, &hit_rate, &distances);
+    report.truncated = truncated;
+    report.resumed_after = resumed_after;
+
+    if let Some(spike_ins) = &spike_ins {
+        match spike_ins.table(&report.features) {
+            Some(table) => println!("\nSpike-in quantification:\n{}", table.display()?),
+            None => println!("\nNo spike-in reads found, molecules not estimated"),
+        }
+    }
+
+    if let Some(expected) = &expected {
+        report.expected_fractions = expected.compare(&report.features, config.expected_tolerance);
+        println!("\nObserved versus expected fractions:\n{}", fractions_table(&report.expected_fractions).display()?);
+        let outside = report.expected_fractions.iter().filter(|f| !f.within_tolerance).count();
+        println!(
+            "Features outside the {}x tolerance: {outside} of {}",
+            config.expected_tolerance,
+            report.expected_fractions.len()
+        );
+    }
+
+    if let Some(json) = &config.json {
+        report.write(File::create(json)?)?;
+    }
+
+    if let Some(sqlite) = &config.sqlite {
+        let parameters = serde_json::to_value(&config)?;
+        database::write_sqlite(sqlite, &parameters, &report, &summary.long_counts(config.min_reads))?;
+    }
+
+    if let Some(path) = &config.manifest {
+        let mut files: Vec<_> = config.csv.iter().chain(&config.whitelist).collect();
+        for (r1, r2) in &pairs {
+            files.push(r1);
+            if r2 != r1 {
+                files.push(r2);
+            }
+        }
+        files.extend(config.spike_ins.iter().chain(&config.expected).chain(&config.demux_stats));
+        let (stdin, files): (Vec<&PathBuf>, _) = files.into_iter().partition(|p| reader::is_stdin(p));
+        let inputs = checksum::Inputs::from_paths(files.into_iter().map(PathBuf::as_path))?;
+
+        let manifest = Manifest {
+            version: report.version.clone(),
+            hasher: report.hasher.clone(),
+            args: with_whitelist(args, config.whitelist.as_deref()),
+            directory: std::env::current_dir()?,
+            parameters: serde_json::to_value(&config)?,
+            inputs: inputs
+                .iter()
+                .map(|(sum, path)| report::Input {
+                    path: path.clone(),
+                    sha256: sum.clone(),
+                })
+                .collect(),
+            stdin: !stdin.is_empty(),
+            chemistry: report.chemistry,
+            read_structure: report.read_structure.clone(),
+            swapped: swap,
+            host: Host::current(),
+        };
+        manifest.write(File::create(path)?)?;
+    }
+
+    // the run completed, a later run starts over
+    if let Some(path) = config.checkpoint.as_ref().filter(|p| p.exists()) {
+        std::fs::remove_file(path)?;
+    }
+
+    if count == 0 {
+        println!("No reads found in the input");
+        return Ok(EXIT_NO_READS);
+    }
+    if summary.n_passing(config.min_reads, config.min_cells, config.reads_per_cell) == 0 {
+        println!("No features passed the filters");
+        return Ok(EXIT_NO_FEATURES);
+    }
+
+    Ok(0)
 }
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::checksum;
+use crate::chemistry::Chemistry;
+use crate::report::Input;
+
+/// Everything needed to reproduce a run: the arguments, the directory they were given in and
+/// the checksums of the input files
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    /// Hasher of the hash maps, chosen at build time
+    pub hasher: String,
+    /// The arguments of the run, without the program name
+    pub args: Vec<String>,
+    /// Working directory the relative paths of the arguments are resolved from
+    pub directory: PathBuf,
+    /// All options of the run, including the defaults
+    pub parameters: serde_json::Value,
+    /// Reference, whitelist, FastQ and other input files
+    pub inputs: Vec<Input>,
+    /// Reads were taken from the standard input, which cannot be replayed
+    #[serde(default)]
+    pub stdin: bool,
+    /// The detected 10x chemistry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chemistry: Option<Chemistry>,
+    /// Diagram of the positions of the codes in the reads
+    pub read_structure: String,
+    /// The cell codes were read from read 2 and the feature barcodes from read 1
+    pub swapped: bool,
+    pub host: Host,
+}
+
+/// The machine a run was done on
+#[derive(Serialize, Deserialize)]
+pub struct Host {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub cpus: usize,
+}
+
+impl Host {
+    pub fn current() -> Self {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+            .map(|h| h.trim().to_owned())
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_default();
+
+        Host {
+            hostname,
+            os: std::env::consts::OS.to_owned(),
+            arch: std::env::consts::ARCH.to_owned(),
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn read<P: AsRef<Path>>(p: P) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(p)?))?)
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> Result<()> {
+        serde_json::to_writer_pretty(&mut w, self)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    /// Check that the run can be replayed by this build with the same inputs, printing the
+    /// status of each input. Returns false when any of the inputs changed or is missing.
+    pub fn verify(&self) -> Result<bool> {
+        let version = env!("CARGO_PKG_VERSION");
+        if self.version != version {
+            anyhow::bail!("the run was done with featureseek {}, this is {version}", self.version);
+        }
+        if self.stdin {
+            anyhow::bail!("the run read the standard input, which cannot be replayed");
+        }
+
+        let mut ok = true;
+        for input in &self.inputs {
+            let status = checksum::status(&input.sha256, &input.path);
+            ok &= status == "OK";
+            println!("  {}: {status}", input.path.display());
+        }
+        Ok(ok)
+    }
+}
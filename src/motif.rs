@@ -0,0 +1,62 @@
+use anyhow::{bail, Result};
+use serde::{Serialize, Serializer};
+
+/// A sequence motif in IUPAC codes, searched in the feature barcode window
+#[derive(Clone, Debug)]
+pub struct Motif {
+    motif: String,
+    /// The bases allowed at each position, a bit per base in ACGT order
+    masks: Vec<u8>,
+}
+
+/// The bases of an IUPAC code, a bit per base in ACGT order
+fn mask(code: u8) -> Option<u8> {
+    let (a, c, g, t) = (1, 2, 4, 8);
+    Some(match code {
+        b'A' => a,
+        b'C' => c,
+        b'G' => g,
+        b'T' | b'U' => t,
+        b'R' => a | g,
+        b'Y' => c | t,
+        b'S' => c | g,
+        b'W' => a | t,
+        b'K' => g | t,
+        b'M' => a | c,
+        b'B' => c | g | t,
+        b'D' => a | g | t,
+        b'H' => a | c | t,
+        b'V' => a | c | g,
+        b'N' => a | c | g | t,
+        _ => return None,
+    })
+}
+
+impl Motif {
+    pub fn parse(s: &str) -> Result<Self> {
+        let masks: Option<Vec<u8>> = s.bytes().map(|b| mask(b.to_ascii_uppercase())).collect();
+        match masks {
+            Some(masks) if !masks.is_empty() => Ok(Motif {
+                motif: s.to_ascii_uppercase(),
+                masks,
+            }),
+            _ => bail!("expected a sequence of IUPAC codes"),
+        }
+    }
+
+    /// Test if the motif occurs in the sequence. An N in the sequence matches no motif base.
+    pub fn find(&self, seq: &[u8]) -> bool {
+        seq.windows(self.masks.len()).any(|window| {
+            window
+                .iter()
+                .zip(&self.masks)
+                .all(|(&b, &m)| b != b'N' && mask(b).is_some_and(|b| b & m != 0))
+        })
+    }
+}
+
+impl Serialize for Motif {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.motif)
+    }
+}
@@ -1,45 +1,974 @@
-use std::io::Read;
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Error as IoError, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use anyhow::Result;
-use fastq::{Parser, Record, RecordRefIter};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::{BCLENGTH, CCLENGTH};
+use crate::bam::BamFastq;
+use crate::fastq::Records;
+use crate::hash::BuildHasher;
+use crate::remote;
+use crate::structure::Structure;
+use crate::warnings::Warnings;
+use crate::{complement, Barcode, CellCode, Umi, MAX_BCLENGTH, MAX_CCLENGTH, UMILENGTH};
+
+/// Result of extracting the codes from a read pair
+#[derive(Clone, Copy)]
+pub enum Extract {
+    Complete,
+    /// One of the reads is too short to contain the code
+    TooShort,
+    /// One of the reads was too short to contain the code, the missing bases are N
+    Padded,
+    /// The cell code anchor was not found in read 1
+    NoAnchor,
+    /// The feature barcode anchor was not found in read 2
+    NoBcAnchor,
+    /// The read 1 name does not contain a cell code
+    NoNameMatch,
+    /// The read 1 name does not contain a UMI
+    NoUmiNameMatch,
+    /// Read 2 was trimmed at an adapter before the end of the feature barcode
+    Trimmed,
+    /// One of the records is not valid FastQ
+    Malformed,
+}
+
+/// The codes extracted from a read pair
+#[derive(Clone, Copy)]
+pub struct Codes {
+    pub extract: Extract,
+    pub cc: CellCode,
+    pub bc: Barcode,
+    /// None when read 1 is too short to contain the UMI
+    pub umi: Option<Umi>,
+    /// Hash of the read 1 and read 2 sequences, when kept
+    pub pair: Option<u64>,
+    /// The feature barcode has a base below Q10
+    pub low_quality: bool,
+}
+
+/// Warning of the read pairs with a malformed record
+pub const MALFORMED: &str = "with a malformed FastQ record";
+/// Warning of the read pairs with differing read names
+pub const MISPAIRED: &str = "with differing read names";
+/// Warning of the read pairs too short to contain the codes
+pub const TOO_SHORT: &str = "too short to contain the codes";
+/// Warning of the read pairs padded with N
+pub const PADDED: &str = "too short to contain the codes, padded with N";
+/// Warning of the read pairs with an N in the feature barcode
+pub const CONTAINS_N: &str = "with N in the feature barcode";
+
+/// Phred+33 quality character of Q30
+const Q30: u8 = b'!' + 30;
+/// Phred+33 quality character of Q10, below which a feature barcode base is of low quality
+const Q10: u8 = b'!' + 10;
+
+/// Fewest bases of an adapter running off the end of read 2 to trim it
+const MIN_ADAPTER_OVERLAP: usize = 10;
+
+/// Path of the standard input
+pub const STDIN: &str = "-";
+
+/// Compression of the FastQ input
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Detected from the first bytes
+    #[default]
+    Auto,
+    /// Uncompressed
+    None,
+    /// Gzip or bgzip
+    Gzip,
+}
+
+/// What to do with a read pair too short to contain the codes
+#[derive(Clone, Copy, Debug, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShortReads {
+    /// Count the pair as too short
+    #[default]
+    Skip,
+    /// Stop at the first pair
+    Error,
+    /// Pad the reads with N to the length of the codes
+    Pad,
+}
+
+/// What to do when the read names of a pair differ
+#[derive(Clone, Copy, Debug, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pairing {
+    /// Stop at the first pair
+    #[default]
+    Error,
+    /// Count the pairs and warn at the end
+    Warn,
+}
+
+/// Arrangement of the read pairs in the input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Read 1 and read 2 in separate FastQ files
+    Paired,
+    /// Read 1 and read 2 records alternating in a single FastQ file
+    Interleaved,
+    /// A 10x BAM file with the cell barcode and UMI in tags
+    Bam,
+    /// A single FastQ file with the cell code and feature barcode in each record
+    Single,
+}
+
+/// Reads and base qualities of a lane
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LaneStats {
+    pub reads: usize,
+    /// Bases in the cell code windows, and those of at least Q30
+    pub cc_bases: usize,
+    pub cc_q30: usize,
+    /// Bases in the feature barcode windows, and those of at least Q30
+    pub bc_bases: usize,
+    pub bc_q30: usize,
+}
 
 pub struct Reader {
-    r1: RecordRefIter<Box<dyn Read>>,
-    r2: RecordRefIter<Box<dyn Read>>,
+    r1: Records,
+    r2: Records,
+    /// The FastQ pairs of the next lanes
+    pending: VecDeque<(PathBuf, PathBuf)>,
+    /// Statistics per lane, up to the current one
+    lanes: Vec<LaneStats>,
+    compression: Compression,
+    /// Count malformed read pairs instead of failing
+    skip_malformed: bool,
+    /// Read 1 and read 2 are the alternating records of a single file
+    interleaved: bool,
+    /// The malformed, mispaired, short and N containing read pairs
+    warnings: Warnings,
+    /// Compare the read names of read 1 and read 2
+    check_pairing: Option<Pairing>,
+    short_reads: ShortReads,
+    /// Constant sequence preceding the cell code
+    cb_anchor: Option<Vec<u8>>,
+    /// Start of the read structure in the current read 1, after the anchor
+    r1_offset: usize,
+    /// Constant sequence preceding the feature barcode
+    bc_anchor: Option<Vec<u8>>,
+    /// Pattern of the cell code in the read 1 header, instead of the sequence
+    cb_from_name: Option<Regex>,
+    /// Pattern of the UMI in the read 1 header, instead of the sequence
+    umi_from_name: Option<Regex>,
+    /// The UMI of the current read 1 header
+    name_umi: Umi,
+    /// Keep the GEM well suffix of the cell code in the read 1 header with the cell code
+    gem_wells: bool,
+    /// Adapters trimmed from read 2, in both orientations
+    adapters: Vec<Vec<u8>>,
+    /// The feature barcode reads of the last batch and their ends, when kept
+    barcode_reads: Option<(Vec<u8>, Vec<usize>)>,
+    /// Hasher of the read pair sequences, when kept
+    pair_hasher: Option<BuildHasher>,
+    /// The fraction of the read pairs kept and the seed of their draws
+    subsample: Option<(f64, u64)>,
+    /// Read pairs read, also those left out of the subsample
+    pairs: usize,
+    structure: Structure,
+    /// The cell code is in read 2 and the feature barcode in read 1
+    swap: bool,
+    /// The feature barcode of the current read pair has a base below Q10
+    low_quality: bool,
+}
+
+/// Test if the path is the standard input
+pub fn is_stdin<P: AsRef<Path>>(p: P) -> bool {
+    p.as_ref() == Path::new(STDIN)
+}
+
+/// Open a possibly compressed file, the standard input for `-` or a remote file for a URL.
+/// Empty files have no compression header to sniff and are read as an empty stream.
+fn open<P: AsRef<Path>>(p: P, compression: Compression) -> Result<Box<dyn Read>> {
+    let p = p.as_ref();
+    let f: Box<dyn Read> = if is_stdin(p) {
+        Box::new(io::stdin().lock())
+    } else if remote::is_remote(p) {
+        remote::open(p)?
+    } else if fs::metadata(p)?.len() == 0 {
+        return Ok(Box::new(io::empty()));
+    } else {
+        Box::new(File::open(p)?)
+    };
+
+    Ok(match compression {
+        Compression::Auto => {
+            niffler::get_reader(f).with_context(|| format!("Reading {}", p.display()))?.0
+        }
+        Compression::None => f,
+        Compression::Gzip => Box::new(MultiGzDecoder::new(f)),
+    })
+}
+
+/// Records of a FastQ pair
+fn open_pair<P: AsRef<Path>>(r1: P, r2: P, compression: Compression) -> Result<(Records, Records)> {
+    Ok((
+        Records::new(open(&r1, compression)?, r1),
+        Records::new(open(&r2, compression)?, r2),
+    ))
+}
+
+/// A stream read by two readers, each getting all data. What one reader reads from the source
+/// is queued for the other.
+struct Tee {
+    source: Box<dyn Read>,
+    queues: [VecDeque<u8>; 2],
+}
+
+/// One of the readers of a tee
+struct Branch {
+    tee: Rc<RefCell<Tee>>,
+    side: usize,
+}
+
+impl Read for Branch {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut tee = self.tee.borrow_mut();
+        if !tee.queues[self.side].is_empty() {
+            return tee.queues[self.side].read(buf);
+        }
+        let n = tee.source.read(buf)?;
+        tee.queues[1 - self.side].extend(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Two readers of the same stream, so an interleaved file is read and decompressed once
+fn tee(source: Box<dyn Read>) -> (Box<dyn Read>, Box<dyn Read>) {
+    let tee = Rc::new(RefCell::new(Tee {
+        source,
+        queues: Default::default(),
+    }));
+    (
+        Box::new(Branch { tee: tee.clone(), side: 0 }),
+        Box::new(Branch { tee, side: 1 }),
+    )
+}
+
+impl LaneStats {
+    pub fn add(&mut self, other: &LaneStats) {
+        self.reads += other.reads;
+        self.cc_bases += other.cc_bases;
+        self.cc_q30 += other.cc_q30;
+        self.bc_bases += other.bc_bases;
+        self.bc_q30 += other.bc_q30;
+    }
+
+    /// Fraction of the cell code and feature barcode bases of at least Q30, None when no
+    /// codes were read
+    pub fn q30(&self) -> Option<(f64, f64)> {
+        (self.cc_bases > 0).then(|| {
+            (
+                self.cc_q30 as f64 / self.cc_bases as f64,
+                self.bc_q30 as f64 / self.bc_bases as f64,
+            )
+        })
+    }
 }
 
 impl Reader {
-    pub fn from_paths<P: AsRef<Path>>(r1: P, r2: P) -> Result<Reader> {
-        let (f1, _format) = niffler::from_path(r1)?;
-        let (f2, _format) = niffler::from_path(r2)?;
+    pub fn from_paths<P: AsRef<Path>>(r1: P, r2: P, compression: Compression) -> Result<Reader> {
+        Reader::from_pairs(&[(r1.as_ref().to_owned(), r2.as_ref().to_owned())], compression)
+    }
 
-        let p1 = Parser::new(f1);
-        let p2 = Parser::new(f2);
+    /// Read the FastQ pairs of multiple lanes one after the other as a single input. All
+    /// files should exist, the later pairs are opened when the previous pair is exhausted.
+    /// One of the files can be the standard input, `-`.
+    pub fn from_pairs(pairs: &[(PathBuf, PathBuf)], compression: Compression) -> Result<Reader> {
+        let Some(((first1, first2), rest)) = pairs.split_first() else {
+            bail!("No FastQ files given");
+        };
+        if pairs.iter().flat_map(|(r1, r2)| [r1, r2]).filter(|p| is_stdin(p)).count() > 1 {
+            bail!("Only one FastQ file can be read from the standard input, use --interleaved");
+        }
+        for (r1, r2) in rest {
+            for p in [r1, r2] {
+                if !is_stdin(p) && !remote::is_remote(p) {
+                    fs::metadata(p)?;
+                }
+            }
+        }
+        let (r1, r2) = open_pair(first1, first2, compression)?;
+        Ok(Reader::new(r1, r2, rest.iter().cloned().collect(), compression))
+    }
+
+    /// Read the pairs from a single FastQ file, or the standard input for `-`, with the read 1
+    /// and read 2 records alternating. The read names of a pair should match.
+    pub fn interleaved<P: AsRef<Path>>(p: P, compression: Compression) -> Result<Reader> {
+        Reader::from_stream(open(&p, compression)?, p, compression)
+    }
 
+    /// Read the codes from a single FastQ file, or the standard input for `-`, with the cell
+    /// code and the feature barcode in each record. Both reads of a pair are the same record.
+    pub fn single<P: AsRef<Path>>(p: P, compression: Compression) -> Result<Reader> {
+        let (f1, f2) = tee(open(&p, compression)?);
+        let (r1, r2) = (Records::new(f1, &p), Records::new(f2, &p));
+        Ok(Reader::new(r1, r2, VecDeque::new(), compression))
+    }
+
+    /// Read the pairs from a 10x BAM file, or the standard input for `-`, taking the corrected
+    /// cell code from the `CB` tag and the feature barcode from the record sequence.
+    pub fn from_bam<P: AsRef<Path>>(p: P, compression: Compression) -> Result<Reader> {
+        let bam = BamFastq::new(open(&p, compression)?)
+            .with_context(|| format!("Reading BAM file {}", p.as_ref().display()))?;
+        Reader::from_stream(Box::new(bam), p, compression)
+    }
+
+    /// Read the pairs from an interleaved FastQ stream
+    fn from_stream<P: AsRef<Path>>(f: Box<dyn Read>, p: P, compression: Compression) -> Result<Reader> {
+        let (f1, f2) = tee(f);
+        let r1 = Records::new(f1, &p).interleaved(false);
+        let r2 = Records::new(f2, &p).interleaved(true);
         Ok(Reader {
-            r1: p1.ref_iter(),
-            r2: p2.ref_iter(),
+            interleaved: true,
+            ..Reader::new(r1, r2, VecDeque::new(), compression)
         })
     }
 
-    pub fn read_code(&mut self, cc: &mut [u8], bc: &mut [u8]) -> Option<Result<()>> {
-        if let Err(e) = self.r1.advance() {
-            return Some(Err(e.into()));
+    fn new(
+        r1: Records,
+        r2: Records,
+        pending: VecDeque<(PathBuf, PathBuf)>,
+        compression: Compression,
+    ) -> Reader {
+        Reader {
+            r1,
+            r2,
+            pending,
+            lanes: vec![LaneStats::default()],
+            compression,
+            skip_malformed: false,
+            interleaved: false,
+            warnings: Warnings::default(),
+            check_pairing: None,
+            short_reads: ShortReads::Skip,
+            cb_anchor: None,
+            r1_offset: 0,
+            bc_anchor: None,
+            cb_from_name: None,
+            umi_from_name: None,
+            gem_wells: false,
+            name_umi: [0; UMILENGTH],
+            adapters: Vec::new(),
+            barcode_reads: None,
+            pair_hasher: None,
+            subsample: None,
+            pairs: 0,
+            structure: Structure::default(),
+            swap: false,
+            low_quality: false,
+        }
+    }
+
+    /// Length of the UMI following the cell code, at most [`UMILENGTH`]
+    pub fn with_umi_length(self, umi_length: usize) -> Self {
+        Reader {
+            structure: self.structure.with_umi_length(umi_length),
+            ..self
+        }
+    }
+
+    /// Return malformed records as [`Extract::Malformed`] instead of failing on the first
+    pub fn with_skip_malformed(self) -> Self {
+        Reader {
+            skip_malformed: true,
+            ..self
+        }
+    }
+
+    /// Compare the read names of each pair, failing or counting the pairs that differ. The
+    /// names of an interleaved file are always compared.
+    pub fn with_check_pairing(self, pairing: Pairing) -> Self {
+        Reader {
+            check_pairing: Some(pairing),
+            ..self
+        }
+    }
+
+    /// The positions of the codes in the reads, by default those of 10x 3' v3 with a
+    /// TotalSeq-B barcode
+    pub fn with_structure(self, structure: Structure) -> Self {
+        Reader { structure, ..self }
+    }
+
+    /// What to do with the read pairs too short to contain the codes, by default counted as
+    /// too short
+    pub fn with_short_reads(self, short_reads: ShortReads) -> Self {
+        Reader { short_reads, ..self }
+    }
+
+    /// Take the cell code and UMI from read 2 and the feature barcode from read 1
+    pub fn with_swap(self) -> Self {
+        Reader { swap: true, ..self }
+    }
+
+    /// Locate the cell code after the first occurrence of the anchor in read 1, allowing one
+    /// mismatch, instead of at the start of the read.
+    pub fn with_cb_anchor(self, anchor: Vec<u8>) -> Self {
+        Reader {
+            cb_anchor: Some(anchor),
+            ..self
+        }
+    }
+
+    /// Locate the feature barcode after the first occurrence of the anchor in read 2, allowing
+    /// one mismatch, instead of at a fixed position.
+    pub fn with_bc_anchor(self, anchor: Vec<u8>) -> Self {
+        Reader {
+            bc_anchor: Some(anchor),
+            ..self
+        }
+    }
+
+    /// Take the cell code from the read 1 header, the first group of the pattern or the whole
+    /// match, instead of the sequence. Read 1 is then only used for the UMI, with the cell code
+    /// removed from the positions of the structure.
+    pub fn with_cb_from_name(self, pattern: Regex) -> Self {
+        Reader {
+            cb_from_name: Some(pattern),
+            ..self
+        }
+    }
+
+    /// Take the UMI from the read 1 header, the first group of the pattern or the whole match of
+    /// at most [`UMILENGTH`] bases, instead of the sequence.
+    pub fn with_umi_from_name(self, pattern: Regex) -> Self {
+        Reader {
+            umi_from_name: Some(pattern),
+            ..self
+        }
+    }
+
+    /// Keep the GEM well of the cell code in the last byte of its padding, see
+    /// [`gem_well`](crate::gem_well), for the aggregated libraries of several GEM wells. The
+    /// well is the -N suffix following the cell code taken from the read name, or else
+    /// following the CB:Z: tag in the read 1 header. Cell codes without a suffix have well 0.
+    pub fn with_gem_wells(self) -> Self {
+        Reader {
+            gem_wells: true,
+            ..self
+        }
+    }
+
+    /// Trim read 2 at the first of these adapters, or their reverse complement, allowing one
+    /// mismatch. Read pairs trimmed before the end of the feature barcode are
+    /// [`Extract::Trimmed`].
+    pub fn with_adapters(self, adapters: &[Vec<u8>]) -> Self {
+        let adapters = adapters
+            .iter()
+            .flat_map(|a| [a.clone(), a.iter().rev().map(|&b| complement(b)).collect()])
+            .collect();
+        Reader { adapters, ..self }
+    }
+
+    /// Keep a hash of the sequences of each read pair, to find duplicate read pairs
+    pub fn with_pair_hashes(self) -> Self {
+        Reader {
+            pair_hasher: Some(BuildHasher::default()),
+            ..self
+        }
+    }
+
+    /// Keep a reproducible random fraction of the read pairs, drawn per read pair from the seed.
+    /// The other pairs are skipped before their codes are extracted, they are only in the read
+    /// counts of the lanes.
+    pub fn with_subsample(self, fraction: f64, seed: u64) -> Self {
+        Reader {
+            subsample: Some((fraction, seed)),
+            ..self
+        }
+    }
+
+    /// Keep the feature barcode reads of each batch, to search them beyond the barcode window
+    pub fn with_barcode_reads(self) -> Self {
+        Reader {
+            barcode_reads: Some((Vec::new(), Vec::new())),
+            ..self
+        }
+    }
+
+    /// Advance to the next read pair, continuing with the next lane at the end of a lane. None
+    /// at the end of the last lane.
+    fn next_pair(&mut self) -> Option<Result<()>> {
+        loop {
+            let more1 = match self.r1.advance() {
+                Ok(more) => more,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let more2 = match self.r2.advance() {
+                Ok(more) => more,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if more1 && more2 {
+                self.lanes.last_mut().unwrap().reads += 1;
+                self.pairs += 1;
+                return Some(Ok(()));
+            }
+            if more1 != more2 {
+                return Some(Err(self.unequal(more1)));
+            }
+            // continue with the next lane
+            let (r1, r2) = self.pending.pop_front()?;
+            match open_pair(r1, r2, self.compression) {
+                Ok((r1, r2)) => (self.r1, self.r2) = (r1, r2),
+                Err(e) => return Some(Err(e)),
+            }
+            self.lanes.push(LaneStats::default());
+        }
+    }
+
+    /// Continue after the read pairs read by an interrupted run, with the statistics of their
+    /// lanes. The read pairs are skipped without extracting their codes.
+    pub fn resume(&mut self, pairs: usize, lanes: Vec<LaneStats>) -> Result<()> {
+        while self.pairs < pairs {
+            if self.next_pair().transpose()?.is_none() {
+                bail!("The input ends after {} of the {pairs} read pairs read before", self.pairs);
+            }
+        }
+        if lanes.len() != self.lanes.len() {
+            bail!(
+                "The {pairs} read pairs read before are in {} lanes, not in {}",
+                lanes.len(),
+                self.lanes.len()
+            );
         }
+        self.lanes = lanes;
+        Ok(())
+    }
 
-        if let Err(e) = self.r2.advance() {
-            return Some(Err(e.into()));
+    pub fn read_code(&mut self, cc: &mut [u8], bc: &mut [u8]) -> Option<Result<Extract>> {
+        loop {
+            if let Err(e) = self.next_pair()? {
+                return Some(Err(e));
+            }
+            match self.subsample {
+                Some((fraction, seed)) if crate::uniform(seed, self.pairs as u64) >= fraction => continue,
+                _ => break,
+            }
         }
+        let lane = self.lanes.last_mut().unwrap();
 
-        let read1 = self.r1.get()?;
-        let read2 = self.r2.get()?;
+        let mut malformed = self.r1.malformed().or_else(|| self.r2.malformed());
+        if malformed.is_none() && self.interleaved && self.r1.name() != self.r2.name() {
+            malformed = Some(self.r2.describe("read name differs from the previous read 1 record"));
+        }
+        if let Some(description) = malformed {
+            if !self.skip_malformed {
+                let e = IoError::new(ErrorKind::InvalidData, format!("Malformed FastQ {description}"));
+                return Some(Err(e.into()));
+            }
+            self.warnings.add(MALFORMED, || description);
+            return Some(Ok(Extract::Malformed));
+        }
+
+        if let Some(pairing) = self.check_pairing {
+            if self.r1.name() != self.r2.name() {
+                let description = self.r2.describe(&format!(
+                    "read name {} differs from the read 1 name {}",
+                    String::from_utf8_lossy(self.r2.name()),
+                    String::from_utf8_lossy(self.r1.name())
+                ));
+                if let Pairing::Error = pairing {
+                    let e = IoError::new(ErrorKind::InvalidData, format!("Mispaired FastQ {description}"));
+                    return Some(Err(e.into()));
+                }
+                self.warnings.add(MISPAIRED, || description);
+            }
+        }
+
+        // the records of the cell code and of the feature barcode
+        let (cells, barcodes) = if self.swap { (&self.r2, &self.r1) } else { (&self.r1, &self.r2) };
+        let (read1, read2) = (cells.seq(), barcodes.seq());
+
+        let (mut r1_length, r2_length) = self.structure.min_lengths();
+        if self.cb_from_name.is_some() {
+            r1_length = 0;
+        }
+        let short = read1.len() < r1_length || read2.len() < r2_length;
+        let describe_short = || {
+            let (need1, need2) = if self.swap { (r2_length, r1_length) } else { (r1_length, r2_length) };
+            self.r2.describe(&format!(
+                "read 1 of {} and read 2 of {} bases, the codes need {need1} and {need2} bases",
+                self.r1.seq().len(),
+                self.r2.seq().len()
+            ))
+        };
+        match self.short_reads {
+            _ if !short => {}
+            ShortReads::Skip => {
+                self.warnings.add(TOO_SHORT, describe_short);
+                return Some(Ok(Extract::TooShort));
+            }
+            ShortReads::Error => {
+                let e = IoError::new(ErrorKind::InvalidData, format!("Short reads in {}", describe_short()));
+                return Some(Err(e.into()));
+            }
+            ShortReads::Pad => self.warnings.add(PADDED, describe_short),
+        }
+
+        self.r1_offset = 0;
+        if let Some(anchor) = &self.cb_anchor {
+            let end = (read1.len() + 1).saturating_sub(anchor.len() + r1_length);
+            match (0..end).find(|&i| hamming(&read1[i..][..anchor.len()], anchor) <= 1) {
+                Some(i) => self.r1_offset = i + anchor.len(),
+                None => return Some(Ok(Extract::NoAnchor)),
+            }
+        }
+        let mut r2_offset = 0;
+        if let Some(anchor) = &self.bc_anchor {
+            let end = (read2.len() + 1).saturating_sub(anchor.len() + r2_length);
+            match (0..end).find(|&i| hamming(&read2[i..][..anchor.len()], anchor) <= 1) {
+                Some(i) => r2_offset = i + anchor.len(),
+                None => return Some(Ok(Extract::NoBcAnchor)),
+            }
+        }
 
-        cc.copy_from_slice(&read1.seq()[0..CCLENGTH]);
-        bc.copy_from_slice(&read2.seq()[10..][..BCLENGTH]);
+        let cc_start = self.r1_offset + self.structure.cc_start;
+        let bc_start = r2_offset + self.structure.bc_start;
+        let bc_end = bc_start + self.structure.bc_length;
+        if self.adapters.iter().any(|adapter| find_adapter(read2, adapter, bc_end).is_some()) {
+            return Some(Ok(Extract::Trimmed));
+        }
+        let cc_length = self.structure.cc_length;
+        cc[cc_length..].fill(0);
+        // the header following the cell code, for its GEM well suffix
+        let mut suffix = None;
+        if let Some(pattern) = &self.cb_from_name {
+            let header = cells.header();
+            let code = pattern.captures(header).and_then(|c| c.get(1).or_else(|| c.get(0)));
+            let Some(code) = code else {
+                return Some(Ok(Extract::NoNameMatch));
+            };
+            // the match may include the suffix
+            let bases = match code.as_bytes().iter().position(|&b| b == b'-') {
+                Some(n) if self.gem_wells => n,
+                _ => code.len(),
+            };
+            if bases != cc_length {
+                return Some(Ok(Extract::NoNameMatch));
+            }
+            cc[..cc_length].copy_from_slice(&code.as_bytes()[..cc_length]);
+            suffix = Some(&header[code.start() + cc_length..]);
+        } else {
+            let cc_bases = pad(&mut cc[..cc_length], read1.get(cc_start..).unwrap_or_default());
+            lane.cc_bases += cc_length;
+            lane.cc_q30 += q30(&cells.qual().get(cc_start..).unwrap_or_default()[..cc_bases]);
+        }
+        if self.gem_wells {
+            let header = cells.header();
+            let suffix = suffix.or_else(|| {
+                let tag = header.windows(5).position(|w| w == b"CB:Z:")? + 5;
+                let bases = header[tag..].iter().take_while(|b| b.is_ascii_alphabetic()).count();
+                Some(&header[tag + bases..])
+            });
+            cc[MAX_CCLENGTH] = suffix.map_or(0, parse_gem_well);
+        }
+        if let Some(pattern) = &self.umi_from_name {
+            let umi = pattern.captures(cells.header()).and_then(|c| c.get(1).or_else(|| c.get(0)));
+            match umi {
+                Some(umi) if (1..=UMILENGTH).contains(&umi.len()) => {
+                    self.name_umi.fill(0);
+                    self.name_umi[..umi.len()].copy_from_slice(umi.as_bytes());
+                }
+                _ => return Some(Ok(Extract::NoUmiNameMatch)),
+            }
+        }
+        let bc_length = self.structure.bc_length;
+        bc[bc_length..].fill(0);
+        let bc_bases = pad(&mut bc[..bc_length], read2.get(bc_start..).unwrap_or_default());
+        if bc[..bc_bases].contains(&b'N') {
+            let bc = String::from_utf8_lossy(&bc[..bc_length]);
+            self.warnings.add(CONTAINS_N, || barcodes.describe(&format!("feature barcode {bc}")));
+        }
+
+        let bc_qual = &barcodes.qual().get(bc_start..).unwrap_or_default()[..bc_bases];
+        lane.bc_bases += bc_length;
+        lane.bc_q30 += q30(bc_qual);
+        self.low_quality = bc_qual.iter().any(|&q| q < Q10);
+
+        Some(Ok(if short { Extract::Padded } else { Extract::Complete }))
+    }
+
+    /// The error when one file of a pair ends before the other, with the record counts of both
+    /// files after reading the rest of the longer file
+    fn unequal(&mut self, r1_longer: bool) -> anyhow::Error {
+        let shorter = self.lanes.last().unwrap().reads;
+        let longer = if r1_longer { &mut self.r1 } else { &mut self.r2 };
+        let mut n = shorter + 1;
+        loop {
+            match longer.advance() {
+                Ok(true) => n += 1,
+                Ok(false) => break,
+                Err(e) => return e.into(),
+            }
+        }
+
+        let description = if self.interleaved {
+            format!(
+                "Interleaved file {} ends with a read 1 record without read 2, after {shorter} read pairs",
+                self.r1.path().display()
+            )
+        } else {
+            let (n1, n2) = if r1_longer { (n, shorter) } else { (shorter, n) };
+            let (truncated, path) = if r1_longer { ("2", self.r2.path()) } else { ("1", self.r1.path()) };
+            format!(
+                "Read 1 file {} has {n1} records and read 2 file {} has {n2}: the read {truncated} file {} \
+                is truncated or the files are not a pair",
+                self.r1.path().display(),
+                self.r2.path().display(),
+                path.display()
+            )
+        };
+        IoError::new(ErrorKind::InvalidData, description).into()
+    }
+
+    /// Replace the batch with the codes of the next n read pairs, fewer at the end of the files
+    pub fn read_batch(&mut self, batch: &mut Vec<Codes>, n: usize) -> Result<()> {
+        batch.clear();
+        if let Some((reads, ends)) = &mut self.barcode_reads {
+            reads.clear();
+            ends.clear();
+        }
+        let mut codes = Codes {
+            extract: Extract::TooShort,
+            cc: [0; MAX_CCLENGTH + 1],
+            bc: [0; MAX_BCLENGTH],
+            umi: None,
+            pair: None,
+            low_quality: false,
+        };
+        while batch.len() < n {
+            let Some(result) = self.read_code(&mut codes.cc, &mut codes.bc) else {
+                break;
+            };
+            codes.extract = result?;
+            codes.low_quality = self.low_quality;
+            let mut umi = [0; UMILENGTH];
+            codes.umi = self.read_umi(&mut umi).then_some(umi);
+            codes.pair = self
+                .pair_hasher
+                .as_ref()
+                .map(|h| std::hash::BuildHasher::hash_one(h, (self.r1.seq(), self.r2.seq())));
+            batch.push(codes);
+            if let Some((reads, ends)) = &mut self.barcode_reads {
+                reads.extend_from_slice(if self.swap { self.r1.seq() } else { self.r2.seq() });
+                ends.push(reads.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Read pairs read, also those left out of the subsample
+    pub fn pairs_read(&self) -> usize {
+        self.pairs
+    }
+
+    /// Whether all read pairs were read, also of the next lanes. The last batch is kept, but
+    /// not the current read pair.
+    pub fn at_end(&mut self) -> Result<bool> {
+        Ok(self.pending.is_empty() && self.r1.at_end()?)
+    }
+
+    /// The feature barcode read of the i-th read pair of the last batch, when kept
+    pub fn barcode_read(&self, i: usize) -> &[u8] {
+        let (reads, ends) = self.barcode_reads.as_ref().expect("barcode reads are kept");
+        &reads[i.checked_sub(1).map_or(0, |j| ends[j])..ends[i]]
+    }
 
-        Some(Ok(()))
+    /// The skipped malformed records, the differing read names counted with
+    /// [`Pairing::Warn`], the short read pairs and the feature barcodes containing N
+    pub fn warnings(&self) -> &Warnings {
+        &self.warnings
     }
+
+    /// Statistics of the lanes read so far
+    pub fn lanes(&self) -> &[LaneStats] {
+        &self.lanes
+    }
+
+    /// Copy the UMI of the current read 1, or read 2 when swapped, or of its header, shorter
+    /// UMIs are padded with zeros. Returns false when the read is too short to contain the UMI.
+    pub fn read_umi(&self, umi: &mut [u8]) -> bool {
+        if self.umi_from_name.is_some() {
+            umi.copy_from_slice(&self.name_umi);
+            return true;
+        }
+        let start = self.umi_start();
+        let length = self.structure.umi_length;
+        let read1 = if self.swap { self.r2.seq() } else { self.r1.seq() };
+        if read1.len() < start + length {
+            return false;
+        }
+        umi.fill(0);
+        umi[..length].copy_from_slice(&read1[start..][..length]);
+        true
+    }
+
+    /// Start of the UMI in the current read 1. A cell code taken from the read name was moved
+    /// out of read 1, so a UMI following it starts that much earlier.
+    fn umi_start(&self) -> usize {
+        let structure = &self.structure;
+        match self.cb_from_name {
+            Some(_) if structure.umi_start >= structure.cc_start + structure.cc_length => {
+                structure.umi_start - structure.cc_length
+            }
+            _ => self.r1_offset + structure.umi_start,
+        }
+    }
+
+    /// The positions of the codes in the reads
+    pub fn structure(&self) -> Structure {
+        self.structure
+    }
+
+    /// ASCII diagram of the positions of the codes in the reads, with 1-based positions
+    pub fn structure_diagram(&self) -> String {
+        let structure = &self.structure;
+        let mut codes = Vec::new();
+        if self.cb_from_name.is_none() {
+            codes.push((structure.cc_start, "C".repeat(structure.cc_length), "cell code"));
+        }
+        if structure.umi_length > 0 && self.umi_from_name.is_none() {
+            codes.push((self.umi_start(), "U".repeat(structure.umi_length), "UMI"));
+        }
+        if structure.single {
+            codes.push((structure.bc_start, "B".repeat(structure.bc_length), "feature barcode"));
+        }
+        codes.sort_unstable();
+
+        let mut r1 = Vec::new();
+        if let Some(anchor) = &self.cb_anchor {
+            r1.push((String::from_utf8_lossy(anchor).into_owned(), "anchor, searched"));
+        }
+        let mut end = 0;
+        for (start, text, name) in codes {
+            r1.push((".".repeat(start - end), ""));
+            end = start + text.len();
+            r1.push((text, name));
+        }
+        let mut r2 = Vec::new();
+        if let Some(anchor) = &self.bc_anchor {
+            r2.push((String::from_utf8_lossy(anchor).into_owned(), "anchor, searched"));
+        }
+        r2.push((".".repeat(structure.bc_start), ""));
+        r2.push(("B".repeat(structure.bc_length), "feature barcode"));
+
+        let mut text = if structure.single {
+            diagram("R1", &r1)
+        } else if self.swap {
+            diagram("R1", &r2) + &diagram("R2", &r1)
+        } else {
+            diagram("R1", &r1) + &diagram("R2", &r2)
+        };
+        if let Some(pattern) = &self.cb_from_name {
+            text += &format!("cell code from the read name: {pattern}\n");
+        }
+        if let Some(pattern) = &self.umi_from_name {
+            text += &format!("UMI from the read name: {pattern}\n");
+        }
+        if self.gem_wells {
+            text += "GEM well from the -N suffix of the cell code in the read name\n";
+        }
+        text
+    }
+}
+
+/// Call f with the sequence of each of the first n records of a FastQ file, skipping the
+/// malformed records. Only the read 1, or with second the read 2, records of an interleaved
+/// file or BAM file.
+pub fn for_each_record<P: AsRef<Path>, F: FnMut(&[u8])>(
+    p: P,
+    n: usize,
+    layout: Layout,
+    second: bool,
+    compression: Compression,
+    mut f: F,
+) -> Result<()> {
+    let f1 = open(&p, compression)?;
+    let mut records = match layout {
+        Layout::Paired | Layout::Single => Records::new(f1, &p),
+        Layout::Interleaved => Records::new(f1, &p).interleaved(second),
+        Layout::Bam => Records::new(Box::new(BamFastq::new(f1)?), &p).interleaved(second),
+    };
+    for _ in 0..n {
+        if !records.advance()? {
+            break;
+        }
+        if records.malformed().is_none() {
+            f(records.seq());
+        }
+    }
+    Ok(())
+}
+
+/// Diagram line of the segments of a read, followed by a line with the positions of each
+/// named segment
+fn diagram(read: &str, segments: &[(String, &str)]) -> String {
+    let mut s = format!("{read}  {}\n", segments.iter().map(|(text, _)| text.as_str()).collect::<String>());
+    let mut offset = 0;
+    for (text, name) in segments {
+        if !name.is_empty() {
+            s += &format!(
+                "    {}^{:<w$}^{}  {name}\n",
+                " ".repeat(offset),
+                offset + 1,
+                offset + text.len(),
+                w = text.len().saturating_sub(2),
+            );
+        }
+        offset += text.len();
+    }
+    s
 }
+
+/// Copy the start of the read into the code, padding a short read with N. The number of bases
+/// copied.
+fn pad(code: &mut [u8], read: &[u8]) -> usize {
+    let n = code.len().min(read.len());
+    code[..n].copy_from_slice(&read[..n]);
+    code[n..].fill(b'N');
+    n
+}
+
+/// Number of bases of at least Q30
+fn q30(qual: &[u8]) -> usize {
+    qual.iter().filter(|&&q| q >= Q30).count()
+}
+
+/// Start of the first adapter in the read before end, allowing one mismatch. An adapter
+/// running off the end of the read needs at least [`MIN_ADAPTER_OVERLAP`] bases.
+fn find_adapter(read: &[u8], adapter: &[u8], end: usize) -> Option<usize> {
+    (0..end.min(read.len())).find(|&start| {
+        let n = adapter.len().min(read.len() - start);
+        n >= MIN_ADAPTER_OVERLAP.min(adapter.len()) && hamming(&read[start..][..n], &adapter[..n]) <= 1
+    })
+}
+
+/// The GEM well of a -N suffix at the start of the text, 0 without a suffix or above 255
+fn parse_gem_well(text: &[u8]) -> u8 {
+    let Some(digits) = text.strip_prefix(b"-") else {
+        return 0;
+    };
+    let n = digits.iter().take_while(|b| b.is_ascii_digit()).count();
+    std::str::from_utf8(&digits[..n]).ok().and_then(|d| d.parse().ok()).unwrap_or(0)
+}
+
+fn hamming(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).filter(|(a, b)| a != b).count()
+}
+
@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use anyhow::{Context, Result};
+
+/// URL schemes streamed by an external command, with the command and its arguments before
+/// the URL
+const SCHEMES: [(&str, &str, &[&str]); 4] = [
+    ("https://", "curl", &["--fail", "--silent", "--show-error", "--location"]),
+    ("http://", "curl", &["--fail", "--silent", "--show-error", "--location"]),
+    ("s3://", "aws", &["s3", "cp", "--no-progress"]),
+    ("gs://", "gsutil", &["cat"]),
+];
+
+/// A remote file streamed from the standard output of a download command
+struct Download {
+    child: Child,
+    stdout: ChildStdout,
+    url: String,
+}
+
+/// Test if the path is an `https://`, `http://`, `s3://` or `gs://` URL
+pub fn is_remote<P: AsRef<Path>>(p: P) -> bool {
+    let p = p.as_ref().to_string_lossy();
+    SCHEMES.iter().any(|(scheme, _, _)| p.starts_with(scheme))
+}
+
+/// Open a local file, or stream a remote file with `curl`, `aws` or `gsutil`
+pub fn open<P: AsRef<Path>>(p: P) -> Result<Box<dyn Read>> {
+    let url = p.as_ref().to_string_lossy();
+    let Some((_, program, args)) = SCHEMES.iter().find(|(scheme, _, _)| url.starts_with(scheme)) else {
+        return Ok(Box::new(File::open(p)?));
+    };
+
+    let mut command = Command::new(program);
+    command.args(*args).arg(url.as_ref());
+    if *program == "aws" {
+        // copy to the standard output
+        command.arg("-");
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Reading {url} needs the {program} command"))?;
+    let stdout = child.stdout.take().unwrap();
+
+    Ok(Box::new(Download {
+        child,
+        stdout,
+        url: url.into_owned(),
+    }))
+}
+
+impl Read for Download {
+    /// Read the download, failing at the end when the download command failed
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("Download of {} failed: {status}", self.url)));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Download {
+    /// Stop a download that was not read to the end
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
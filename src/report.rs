@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::barcodes::Duplicate;
+use crate::chemistry::Chemistry;
+use crate::classify::Distribution;
+use crate::complexity::Complexity;
+use crate::throughput::ThroughputSummary;
+
+/// Machine readable summary of a run
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    pub version: String,
+    /// Hasher of the hash maps, chosen at build time
+    #[serde(default)]
+    pub hasher: String,
+    /// Diagram of the positions of the codes in the reads
+    #[serde(default)]
+    pub read_structure: String,
+    pub reads: usize,
+    /// The detected 10x chemistry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chemistry: Option<Chemistry>,
+    /// The cell codes were not corrected against a whitelist
+    #[serde(default)]
+    pub uncorrected: bool,
+    /// The cell codes were read from read 2 and the feature barcodes from read 1
+    #[serde(default)]
+    pub swapped: bool,
+    /// The feature counts are distinct UMIs instead of reads
+    #[serde(default)]
+    pub umi_counts: bool,
+    /// Counting stopped at --max-reads before the end of the input
+    #[serde(default)]
+    pub truncated: bool,
+    /// Read pairs counted by the interrupted run continued with --resume
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resumed_after: Option<usize>,
+    /// The random fraction of the read pairs counted with --subsample
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsample: Option<Subsample>,
+    pub inputs: Vec<Input>,
+    /// The FastQ pairs counted, one per lane
+    #[serde(default)]
+    pub lanes: Vec<Lane>,
+    /// Base quality of the cell codes and feature barcodes over all lanes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q30: Option<Q30>,
+    pub counters: Counters,
+    pub features: Vec<Feature>,
+    pub throughput: ThroughputSummary,
+    /// Whitelist hit rate per million whitelist checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist_hit_rate: Option<Vec<f64>>,
+    /// Cell codes of the first reads at Hamming distance 0, 1, 2 and more than 2 of the
+    /// nearest whitelisted code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist_distances: Option<[usize; 4]>,
+    /// Cells and reads per GEM well of an aggregated library, counted with --gem-wells
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gem_wells: Vec<GemWell>,
+    /// Observed versus expected fractions of the features given with --expected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expected_fractions: Vec<ExpectedFraction>,
+    /// Estimated library complexity, when UMIs were tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub complexity: Option<Complexity>,
+    /// Sequences on more than one reference row and the id they were counted under
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duplicate_sequences: Vec<Duplicate>,
+    /// The reference rows counted, to compare the references of combined runs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reference: Vec<ReferenceFeature>,
+    /// The reference rows left out with --use-features or --exclude-features
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_features: Vec<ReferenceFeature>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Input {
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Lane {
+    pub r1: PathBuf,
+    pub r2: PathBuf,
+    pub reads: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q30: Option<Q30>,
+}
+
+/// The observed fraction of a feature in the listed features versus its expected fraction,
+/// with the fold deviation
+#[derive(Serialize, Deserialize)]
+pub struct ExpectedFraction {
+    pub id: String,
+    pub name: String,
+    pub expected: f64,
+    pub observed: f64,
+    pub fold: f64,
+    pub within_tolerance: bool,
+}
+
+/// The cells with a barcode above --min-reads and the reads counted of a GEM well, well 0 for
+/// the cell codes without a suffix
+#[derive(Serialize, Deserialize)]
+pub struct GemWell {
+    pub well: u8,
+    pub cells: usize,
+    pub reads: usize,
+}
+
+/// The fraction and seed of --subsample, and the read pairs read including those left out
+#[derive(Serialize, Deserialize)]
+pub struct Subsample {
+    pub fraction: f64,
+    pub seed: u64,
+    pub read_pairs: usize,
+}
+
+/// Fraction of the bases of at least Q30 in the code windows
+#[derive(Serialize, Deserialize)]
+pub struct Q30 {
+    pub cell_code: f64,
+    pub barcode: f64,
+}
+
+impl Q30 {
+    pub fn from_fractions(fractions: Option<(f64, f64)>) -> Option<Self> {
+        fractions.map(|(cell_code, barcode)| Q30 { cell_code, barcode })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Counters {
+    pub ignored: usize,
+    /// Reads per ignore list barcode
+    #[serde(default)]
+    pub ignored_barcodes: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub rejected: usize,
+    pub no_exact_hit: usize,
+    pub no_approximate_hit: usize,
+    pub multiple: usize,
+    pub contains_n: usize,
+    /// Barcodes without a reference hit with a base below Q10
+    #[serde(default)]
+    pub below_quality: usize,
+    pub too_short: usize,
+    /// Read pairs too short to contain the codes, padded with N
+    #[serde(default)]
+    pub padded: usize,
+    #[serde(default)]
+    pub no_anchor: usize,
+    #[serde(default)]
+    pub no_bc_anchor: usize,
+    #[serde(default)]
+    pub no_name_match: usize,
+    #[serde(default)]
+    pub no_umi_name_match: usize,
+    /// Read pairs with read 2 trimmed at an adapter before the end of the feature barcode
+    #[serde(default)]
+    pub trimmed: usize,
+    #[serde(default)]
+    pub malformed: usize,
+    pub not_whitelisted: usize,
+    pub evicted_cells: usize,
+    pub evicted_reads: usize,
+    #[serde(default)]
+    pub collapsed_cells: usize,
+    #[serde(default)]
+    pub collapsed_reads: usize,
+    /// Reads above the cap of a (cell, barcode) pair, not counted
+    #[serde(default)]
+    pub capped: usize,
+    /// UMIs merged into a more frequent UMI of the same cell and barcode
+    #[serde(default)]
+    pub corrected_umis: usize,
+    /// Read pairs dropped as duplicates of an earlier read pair of the same cell
+    #[serde(default)]
+    pub duplicates: usize,
+    /// Reads not counted as the count of their cell and barcode or molecule reached the 32 bit
+    /// maximum
+    #[serde(default)]
+    pub saturated: usize,
+    /// Reads without a barcode at its position counted after scanning the whole read, per
+    /// shift from the position
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scan_rescued: BTreeMap<isize, usize>,
+}
+
+/// A row of the reference
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReferenceFeature {
+    pub id: String,
+    pub name: String,
+    pub sequence: String,
+}
+
+impl ReferenceFeature {
+    pub fn from_record(record: &csv::StringRecord) -> Self {
+        ReferenceFeature {
+            id: record.get(0).unwrap_or_default().to_owned(),
+            name: record.get(1).unwrap_or_default().to_owned(),
+            sequence: record.get(4).unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Feature {
+    pub id: String,
+    pub name: String,
+    pub barcode: String,
+    pub count: usize,
+    pub cells: usize,
+    pub reads_per_cell: Option<usize>,
+    pub rescued: usize,
+    pub passed: bool,
+    pub distribution: Distribution,
+    /// Distinct (cell, UMI) molecules, when UMIs were tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub umis: Option<usize>,
+    /// Reads per molecule, the UMI duplication rate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reads_per_umi: Option<f64>,
+    /// Cells above the minimum reads only through the reads gained by approximate matching,
+    /// read scanning or collapsing the cell codes, when tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lenient_cells: Option<usize>,
+    /// The feature passes the thresholds without the reads gained by the lenient settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passed_strict: Option<bool>,
+}
+
+impl Report {
+    pub fn from_path<P: AsRef<Path>>(p: P) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(p)?))?)
+    }
+
+    pub fn write<W: Write>(&self, w: W) -> Result<()> {
+        serde_json::to_writer_pretty(w, self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::hash::HashMap;
+use crate::{BarcodeRef, CellCode, CompactRef, Umi};
+
+/// Default read fractions of the saturation curve
+pub const FRACTIONS: [f64; 5] = [0.1, 0.25, 0.5, 0.75, 1.0];
+
+/// Fixed seed of the subsamples, so the curve is reproducible
+const SEED: u64 = 0x5eed_f5ee_c0de_0001;
+
+/// The feature counts of read subsamples of increasing size, taken in a single pass: each read
+/// pair has a fixed uniform draw and belongs to every subsample of a larger fraction. Counts
+/// are kept by the smallest subsample including them.
+pub struct Saturation {
+    fractions: Vec<f64>,
+    /// Read pairs per smallest subsample
+    reads: Vec<usize>,
+    /// Assigned reads per smallest subsample
+    assigned: Vec<usize>,
+    /// Reads per (cell, barcode) and smallest subsample
+    features: HashMap<(CellCode, CompactRef), Vec<u32>>,
+    /// The smallest subsample of each (cell, barcode, UMI) molecule, when UMIs are tracked
+    molecules: Option<HashMap<(CellCode, CompactRef, Umi), u8>>,
+}
+
+/// A point of the saturation curve
+pub struct Point {
+    pub fraction: f64,
+    pub reads: usize,
+    pub assigned: usize,
+    /// Cells with a barcode above the minimum reads
+    pub cells: usize,
+    pub umis: Option<usize>,
+}
+
+impl Saturation {
+    /// Subsamples of these fractions of the read pairs, sorted, tracking the distinct UMIs
+    /// when the reads have UMIs
+    pub fn new(fractions: &[f64], umis: bool) -> Self {
+        let mut fractions = fractions.to_vec();
+        fractions.sort_by(f64::total_cmp);
+        fractions.dedup();
+        Saturation {
+            reads: vec![0; fractions.len()],
+            assigned: vec![0; fractions.len()],
+            fractions,
+            features: HashMap::default(),
+            molecules: umis.then(HashMap::default),
+        }
+    }
+
+    /// The smallest subsample including the i-th read pair, None when none does
+    fn subsample(&self, i: usize) -> Option<usize> {
+        let u = crate::uniform(SEED, i as u64);
+        self.fractions.iter().position(|&f| u < f)
+    }
+
+    /// Count the i-th read pair
+    pub fn read(&mut self, i: usize) {
+        if let Some(k) = self.subsample(i) {
+            self.reads[k] += 1;
+        }
+    }
+
+    /// Count the i-th read pair, assigned to a barcode in a cell
+    pub fn assign(&mut self, i: usize, cellcode: CellCode, pos: BarcodeRef, umi: Option<Umi>) {
+        let Some(k) = self.subsample(i) else {
+            return;
+        };
+        self.assigned[k] += 1;
+        let n = self.fractions.len();
+        self.features.entry((cellcode, pos as CompactRef)).or_insert_with(|| vec![0; n])[k] += 1;
+        if let (Some(molecules), Some(umi)) = (&mut self.molecules, umi) {
+            let first = molecules.entry((cellcode, pos as CompactRef, umi)).or_insert(k as u8);
+            *first = (*first).min(k as u8);
+        }
+    }
+
+    /// The curve, with the cells having a barcode above min_reads in each subsample
+    pub fn curve(&self, min_reads: usize) -> Vec<Point> {
+        let n = self.fractions.len();
+        // the smallest subsample in which each cell passes
+        let mut cells: HashMap<CellCode, usize> = HashMap::default();
+        for ((cellcode, _), counts) in &self.features {
+            let mut total = 0;
+            let passing = counts.iter().position(|&c| {
+                total += c as usize;
+                total > min_reads
+            });
+            if let Some(k) = passing {
+                let first = cells.entry(*cellcode).or_insert(k);
+                *first = (*first).min(k);
+            }
+        }
+        let mut cells_from = vec![0; n];
+        for k in cells.into_values() {
+            cells_from[k] += 1;
+        }
+        let mut umis_from = vec![0; n];
+        if let Some(molecules) = &self.molecules {
+            for &k in molecules.values() {
+                umis_from[k as usize] += 1;
+            }
+        }
+
+        let mut points = Vec::new();
+        let (mut reads, mut assigned, mut cells, mut umis) = (0, 0, 0, 0);
+        for k in 0..n {
+            reads += self.reads[k];
+            assigned += self.assigned[k];
+            cells += cells_from[k];
+            umis += umis_from[k];
+            points.push(Point {
+                fraction: self.fractions[k],
+                reads,
+                assigned,
+                cells,
+                umis: self.molecules.as_ref().map(|_| umis),
+            });
+        }
+        points
+    }
+
+    /// Write the curve as CSV, with the sequencing saturation, the fraction of the assigned
+    /// reads that are duplicates of a UMI, when UMIs are tracked
+    pub fn write_csv<W: Write>(&self, w: W, min_reads: usize) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(w);
+        writer.write_record(["fraction", "reads", "assigned_reads", "cells", "umis", "saturation"])?;
+        for p in self.curve(min_reads) {
+            let saturation = p.umis.filter(|_| p.assigned > 0).map(|u| 1.0 - u as f64 / p.assigned as f64);
+            writer.write_record([
+                p.fraction.to_string(),
+                p.reads.to_string(),
+                p.assigned.to_string(),
+                p.cells.to_string(),
+                p.umis.map_or_else(String::new, |u| u.to_string()),
+                saturation.map_or_else(String::new, |s| format!("{s:.4}")),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
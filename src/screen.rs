@@ -0,0 +1,181 @@
+use std::cmp::Reverse;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::barcodes::Barcodes;
+use crate::hash::HashMap;
+use crate::reader::{self, Compression, Layout};
+use crate::structure::Structure;
+use crate::whitelist::Whitelist;
+use crate::complement;
+
+/// Number of read 2 records screened for the orientation and position of the barcodes
+const SCREEN_READS: usize = 10_000;
+
+/// Report another orientation or position when it holds at least this fraction of the
+/// screened reads
+const SCREEN_MIN_FRACTION: f64 = 0.1;
+
+/// Where a reference barcode was found in read 2
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Placement {
+    /// The barcode is reverse complemented
+    pub reverse: bool,
+    /// 0-based start of the barcode in read 2, in the orientation of the barcode
+    pub offset: usize,
+}
+
+/// The reference barcodes found in the first read 2 records
+pub struct Screen {
+    /// Start of the feature barcode in read 2
+    pub bc_start: usize,
+    pub reads: usize,
+    /// Reads with a reference barcode at the expected position
+    pub expected: usize,
+    /// The most frequent other placement of the reads without a barcode at the expected
+    /// position, with its reads
+    pub other: Option<(Placement, usize)>,
+}
+
+/// The first records of both files with a whitelisted cell code and with a reference barcode
+/// at the positions of the structure, to detect swapped read 1 and read 2 files
+pub struct Roles {
+    /// Records screened of read 1
+    pub reads: usize,
+    /// Records of read 1 and read 2 with a whitelisted cell code, when a whitelist is given
+    pub cell_codes: Option<[usize; 2]>,
+    /// Records of read 1 and read 2 with a reference barcode
+    pub barcodes: [usize; 2],
+}
+
+impl Screen {
+    /// Search the reference barcodes in the first read 2 records: at the expected position
+    /// bc_start, and otherwise at any position of the read and of its reverse complement
+    pub fn run<P: AsRef<Path>>(
+        r2: P,
+        layout: Layout,
+        compression: Compression,
+        barcodes: &Barcodes,
+        bc_start: usize,
+    ) -> Result<Self> {
+        let (mut reads, mut expected) = (0, 0);
+        let mut others: HashMap<Placement, usize> = HashMap::default();
+        let mut reverse = Vec::new();
+        let length = barcodes.length();
+        reader::for_each_record(r2, SCREEN_READS, layout, true, compression, |seq| {
+            reads += 1;
+            if seq.get(bc_start..bc_start + length).is_some_and(|bc| barcodes.exact(bc).is_some()) {
+                expected += 1;
+                return;
+            }
+
+            reverse.clear();
+            reverse.extend(seq.iter().rev().map(|&b| complement(b)));
+            let found = [(false, seq), (true, reverse.as_slice())].into_iter().find_map(|(rc, seq)| {
+                seq.windows(length)
+                    .position(|w| barcodes.exact(w).is_some())
+                    .map(|offset| Placement { reverse: rc, offset })
+            });
+            if let Some(placement) = found {
+                *others.entry(placement).or_default() += 1;
+            }
+        })?;
+
+        // ties to the forward and first placement
+        let other = others.into_iter().max_by_key(|&(p, n)| (n, Reverse((p.reverse, p.offset))));
+        Ok(Screen {
+            bc_start,
+            reads,
+            expected,
+            other,
+        })
+    }
+
+    /// A description of the other orientation or position when it holds more reads than the
+    /// expected position and a sizeable fraction of the screened reads
+    pub fn diagnosis(&self) -> Option<String> {
+        let (placement, n) = self.other?;
+        if n <= self.expected || (n as f64) < SCREEN_MIN_FRACTION * self.reads as f64 {
+            return None;
+        }
+        let percentage = 100.0 * n as f64 / self.reads as f64;
+        let cause = if placement.reverse {
+            format!(
+                "in the reverse complement of the read at position {}, \
+                the panel may be sequenced in the opposite orientation",
+                placement.offset + 1
+            )
+        } else if placement.offset == 0 {
+            format!(
+                "at position 1 instead of {}, as in TotalSeq-A libraries: use --chemistry totalseq-a",
+                self.bc_start + 1
+            )
+        } else {
+            format!(
+                "at position {} instead of {}, the reads may be frame-shifted",
+                placement.offset + 1,
+                self.bc_start + 1
+            )
+        };
+        Some(format!(
+            "{percentage:.1}% of the first {} read 2 records have a reference barcode {cause} \
+            ({:.1}% at the expected position)",
+            self.reads,
+            100.0 * self.expected as f64 / self.reads as f64
+        ))
+    }
+}
+
+impl Roles {
+    /// Search the cell codes and reference barcodes in the first records of both files
+    pub fn run<P: AsRef<Path>>(
+        pair: (P, P),
+        layout: Layout,
+        compression: Compression,
+        structure: Structure,
+        barcodes: &Barcodes,
+        whitelist: Option<&Whitelist>,
+    ) -> Result<Self> {
+        let mut roles = Roles { reads: 0, cell_codes: whitelist.map(|_| [0; 2]), barcodes: [0; 2] };
+        let cc = structure.cc_start..structure.cc_start + structure.cc_length;
+        let bc = structure.bc_start..structure.bc_start + structure.bc_length;
+        for (i, p) in [pair.0, pair.1].into_iter().enumerate() {
+            reader::for_each_record(p, SCREEN_READS, layout, i == 1, compression, |seq| {
+                roles.reads += (i == 0) as usize;
+                if let (Some(w), Some(cell_codes)) = (whitelist, &mut roles.cell_codes) {
+                    cell_codes[i] += seq.get(cc.clone()).is_some_and(|cc| w.contains(cc)) as usize;
+                }
+                if seq.get(bc.clone()).is_some_and(|bc| barcodes.exact(bc).is_some()) {
+                    roles.barcodes[i] += 1;
+                }
+            })?;
+        }
+        Ok(roles)
+    }
+
+    /// Whether more codes are found in the other reads than in the reads expected, read 2 for
+    /// the cell codes when swapped, in a sizeable fraction of the screened reads
+    pub fn swapped(&self, swap: bool) -> bool {
+        let (cells, barcodes) = if swap { (1, 0) } else { (0, 1) };
+        let cell_codes = self.cell_codes.unwrap_or_default();
+        let expected = cell_codes[cells] + self.barcodes[barcodes];
+        let other = cell_codes[barcodes] + self.barcodes[cells];
+        other > expected && other as f64 >= SCREEN_MIN_FRACTION * self.reads as f64
+    }
+
+    /// The percentages of the screened records with a cell code and a barcode in each read
+    pub fn describe(&self) -> String {
+        let percentage = |n: usize| 100.0 * n as f64 / self.reads.max(1) as f64;
+        (0..2)
+            .map(|i| {
+                let barcodes = format!("{:.1}% reference barcodes", percentage(self.barcodes[i]));
+                match self.cell_codes {
+                    Some(c) => format!("read {} {:.1}% whitelisted cell codes and {barcodes}", i + 1, percentage(c[i])),
+                    None => format!("read {} {barcodes}", i + 1),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+
+use anyhow::Result;
+use cli_table::{
+    format::{Border, Justify, Separator},
+    Cell, Table, TableStruct,
+};
+use serde::Deserialize;
+
+use crate::barcodes::Barcodes;
+use crate::report::Feature;
+
+/// A spike-in barcode with its known number of input molecules
+#[derive(Deserialize)]
+pub struct SpikeIn {
+    pub id: String,
+    pub molecules: f64,
+}
+
+pub struct SpikeIns(Vec<SpikeIn>);
+
+impl SpikeIns {
+    /// Read the spike-ins from a csv file with `id` and `molecules` columns. The ids should be
+    /// in the reference.
+    pub fn from_csv<P: AsRef<Path>>(p: P, barcodes: &Barcodes) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_path(p)?;
+        let mut spike_ins = Vec::new();
+        for (i, row) in reader.deserialize().enumerate() {
+            let spike_in: SpikeIn = row?;
+            if !barcodes.records.iter().any(|r| r.get(0) == Some(spike_in.id.as_str())) {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Spike-in {} on row {} not in the reference", spike_in.id, i + 2),
+                )
+                .into());
+            }
+            if spike_in.molecules <= 0.0 {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Spike-in {} on row {} needs a positive number of molecules", spike_in.id, i + 2),
+                )
+                .into());
+            }
+            spike_ins.push(spike_in);
+        }
+        Ok(SpikeIns(spike_ins))
+    }
+
+    /// Reads per input molecule over all spike-ins, None when no spike-in was counted
+    pub fn efficiency(&self, features: &[Feature]) -> Option<f64> {
+        let reads: usize = self.0.iter().map(|s| count(features, &s.id)).sum();
+        let molecules: f64 = self.0.iter().map(|s| s.molecules).sum();
+        (reads > 0).then(|| reads as f64 / molecules)
+    }
+
+    /// Table of the capture efficiency of the spike-ins and the estimated molecules of the
+    /// other features
+    pub fn table(&self, features: &[Feature]) -> Option<TableStruct> {
+        let efficiency = self.efficiency(features)?;
+
+        let mut tabledata = Vec::new();
+        for feature in features {
+            let spike_in = self.0.iter().find(|s| s.id == feature.id);
+            let (input, capture) = match spike_in {
+                Some(s) => (format!("{}", s.molecules), format!("{:.3}", feature.count as f64 / s.molecules)),
+                None => (String::new(), String::new()),
+            };
+            tabledata.push(vec![
+                feature.name.clone().cell(),
+                feature.count.cell().justify(Justify::Right),
+                input.cell().justify(Justify::Right),
+                capture.cell().justify(Justify::Right),
+                format!("{:.0}", feature.count as f64 / efficiency).cell().justify(Justify::Right),
+            ]);
+        }
+
+        Some(
+            tabledata
+                .table()
+                .title(vec![
+                    "name".cell(),
+                    "count".cell(),
+                    "input molecules".cell(),
+                    "reads/molecule".cell(),
+                    "estimated molecules".cell(),
+                ])
+                .border(Border::builder().build())
+                .separator(Separator::builder().row(None).column(None).build()),
+        )
+    }
+}
+
+fn count(features: &[Feature], id: &str) -> usize {
+    features.iter().find(|f| f.id == id).map_or(0, |f| f.count)
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+
+use crate::chemistry::Chemistry;
+use crate::{BCLENGTH, CCLENGTH, MAX_BCLENGTH, MAX_CCLENGTH, UMILENGTH};
+
+/// Positions of the codes in the reads, as in `R1:16C12U,R2:10X15B`: a number of bases
+/// followed by `C` for the cell code, `U` for the UMI, `B` for the feature barcode or `X` for
+/// bases that are skipped. Single-read libraries have all codes in read 1, as in
+/// `R1:16C12U10X15B`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Structure {
+    /// Start and length of the cell code in read 1
+    pub cc_start: usize,
+    pub cc_length: usize,
+    /// Start and length of the UMI in read 1
+    pub umi_start: usize,
+    pub umi_length: usize,
+    /// Start and length of the feature barcode in read 2
+    pub bc_start: usize,
+    pub bc_length: usize,
+    /// The feature barcode is in read 1 with the cell code, there is no read 2
+    pub single: bool,
+}
+
+/// Library presets, setting the positions of the codes. The barcodes are read forward in
+/// read 2.
+#[derive(Clone, Copy, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// 10x 3' with the barcode at the start of read 2: R1:16C12U,R2:15B
+    TotalseqA,
+    /// 10x 3' with the barcode after 10 bases of read 2: R1:16C12U,R2:10X15B
+    TotalseqB,
+    /// 10x 5' with a 10 base UMI and the barcode after 10 bases of read 2: R1:16C10U,R2:10X15B
+    TotalseqC,
+    /// 10x 5' v2 as totalseq-c, with the UMI length fixed instead of detected from the longer
+    /// 5' read 1: R1:16C10U,R2:10X15B
+    #[value(name = "5p")]
+    #[serde(rename = "5p")]
+    FivePrime,
+}
+
+impl Preset {
+    pub fn structure(self) -> Structure {
+        let structure = Structure::default();
+        match self {
+            Preset::TotalseqA => structure.with_bc_start(0),
+            Preset::TotalseqB => structure,
+            Preset::TotalseqC | Preset::FivePrime => structure.with_umi_length(10),
+        }
+    }
+
+    /// The chemistry set by the preset, None when it is detected from the reads
+    pub fn chemistry(self) -> Option<Chemistry> {
+        match self {
+            Preset::FivePrime => Some(Chemistry::FivePrime),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Structure {
+    /// The 10x 3' v3 structure with a TotalSeq-B or -C barcode
+    fn default() -> Self {
+        Structure {
+            cc_start: 0,
+            cc_length: CCLENGTH,
+            umi_start: CCLENGTH,
+            umi_length: UMILENGTH,
+            bc_start: 10,
+            bc_length: BCLENGTH,
+            single: false,
+        }
+    }
+}
+
+impl Structure {
+    /// The structure with a cell code of this length, followed by the UMI
+    pub fn with_cc_length(self, cc_length: usize) -> Self {
+        Structure {
+            cc_length,
+            umi_start: self.cc_start + cc_length,
+            ..self
+        }
+    }
+
+    /// The structure with the feature barcode at this position of read 2
+    pub fn with_bc_start(self, bc_start: usize) -> Self {
+        Structure { bc_start, ..self }
+    }
+
+    /// The structure with a feature barcode of this length
+    pub fn with_bc_length(self, bc_length: usize) -> Self {
+        Structure { bc_length, ..self }
+    }
+
+    /// The structure with a UMI of this length following the cell code
+    pub fn with_umi_length(self, umi_length: usize) -> Self {
+        Structure {
+            umi_start: self.cc_start + self.cc_length,
+            umi_length: umi_length.min(UMILENGTH),
+            ..self
+        }
+    }
+
+    /// Bases of read 1 up to the end of the cell code and read 2 up to the end of the feature
+    /// barcode
+    pub fn min_lengths(&self) -> (usize, usize) {
+        (self.cc_start + self.cc_length, self.bc_start + self.bc_length)
+    }
+}
+
+impl FromStr for Structure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (mut cc, mut umi, mut bc) = (None, None, None);
+        let (mut single, mut r2) = (false, false);
+        for read in s.split(',') {
+            let Some((name, segments)) = read.split_once(':') else {
+                bail!("expected R1:<segments> or R2:<segments>, found {read}");
+            };
+            r2 |= name == "R2";
+            let mut start = 0;
+            let mut rest = segments;
+            while !rest.is_empty() {
+                let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+                let Some(kind) = rest[digits..].chars().next() else {
+                    bail!("segment {rest} of {name} lacks one of C, U, B or X");
+                };
+                let length: usize = rest[..digits]
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("segment {rest} of {name} lacks a length"))?;
+                match (name, kind) {
+                    ("R1", 'C') if !(1..=MAX_CCLENGTH).contains(&length) => {
+                        bail!("the cell code should be 1 to {MAX_CCLENGTH} bases")
+                    }
+                    ("R1", 'C') => cc = Some((start, length)),
+                    ("R1", 'U') if length > UMILENGTH => bail!("the UMI should be at most {UMILENGTH} bases"),
+                    ("R1", 'U') => umi = Some((start, length)),
+                    ("R1" | "R2", 'B') if !(1..=MAX_BCLENGTH).contains(&length) => {
+                        bail!("the feature barcode should be 1 to {MAX_BCLENGTH} bases")
+                    }
+                    ("R1" | "R2", 'B') => {
+                        bc = Some((start, length));
+                        single = name == "R1";
+                    }
+                    ("R1" | "R2", 'X') => {}
+                    ("R1" | "R2", _) => bail!("unexpected {kind} in {name}, expected C, U, B and X in R1, B and X in R2"),
+                    _ => bail!("unknown read {name}, expected R1 or R2"),
+                }
+                start += length;
+                rest = &rest[digits + kind.len_utf8()..];
+            }
+        }
+
+        let (Some((cc_start, cc_length)), Some((bc_start, bc_length))) = (cc, bc) else {
+            bail!("expected a cell code C in R1 and a feature barcode B in R1 or R2");
+        };
+        let (umi_start, umi_length) = umi.unwrap_or((cc_start + cc_length, 0));
+        if single && r2 {
+            bail!("expected no R2 with the feature barcode B in R1");
+        }
+        Ok(Structure {
+            cc_start,
+            cc_length,
+            umi_start,
+            umi_length,
+            bc_start,
+            bc_length,
+            single,
+        })
+    }
+}
+
+impl fmt::Display for Structure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut r1 = vec![(self.cc_start, self.cc_length, 'C')];
+        if self.umi_length > 0 {
+            r1.push((self.umi_start, self.umi_length, 'U'));
+        }
+        if self.single {
+            r1.push((self.bc_start, self.bc_length, 'B'));
+        }
+        r1.sort_unstable();
+
+        write!(f, "R1:")?;
+        let mut end = 0;
+        for (start, length, kind) in r1 {
+            if start > end {
+                write!(f, "{}X", start - end)?;
+            }
+            write!(f, "{length}{kind}")?;
+            end = start + length;
+        }
+        if self.single {
+            Ok(())
+        } else if self.bc_start > 0 {
+            write!(f, ",R2:{}X{}B", self.bc_start, self.bc_length)
+        } else {
+            write!(f, ",R2:{}B", self.bc_length)
+        }
+    }
+}
+
+impl Serialize for Structure {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// Track the number of reads processed per minute
+pub struct Throughput {
+    start: Instant,
+    /// Reads processed before the start, by the run resumed
+    start_reads: usize,
+    bucket_start: Instant,
+    bucket_reads: usize,
+    buckets: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ThroughputSummary {
+    pub wall_seconds: f64,
+    pub cpu_seconds: f64,
+    pub reads_per_second_min: f64,
+    pub reads_per_second_avg: f64,
+    pub reads_per_second_max: f64,
+    pub reads_per_minute: Vec<usize>,
+}
+
+impl Default for Throughput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Throughput {
+    pub fn new() -> Self {
+        let start = Instant::now();
+        Throughput {
+            start,
+            start_reads: 0,
+            bucket_start: start,
+            bucket_reads: 0,
+            buckets: Vec::new(),
+        }
+    }
+
+    /// Track a resumed run, counting from the reads processed before
+    pub fn resumed(reads: usize) -> Self {
+        Throughput {
+            start_reads: reads,
+            bucket_reads: reads,
+            ..Self::new()
+        }
+    }
+
+    /// Update with the total number of reads processed so far
+    pub fn update(&mut self, reads: usize) {
+        while self.bucket_start.elapsed() >= BUCKET {
+            self.buckets.push(reads - self.bucket_reads);
+            self.bucket_reads = reads;
+            self.bucket_start += BUCKET;
+        }
+    }
+
+    /// Average reads per second so far
+    pub fn rate(&self, reads: usize) -> f64 {
+        (reads - self.start_reads) as f64 / self.start.elapsed().as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    pub fn finish(&mut self, reads: usize) -> ThroughputSummary {
+        self.update(reads);
+        let wall_seconds = self.start.elapsed().as_secs_f64();
+        let avg = if wall_seconds > 0.0 {
+            (reads - self.start_reads) as f64 / wall_seconds
+        } else {
+            0.0
+        };
+
+        // only complete minutes count for the min and max
+        let per_second = self.buckets.iter().map(|&r| r as f64 / BUCKET.as_secs_f64());
+        let min = per_second.clone().reduce(f64::min).unwrap_or(avg);
+        let max = per_second.reduce(f64::max).unwrap_or(avg);
+
+        let mut reads_per_minute = self.buckets.clone();
+        reads_per_minute.push(reads - self.bucket_reads);
+
+        ThroughputSummary {
+            wall_seconds,
+            cpu_seconds: cpu_time().as_secs_f64(),
+            reads_per_second_min: min,
+            reads_per_second_avg: avg,
+            reads_per_second_max: max,
+            reads_per_minute,
+        }
+    }
+}
+
+impl ThroughputSummary {
+    pub fn print(&self) {
+        println!(
+            "Wall time: {:.1} s, CPU time: {:.1} s, reads/s min/avg/max: {:.0}/{:.0}/{:.0}",
+            self.wall_seconds,
+            self.cpu_seconds,
+            self.reads_per_second_min,
+            self.reads_per_second_avg,
+            self.reads_per_second_max
+        );
+    }
+}
+
+/// User and system CPU time used by this process
+fn cpu_time() -> Duration {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage only writes the provided struct
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return Duration::ZERO;
+    }
+    // SAFETY: initialized by the successful call above
+    let usage = unsafe { usage.assume_init() };
+
+    let tv = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    tv(usage.ru_utime) + tv(usage.ru_stime)
+}
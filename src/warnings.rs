@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// Number of examples kept of each kind of warning
+pub const WARNING_EXAMPLES: usize = 5;
+
+/// A kind of problem of the read pairs, with the number of read pairs and the first examples
+pub struct Warning {
+    /// What the read pairs have in common
+    pub kind: &'static str,
+    pub count: usize,
+    pub examples: Vec<String>,
+}
+
+/// Warnings aggregated by kind, so a problem of millions of read pairs is reported once with a
+/// count and a few examples
+#[derive(Default)]
+pub struct Warnings(Vec<Warning>);
+
+impl Warnings {
+    /// Count a read pair of this kind. The example is only described for the first
+    /// [`WARNING_EXAMPLES`] read pairs.
+    pub fn add<F: FnOnce() -> String>(&mut self, kind: &'static str, example: F) {
+        let i = match self.0.iter().position(|w| w.kind == kind) {
+            Some(i) => i,
+            None => {
+                self.0.push(Warning {
+                    kind,
+                    count: 0,
+                    examples: Vec::new(),
+                });
+                self.0.len() - 1
+            }
+        };
+        let warning = &mut self.0[i];
+        warning.count += 1;
+        if warning.examples.len() < WARNING_EXAMPLES {
+            warning.examples.push(example());
+        }
+    }
+
+    /// The warnings in the order of their first read pair
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.0.iter()
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WARNING: {} read pairs {}", self.count, self.kind)?;
+        if self.count > self.examples.len() {
+            write!(f, ", the first {}:", self.examples.len())?;
+        } else {
+            write!(f, ":")?;
+        }
+        for example in &self.examples {
+            write!(f, "\n    {example}")?;
+        }
+        Ok(())
+    }
+}
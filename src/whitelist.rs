@@ -1,22 +1,429 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error as IoError};
-use std::path::Path;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Error as IoError, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
 
-use ahash::AHashSet;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
-pub struct Whitelist(AHashSet<Vec<u8>>);
+use crate::remote;
+
+/// Longest cell code that fits the packed representation
+const MAX_PACKED: usize = 31;
+
+/// Empty slot in the table, no packed code is zero
+const EMPTY: u64 = 0;
+
+/// The 2 bit code of each base, 4 for other characters
+const BITS: [u8; 256] = {
+    let mut bits = [4; 256];
+    bits[b'A' as usize] = 0;
+    bits[b'C' as usize] = 1;
+    bits[b'G' as usize] = 2;
+    bits[b'T' as usize] = 3;
+    bits
+};
+
+/// Start of a whitelist index file written by `featureseek index`
+const INDEX_MAGIC: &[u8; 8] = b"FSWLIDX1";
+
+/// Environment variable with the directories searched for a whitelist given by name, separated
+/// as in PATH
+pub const WHITELIST_DIR_VAR: &str = "FEATURESEEK_WHITELIST_DIR";
+
+/// Directories searched for a whitelist given by name after those of the environment variable
+const SYSTEM_DIRS: [&str; 2] = ["/usr/local/share/featureseek/whitelists", "/usr/share/featureseek/whitelists"];
+
+/// Extensions tried after a whitelist name, the index first as it is read faster
+const EXTENSIONS: [&str; 3] = ["", ".idx", ".txt"];
+
+/// Set of whitelisted cell codes, packed in 2 bits per base
+pub struct Whitelist {
+    lookup: Lookup,
+    /// Number of unique cell codes
+    codes: usize,
+}
+
+enum Lookup {
+    /// Open addressing table with linear probing, built from a text whitelist
+    Table { table: Vec<u64>, mask: usize },
+    /// The sorted unique codes in Eytzinger (breadth first) order from position 1, read from
+    /// an index file
+    Eytzinger(Vec<u64>),
+}
+
+/// Whitelist hit rate per window of whitelist checks, the reads left after the
+/// earlier filters
+#[derive(Serialize, Deserialize)]
+pub struct HitRate {
+    window: usize,
+    hits: usize,
+    total: usize,
+    rates: Vec<f64>,
+}
+
+/// Hamming distances of the cell codes of the first reads to their nearest whitelisted code
+#[derive(Serialize, Deserialize)]
+pub struct Distances {
+    /// Cell codes at distance 0, 1, 2 and more than 2
+    counts: [usize; 4],
+    limit: usize,
+}
+
+/// A window with a hit rate significantly lower than the windows before it
+pub struct Drop {
+    pub window: usize,
+    pub before: f64,
+    pub rate: f64,
+}
 
 impl Whitelist {
+    /// Read a whitelist of one cell code per line, or an index written by `featureseek index`,
+    /// recognized by its first bytes
     pub fn from_path<P: AsRef<Path>>(p: P) -> Result<Self> {
-        let f = File::open(p.as_ref())?;
-        let b = BufReader::new(f);
-        let hash = b.split(b'\n').collect::<Result<_, IoError>>()?;
+        let mut reader = remote::open(&p)?;
+        let mut head = Vec::new();
+        reader.by_ref().take(INDEX_MAGIC.len() as u64).read_to_end(&mut head)?;
+        let reader = Cursor::new(head).chain(reader);
+        if reader.get_ref().0.get_ref() == INDEX_MAGIC {
+            return Self::from_index(reader, p.as_ref());
+        }
 
-        Ok(Whitelist(hash))
+        let b = BufReader::new(reader);
+        let mut codes = Vec::new();
+        for (i, line) in b.split(b'\n').enumerate() {
+            // tolerate CRLF line ends and stray whitespace
+            let line = line?;
+            let line = line.trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+            let code = pack(line).ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Whitelist line {} is not a cell code of at most {MAX_PACKED} bases", i + 1),
+                )
+            })?;
+            codes.push(code);
+        }
+
+        // at most half full keeps the probe sequences short
+        let size = (2 * codes.len()).next_power_of_two().max(16);
+        let mask = size - 1;
+        let mut table = vec![EMPTY; size];
+        let mut unique = 0;
+        for code in codes {
+            let mut slot = slot(code, mask);
+            while table[slot] != EMPTY && table[slot] != code {
+                slot = (slot + 1) & mask;
+            }
+            unique += usize::from(table[slot] == EMPTY);
+            table[slot] = code;
+        }
+        Ok(Whitelist {
+            lookup: Lookup::Table { table, mask },
+            codes: unique,
+        })
+    }
+
+    /// Read the codes of an index file, after the magic bytes: their number and the codes in
+    /// Eytzinger order, as little endian 64 bit integers
+    fn from_index<R: Read>(mut reader: R, p: &Path) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let truncated = || IoError::new(ErrorKind::InvalidData, format!("Whitelist index {} is truncated", p.display()));
+        let (n, codes) = bytes[INDEX_MAGIC.len()..].split_first_chunk::<8>().ok_or_else(truncated)?;
+        if codes.len() as u64 != 8 * u64::from_le_bytes(*n) {
+            return Err(truncated().into());
+        }
+        let mut eytzinger = vec![EMPTY];
+        eytzinger.extend(codes.chunks_exact(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())));
+        Ok(Whitelist {
+            codes: eytzinger.len() - 1,
+            lookup: Lookup::Eytzinger(eytzinger),
+        })
+    }
+
+    /// Write the whitelist as an index file, read faster than the text whitelist. The number
+    /// of cell codes written.
+    pub fn write_index<P: AsRef<Path>>(&self, p: P) -> Result<usize> {
+        let mut codes: Vec<u64> = match &self.lookup {
+            Lookup::Table { table, .. } => table.iter().copied().filter(|&c| c != EMPTY).collect(),
+            Lookup::Eytzinger(eytzinger) => eytzinger[1..].to_vec(),
+        };
+        codes.sort_unstable();
+        let mut eytzinger = vec![EMPTY; codes.len() + 1];
+        fill_eytzinger(&mut eytzinger, &mut codes.into_iter(), 1);
+
+        let mut w = BufWriter::new(File::create(p)?);
+        w.write_all(INDEX_MAGIC)?;
+        w.write_all(&(eytzinger.len() as u64 - 1).to_le_bytes())?;
+        for code in &eytzinger[1..] {
+            w.write_all(&code.to_le_bytes())?;
+        }
+        w.flush()?;
+        Ok(eytzinger.len() - 1)
+    }
+
+    /// Number of unique cell codes
+    pub fn codes(&self) -> usize {
+        self.codes
     }
 
     pub fn contains(&self, v: &[u8]) -> bool {
-        self.0.contains(v)
+        pack(v).is_some_and(|code| self.probe(code))
+    }
+
+    /// Hamming distance of the cell code to the nearest whitelisted code, None when more than 2
+    pub fn distance(&self, v: &[u8]) -> Option<usize> {
+        if self.contains(v) {
+            return Some(0);
+        }
+        // substitute each base, and while no code at distance 2 is found also each later base
+        let mut code = v.to_vec();
+        let mut nearest = None;
+        for i in 0..v.len() {
+            for a in b"ACGT".iter().filter(|&&a| a != v[i]) {
+                code[i] = *a;
+                if self.contains(&code) {
+                    return Some(1);
+                }
+                if nearest.is_some() {
+                    continue;
+                }
+                for j in i + 1..v.len() {
+                    for b in b"ACGT".iter().filter(|&&b| b != v[j]) {
+                        code[j] = *b;
+                        if self.contains(&code) {
+                            nearest = Some(2);
+                        }
+                    }
+                    code[j] = v[j];
+                }
+            }
+            code[i] = v[i];
+        }
+        nearest
+    }
+
+    /// Look up a batch of cell codes, replacing hits with the result for each code. The table
+    /// slots of the whole batch are prefetched first, and the Eytzinger searches of the batch
+    /// descend a level together, so the cache misses of the lookups overlap.
+    pub fn contains_batch<'a, I>(&self, codes: I, hits: &mut Vec<bool>)
+    where
+        I: Iterator<Item = &'a [u8]> + Clone,
+    {
+        hits.clear();
+        match &self.lookup {
+            Lookup::Table { table, mask } => {
+                for code in codes.clone().filter_map(pack) {
+                    prefetch(table.as_ptr().wrapping_add(slot(code, *mask)));
+                }
+                hits.extend(codes.map(|v| self.contains(v)));
+            }
+            Lookup::Eytzinger(eytzinger) => {
+                // invalid codes search for the empty code, which is never found
+                let packed: Vec<u64> = codes.map(|v| pack(v).unwrap_or(EMPTY)).collect();
+                let mut nodes = vec![1; packed.len()];
+                // the searches are on the same level, only the last level is partly filled
+                let mut descending = true;
+                while descending {
+                    descending = false;
+                    for (k, &code) in nodes.iter_mut().zip(&packed).filter(|(k, _)| **k < eytzinger.len()) {
+                        *k = 2 * *k + (eytzinger[*k] < code) as usize;
+                        prefetch(eytzinger.as_ptr().wrapping_add(*k));
+                        descending = true;
+                    }
+                }
+                hits.extend(nodes.iter().zip(&packed).map(|(&k, &code)| {
+                    let k = k >> (k.trailing_ones() + 1);
+                    code != EMPTY && k != 0 && eytzinger[k] == code
+                }));
+            }
+        }
+    }
+
+    fn probe(&self, code: u64) -> bool {
+        match &self.lookup {
+            Lookup::Table { table, mask } => {
+                let mut slot = slot(code, *mask);
+                loop {
+                    match table[slot] {
+                        EMPTY => return false,
+                        c if c == code => return true,
+                        _ => slot = (slot + 1) & mask,
+                    }
+                }
+            }
+            Lookup::Eytzinger(eytzinger) => {
+                // descend to the right of the smaller codes, prefetching the cache line of the
+                // 8 nodes three levels down
+                let mut k = 1;
+                while k < eytzinger.len() {
+                    prefetch(eytzinger.as_ptr().wrapping_add(8 * k));
+                    k = 2 * k + (eytzinger[k] < code) as usize;
+                }
+                // back up to the last node taken to the left, the first code not smaller
+                k >>= k.trailing_ones() + 1;
+                k != 0 && eytzinger[k] == code
+            }
+        }
+    }
+}
+
+/// The directories searched for a whitelist given by name, in order, with where each comes
+/// from: the directories of FEATURESEEK_WHITELIST_DIR, featureseek/whitelists in the user data
+/// directory, and the system directories
+pub fn search_dirs() -> Vec<(PathBuf, &'static str)> {
+    let mut dirs = Vec::new();
+    if let Some(var) = std::env::var_os(WHITELIST_DIR_VAR) {
+        let from_var = std::env::split_paths(&var).filter(|d| !d.as_os_str().is_empty());
+        dirs.extend(from_var.map(|d| (d, WHITELIST_DIR_VAR)));
+    }
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    if let Some(data) = data {
+        dirs.push((data.join("featureseek/whitelists"), "user"));
+    }
+    dirs.extend(SYSTEM_DIRS.iter().map(|d| (PathBuf::from(d), "system")));
+    dirs
+}
+
+/// Find a whitelist name in the search directories, as given or with an .idx or .txt
+/// extension
+pub fn find(name: &Path) -> Option<PathBuf> {
+    search_dirs().into_iter().find_map(|(dir, _)| {
+        EXTENSIONS.iter().map(|ext| {
+            let mut file = name.as_os_str().to_owned();
+            file.push(ext);
+            dir.join(file)
+        })
+        .find(|p| p.is_file())
+    })
+}
+
+/// The whitelist of --whitelist: an existing file or a URL as given, else a name without a
+/// directory found in the search directories
+pub fn resolve(p: &Path) -> Result<PathBuf> {
+    if p.exists() || remote::is_remote(p) || p.components().count() != 1 {
+        return Ok(p.to_owned());
+    }
+    find(p).ok_or_else(|| {
+        let dirs: Vec<_> = search_dirs().iter().map(|(d, _)| d.display().to_string()).collect();
+        IoError::new(
+            ErrorKind::NotFound,
+            format!("Whitelist {} not found, also not in {}", p.display(), dirs.join(", ")),
+        )
+        .into()
+    })
+}
+
+/// Fibonacci hashing of the packed code to a slot
+fn slot(code: u64, mask: usize) -> usize {
+    (code.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize & mask
+}
+
+/// Fill the subtree of node k of the Eytzinger layout with the next sorted codes, in order
+fn fill_eytzinger(eytzinger: &mut [u64], codes: &mut impl Iterator<Item = u64>, k: usize) {
+    if k < eytzinger.len() {
+        fill_eytzinger(eytzinger, codes, 2 * k);
+        eytzinger[k] = codes.next().unwrap();
+        fill_eytzinger(eytzinger, codes, 2 * k + 1);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn prefetch(p: *const u64) {
+    use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+    // prefetching never faults, also beyond the end of the table
+    unsafe { _mm_prefetch::<_MM_HINT_T0>(p as *const i8) }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn prefetch(_p: *const u64) {}
+
+impl Distances {
+    /// Tally the distances of the first limit cell codes
+    pub fn new(limit: usize) -> Self {
+        Distances { counts: [0; 4], limit }
+    }
+
+    pub fn record(&mut self, whitelist: &Whitelist, code: &[u8], hit: bool) {
+        if self.counts.iter().sum::<usize>() >= self.limit {
+            return;
+        }
+        let distance = if hit { Some(0) } else { whitelist.distance(code) };
+        self.counts[distance.unwrap_or(3)] += 1;
+    }
+
+    /// The cell codes at distance 0, 1, 2 and more than 2
+    pub fn counts(&self) -> [usize; 4] {
+        self.counts
+    }
+}
+
+impl HitRate {
+    pub fn new(window: usize) -> Self {
+        HitRate {
+            window,
+            hits: 0,
+            total: 0,
+            rates: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, hit: bool) {
+        self.hits += hit as usize;
+        self.total += 1;
+        if self.total == self.window {
+            self.rates.push(self.hits as f64 / self.total as f64);
+            self.hits = 0;
+            self.total = 0;
+        }
+    }
+
+    /// The hit rate of all windows, including the last incomplete one
+    pub fn rates(&self) -> Vec<f64> {
+        let mut rates = self.rates.clone();
+        if self.total > 0 {
+            rates.push(self.hits as f64 / self.total as f64);
+        }
+        rates
+    }
+
+    /// The complete windows having a hit rate more than tolerance below the median rate of the
+    /// windows before them
+    pub fn drops(&self, tolerance: f64) -> Vec<Drop> {
+        let mut drops = Vec::new();
+        for window in 1..self.rates.len() {
+            let mut before = self.rates[..window].to_vec();
+            before.sort_by(f64::total_cmp);
+            let median = before[before.len() / 2];
+
+            let rate = self.rates[window];
+            if rate < median - tolerance {
+                drops.push(Drop {
+                    window,
+                    before: median,
+                    rate,
+                });
+            }
+        }
+        drops
+    }
+}
+
+/// Pack a cell code in 2 bits per base after a leading 1 bit, which keeps codes of different
+/// lengths apart. None when the code has other bases than ACGT or is too long.
+fn pack(v: &[u8]) -> Option<u64> {
+    if v.len() > MAX_PACKED {
+        return None;
     }
+    // without branches per base, random bases defeat the branch prediction
+    let (code, invalid) = v.iter().fold((1u64, 0u8), |(code, invalid), &base| {
+        let bits = BITS[base as usize];
+        (code << 2 | (bits & 3) as u64, invalid | bits)
+    });
+    (invalid < 4).then_some(code)
 }
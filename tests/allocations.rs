@@ -0,0 +1,109 @@
+//! The per read work of the counting loop does not allocate once its buffers are warmed up
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+
+use featureseek::barcodes::{Barcodes, MatchResult, Precedence, Scratch};
+use featureseek::reader::{Codes, Compression, Reader};
+use featureseek::BCLENGTH;
+
+/// Counts the allocations of the current thread, as the test harness allocates on its own
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+const BARCODES: [&str; 4] = ["CAGATTTTCATATTA", "TGCAGAAAATCTACT", "GTCAACTCTTTAGCG", "TTCCGCCTCTCTTTG"];
+
+/// Write a reference and pairs of FastQ files of the barcodes, exact, with one mismatch and
+/// unknown in turn
+fn write_inputs(dir: &Path, pairs: usize) {
+    let mut reference = "id,name,read,pattern,sequence,feature_type\n".to_owned();
+    for (i, barcode) in BARCODES.iter().enumerate() {
+        reference += &format!("H{i},Hashtag_{i},R2,5PNNNNNNNNNN(BC),{barcode},Antibody Capture\n");
+    }
+    fs::write(dir.join("ref.csv"), reference).unwrap();
+
+    let (mut r1, mut r2) = (String::new(), String::new());
+    for i in 0..pairs {
+        let mut barcode = BARCODES[i % BARCODES.len()].as_bytes().to_vec();
+        match i % 3 {
+            0 => {}
+            1 => barcode[i % BCLENGTH] = if barcode[i % BCLENGTH] == b'A' { b'C' } else { b'A' },
+            _ => barcode = b"ACGTACGTACGTACG".to_vec(),
+        }
+        let cell: String = (0..16).map(|j| ['A', 'C', 'G', 'T'][(i >> (j % 8)) & 3]).collect();
+        let read1 = format!("{cell}ACGTACGTACGT");
+        let read2 = format!("GTCAGTCAGT{}TTTTTTTTTT", String::from_utf8(barcode).unwrap());
+        r1 += &format!("@r{i}\n{read1}\n+\n{}\n", "F".repeat(read1.len()));
+        r2 += &format!("@r{i}\n{read2}\n+\n{}\n", "F".repeat(read2.len()));
+    }
+    fs::write(dir.join("R1.fq"), r1).unwrap();
+    fs::write(dir.join("R2.fq"), r2).unwrap();
+}
+
+/// The barcodes of the batch found by both the lookup and the scan of the BK-tree
+fn lookup(barcodes: &Barcodes, batch: &[Codes], scratch: &mut Scratch) -> usize {
+    let mut found = 0;
+    for codes in batch {
+        found += usize::from(!matches!(barcodes.find(&codes.bc, true, scratch), MatchResult::NoHit));
+        found += usize::from(!matches!(barcodes.scan(&codes.bc, true, scratch).0, MatchResult::NoHit));
+    }
+    found
+}
+
+#[test]
+fn counting_loop_does_not_allocate() {
+    let dir = std::env::temp_dir().join(format!("featureseek-allocations-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    write_inputs(&dir, 3000);
+
+    let barcodes = Barcodes::from_csv(&[dir.join("ref.csv")], Precedence::First, BCLENGTH).unwrap();
+    let mut reader = Reader::from_paths(dir.join("R1.fq"), dir.join("R2.fq"), Compression::None).unwrap();
+    let mut scratch = Scratch::default();
+    let mut batch = Vec::new();
+
+    // the first batch sizes the batch, the read buffers and the scratch buffers
+    reader.read_batch(&mut batch, 1000).unwrap();
+    lookup(&barcodes, &batch, &mut scratch);
+
+    let before = allocations();
+    let mut found = 0;
+    for _ in 0..2 {
+        reader.read_batch(&mut batch, 1000).unwrap();
+        assert_eq!(batch.len(), 1000);
+        found += lookup(&barcodes, &batch, &mut scratch);
+    }
+    let allocated = allocations() - before;
+    fs::remove_dir_all(&dir).unwrap();
+
+    // the exact and the one mismatch barcodes are found by both searches
+    assert_eq!(found, 2 * (1000..3000).filter(|i| i % 3 != 2).count());
+    assert_eq!(allocated, 0, "{allocated} allocations after warm-up");
+}